@@ -1,18 +1,107 @@
-use crate::error::Error;
+use crate::error::{Error, ValidationError};
+#[cfg(feature = "no-float")]
+use crate::fixed;
+#[cfg(not(feature = "no-float"))]
+use crate::float::{self, Float};
+use crate::float::{hz, Hz, HzDelta};
+use crate::logging::debug;
+use crate::math::{self, MAX_CALIBRATOR_FREQ, MAX_MOD};
+use crate::pll_path::PllPath;
 use crate::registers as regs;
-use crate::STuW81300;
+use crate::rounding::Rounding;
+use crate::supply::{check_supply, configure_supply, max_vco_amplitude};
+use crate::{DeviceVariant, STuW81300};
+use core::fmt;
 use embedded_hal as hal;
+use hal::blocking::delay::DelayUs;
 use hal::blocking::spi::Transfer;
-use hal::digital::v2::OutputPin;
-#[allow(unused_imports)]
-use micromath::F32Ext;
+use hal::digital::v2::{InputPin, OutputPin};
 use regs::RegisterAddr;
 
-const MAX_MOD: u32 = 2097151;
+const MAX_CAL_FREQ: Hz = hz(MAX_CALIBRATOR_FREQ);
+/// Largest N in integer mode, where the DSM is bypassed
+const MAX_N_INT: u32 = 131071;
+
+/// FRAC for a fractional divider part of `half_lsbs` halves of an LSB (rounded down)
+///
+/// The divider ratio is N = N_INT + (FRAC + DITHERING / 2) / MOD, so FRAC is the nearest
+/// integer to `half_lsbs / 2 - DITHERING / 2`. In integers that is
+/// `(half_lsbs + 1 - DITHERING) / 2`, which can't go negative for small fractions and gives
+/// the same result as rounding the exact fraction, since the truncated half LSB can't move it
+/// across a rounding boundary. The result is clamped to MOD - 1 for fractions that round up
+/// to a whole LSB above the last valid FRAC.
+fn nearest_frac(half_lsbs: u64, modu: u32, dithering: bool) -> u32 {
+    let frac = (half_lsbs + 1 - dithering as u64) / 2;
+    frac.min(modu as u64 - 1) as u32
+}
+
+/// FRAC for a fractional divider part of `half_lsbs` halves of an LSB (rounded down), in the
+/// direction of `rounding`, along with the carry into N_INT
+/// `exact` is true if `half_lsbs` wasn't rounded. `Rounding::Exact` is planned separately
+/// and falls back to the nearest FRAC here
+pub(crate) fn round_frac(
+    half_lsbs: u64,
+    exact: bool,
+    modu: u32,
+    dithering: bool,
+    rounding: Rounding,
+) -> (u32, i32) {
+    // The programmed fraction is 2 * FRAC + DITHERING half LSBs
+    let offset = dithering as u64;
+    match rounding {
+        Rounding::Down if half_lsbs < offset => (modu - 1, -1),
+        Rounding::Down => (((half_lsbs - offset) / 2) as u32, 0),
+        Rounding::Up => {
+            let target = half_lsbs + !exact as u64;
+            match (target + 1).saturating_sub(offset) / 2 {
+                frac if frac >= modu as u64 => (0, 1),
+                frac => (frac as u32, 0),
+            }
+        }
+        Rounding::Nearest | Rounding::Exact => (nearest_frac(half_lsbs, modu, dithering), 0),
+    }
+}
+
+/// Checks that N = `n_int`, plus a fraction if `fractional`, is valid for `dsm_order`
+/// Integer ratios of 512 and up bypass the DSM, so only the integer mode limit applies
+pub(crate) fn check_divider_ratio(
+    n_int: u32,
+    fractional: bool,
+    dsm_order: u32,
+) -> Result<(), ValidationError> {
+    if n_int >= 512 {
+        if fractional {
+            return Err(ValidationError::FractionalIntegerMode { n: n_int });
+        }
+        if n_int > MAX_N_INT {
+            return Err(ValidationError::NOutOfRange {
+                given: n_int,
+                min: 24,
+                max: MAX_N_INT,
+            });
+        }
+        return Ok(());
+    }
+    // Valid divider ratios are controlled by the DSM, if there is a fraction part
+    // The field is only two bits wide
+    let range = math::n_range_for(DsmOrder::ALL[(dsm_order & 0b11) as usize]);
+    let (min, max) = (*range.start(), *range.end());
+    // min <= N <= max, where N is n_int plus a fraction
+    if n_int >= min && (n_int < max || (n_int == max && !fractional)) {
+        Ok(())
+    } else {
+        Err(ValidationError::NOutOfRange {
+            given: n_int,
+            min,
+            max,
+        })
+    }
+}
 
 // Public Enums
 #[repr(u32)]
-#[derive(PartialEq)]
+#[derive(Debug, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum ReferenceClockPath {
     Direct,
     Doubled,
@@ -20,7 +109,84 @@ pub enum ReferenceClockPath {
     Quartered,
 }
 
+impl ReferenceClockPath {
+    /// Every reference path, in the order of REF_PATH_SEL
+    pub const ALL: [ReferenceClockPath; 4] = [
+        ReferenceClockPath::Direct,
+        ReferenceClockPath::Doubled,
+        ReferenceClockPath::Halved,
+        ReferenceClockPath::Quartered,
+    ];
+}
+
+/// The rule a reference path breaks for the reference frequency or type
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ReferencePathRule {
+    /// References from 400 to 800 MHz must be quartered
+    QuarteredOnly,
+    /// References from 200 to 400 MHz must be halved or quartered
+    HalvedOrQuartered,
+    /// References from 25 to 200 MHz can't be doubled
+    NoDoubling,
+    /// A differential reference can't be doubled
+    NoDifferentialDoubling,
+}
+
+impl fmt::Display for ReferencePathRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ReferencePathRule::QuarteredOnly => "references from 400 to 800 MHz must be quartered",
+            ReferencePathRule::HalvedOrQuartered => {
+                "references from 200 to 400 MHz must be halved or quartered"
+            }
+            ReferencePathRule::NoDoubling => "references from 25 to 200 MHz can't be doubled",
+            ReferencePathRule::NoDifferentialDoubling => {
+                "a differential reference can't be doubled"
+            }
+        })
+    }
+}
+
+/// A set of reference paths, see `legal_reference_paths`
+#[derive(PartialEq, Eq, Copy, Clone, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ReferencePaths(u8);
+
+impl ReferencePaths {
+    /// Returns true if `path` is in the set
+    pub fn contains(&self, path: ReferenceClockPath) -> bool {
+        self.0 & (1 << path as u8) != 0
+    }
+
+    /// The paths in the set, in the order of `ReferenceClockPath::ALL`
+    pub fn iter(&self) -> impl Iterator<Item = ReferenceClockPath> {
+        let set = *self;
+        ReferenceClockPath::ALL
+            .into_iter()
+            .filter(move |&path| set.contains(path))
+    }
+
+    /// Returns true if the set holds no path
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    fn insert(&mut self, path: ReferenceClockPath) {
+        self.0 |= 1 << path as u8;
+    }
+}
+
+impl fmt::Debug for ReferencePaths {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
 #[repr(u32)]
+#[derive(Debug, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[allow(clippy::enum_variant_names)]
 pub enum DsmOrder {
     ThirdOrder,
     SecondOrder,
@@ -28,20 +194,52 @@ pub enum DsmOrder {
     FourthOrder,
 }
 
-#[repr(u8)]
-pub enum PllPath {
-    Direct,
-    Halved,
+impl DsmOrder {
+    /// Every DSM order, in the order of DSM_ORDER
+    pub const ALL: [DsmOrder; 4] = [
+        DsmOrder::ThirdOrder,
+        DsmOrder::SecondOrder,
+        DsmOrder::FirstOrder,
+        DsmOrder::FourthOrder,
+    ];
 }
 
+/// An RF output of the device
+/// Only RF1 is supported so far, RF2 will be added as a variant once the driver covers it
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum Port {
+    /// RF1, see `set_rf1_path` for the signal it carries
+    Rf1,
+}
+
+/// Signal driving the RF1 output buffer
 #[repr(u32)]
+#[derive(Debug, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Rf1Path {
+    /// The VCO output
+    Fundamental,
+    /// The VCO output divided by 2
+    Divided,
+}
+
+#[repr(u32)]
+#[derive(Debug, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[allow(clippy::enum_variant_names)]
 pub enum PfdDelayMode {
     NoDelay,
     VcoDivDelay,
     RefDivDelay,
+    /// The reserved value 3, only ever read back from a register written raw
+    Reserved,
 }
 
 #[repr(u32)]
+#[derive(Debug, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum PfdDelay {
     /// 1.2 ns / 0 A
     Default,
@@ -53,135 +251,427 @@ pub enum PfdDelay {
     ThreeZero,
 }
 
+/// Length of the precharge phase of each VCO calibration step, in calibrator clock cycles
+/// Longer delays make calibration more robust at temperature extremes at the cost of
+/// calibration time
+#[repr(u32)]
+#[derive(Debug, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PrechargeDelay {
+    /// 1 cycle
+    One,
+    /// 2 cycles
+    Two,
+    /// 3 cycles
+    Three,
+    /// 4 cycles, the most robust setting
+    Four,
+}
+
+/// Output frequency granularity of the current configuration
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct FrequencyResolution {
+    /// Smallest output frequency step in Hz, one LSB of FRAC
+    pub step: Hz,
+    /// Worst-case difference between a requested and programmed frequency in Hz
+    /// `set_output_frequency` rounds FRAC to the nearest value, so this is half a step
+    pub max_error: Hz,
+}
+
+/// Every frequency inside the chip, from the reference input to RF1, see `clock_tree`
+#[derive(Debug, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ClockTree {
+    /// Reference input frequency in Hz
+    pub ref_freq: Hz,
+    /// Doubler, halver or quarterer in front of the reference divider
+    pub reference_path: ReferenceClockPath,
+    /// Reference divider R
+    pub r: u32,
+    /// Phase-frequency detector frequency in Hz, the reference after the path and R
+    pub pfd_freq: Hz,
+    /// VCO calibrator division of the PFD frequency
+    pub calibrator_division: u32,
+    /// VCO calibrator frequency in Hz, `None` while the division is 0
+    pub calibrator_freq: Option<Hz>,
+    /// VCO core frequency in Hz
+    pub vco_freq: Hz,
+    /// Whether the VCO is halved on its way to the N divider
+    pub pll_path: PllPath,
+    /// N divider input in Hz, compared against the PFD frequency times N
+    pub feedback_freq: Hz,
+    /// Whether RF1 carries the VCO or the VCO divided by 2
+    pub rf1_path: Rf1Path,
+    /// RF1 output frequency in Hz
+    pub rf1_freq: Hz,
+}
+
+/// The synthesis parameters behind the output frequency, see `divider_state`
+#[derive(Debug, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DividerState {
+    /// Integer part of the divider ratio
+    pub n: u32,
+    /// Numerator of the fractional part of the divider ratio
+    pub frac: u32,
+    /// Denominator of the fractional part of the divider ratio
+    pub modu: u32,
+    /// Whether the DSM is dithered, offsetting FRAC by half an LSB
+    pub dithering: bool,
+    /// Whether the VCO is halved on its way to the N divider
+    pub pll_path: PllPath,
+    /// Whether RF1 carries the VCO or the VCO divided by 2
+    pub rf1_path: Rf1Path,
+}
+
 impl<SPI, LE> STuW81300<SPI, LE>
 where
     SPI: Transfer<u8>,
     LE: OutputPin,
+{
+    /// Reads the device ID before a driver is set up and hands the bus back either way
+    /// Tells whether a chip is fitted and which variant it is, so bring-up code can pick a
+    /// configuration. Nothing is written, an ID that matches no variant (e.g. from a bus with
+    /// no chip on it) is `Error::UnknownDevice`
+    #[allow(clippy::type_complexity)]
+    pub fn probe(spi: SPI, le: LE) -> Result<(SPI, LE, DeviceVariant), (SPI, LE, Error<SPI, LE>)> {
+        // Neither the supply nor the reference matter for a read
+        let mut vco = STuW81300::new_hz(
+            spi,
+            le,
+            crate::SupplyVoltage::HighVoltage,
+            100_000_000,
+            crate::ReferenceType::SingleEnded,
+        );
+        let variant = vco.device_id().and_then(|device_id| {
+            DeviceVariant::from_id(device_id).ok_or(Error::UnknownDevice(device_id))
+        });
+        let STuW81300 { spi, le, .. } = vco;
+        match variant {
+            Ok(variant) => Ok((spi, le, variant)),
+            Err(e) => Err((spi, le, e)),
+        }
+    }
+}
+
+impl<SPI, LE, PD, MUTE, D, LD> STuW81300<SPI, LE, PD, MUTE, D, LD>
+where
+    SPI: Transfer<u8>,
+    LE: OutputPin,
+    PD: OutputPin,
+    MUTE: OutputPin,
+    LD: InputPin,
 {
     /// Retrieves the device id, either 0x804B or 0x8052
     pub fn device_id(&mut self) -> Result<u32, Error<SPI, LE>> {
         self.read(RegisterAddr::ST11)
     }
 
+    /// The device variant detected by `init`, `None` before the first `init`
+    pub fn variant(&self) -> Option<DeviceVariant> {
+        self.variant
+    }
+
+    /// Reads the raw contents of any register, see `decode` to interpret the value
+    pub fn read_raw(&mut self, addr: RegisterAddr) -> Result<u32, Error<SPI, LE>> {
+        self.read(addr)
+    }
+
     /// Initializes the device
-    pub fn init(&mut self) -> Result<(), Error<SPI, LE>> {
+    /// If a hardware power down pin is attached the device is brought up with `power_up` first,
+    /// whatever level the pin was left at, so the regulators settle before ST9 is written
+    /// In the `WriteOnly` interface mode the device ID isn't checked and every writable
+    /// register is cleared first, see `set_interface`
+    /// Fails without writing the power settings if a setting left in ST4 doesn't suit the
+    /// supply, see `validate_against_supply`
+    pub fn init(&mut self) -> Result<(), Error<SPI, LE>>
+    where
+        D: DelayUs<u32>,
+    {
+        if self.pd.is_some() {
+            self.power_up()?;
+        }
+        self.init_powered()
+    }
+
+    /// `init` for a device known to be out of power down, e.g. after an async `power_up`
+    pub(crate) fn init_powered(&mut self) -> Result<(), Error<SPI, LE>> {
         // Initialization register
-        self.write(RegisterAddr::ST9, 0)?;
-        // Read device_id
-        let device_id = self.device_id()?;
+        self.write(RegisterAddr::ST9, regs::ST9::INIT.into())?;
+        let variant = match self.interface {
+            crate::Interface::FourWire => {
+                let device_id = self.device_id()?;
+                DeviceVariant::from_id(device_id).ok_or(Error::UnknownDevice(device_id))?
+            }
+            crate::Interface::WriteOnly { variant } => {
+                // Nothing can be read back, so start every register from a known state
+                self.batched(|vco| {
+                    RegisterAddr::iter()
+                        .filter(|addr| !addr.read_only() && *addr != RegisterAddr::ST9)
+                        .try_for_each(|addr| vco.write(addr, 0))
+                })?;
+                variant
+            }
+        };
+        self.variant = Some(variant);
 
         // Power settings
         let mut st4: regs::ST4 = self.read_reg()?;
-        match device_id {
-            // STUW81300-1T and STUW81300-1TR
-            0x804B => {
-                st4.calb_3v3_mode1 = false;
-                st4.calb_3v3_mode0 = false;
-            }
-            // STUW81300T and STUW81300TR
-            0x8052 => {
-                st4.calb_3v3_mode1 = self.supply_voltage == crate::SupplyVoltage::LowVoltage;
-                st4.calb_3v3_mode0 = self.supply_voltage == crate::SupplyVoltage::LowVoltage;
-            }
-            _ => unreachable!(),
-        }
-        st4.rf_out_3v3 = self.supply_voltage == crate::SupplyVoltage::LowVoltage;
+        configure_supply(&mut st4, self.supply_voltage, variant);
         st4.ref_buff_mode = self.ref_type as u32;
+        check_supply(&st4, self.supply_voltage, self.variant)?;
 
         self.write_reg(&st4)?;
         Ok(())
     }
 
+    /// Whether reference path `path` can be used with the reference frequency and type
+    pub(crate) fn reference_path_supported(&self, path: ReferenceClockPath) -> bool {
+        self.reference_path_rule(path).is_none()
+    }
+
+    /// The rule reference path `path` breaks for the reference frequency and type, if any
+    fn reference_path_rule(&self, path: ReferenceClockPath) -> Option<ReferencePathRule> {
+        let (rule, supported) = if (400_000_000..=800_000_000).contains(&self.ref_freq) {
+            // Reference clocks higher than 400 MHz must be quartered
+            (
+                ReferencePathRule::QuarteredOnly,
+                matches!(path, ReferenceClockPath::Quartered),
+            )
+        } else if (200_000_000..=400_000_000).contains(&self.ref_freq) {
+            // Reference clocks between 200 and 400 MHz must be halved or quartered
+            (
+                ReferencePathRule::HalvedOrQuartered,
+                matches!(
+                    path,
+                    ReferenceClockPath::Halved | ReferenceClockPath::Quartered
+                ),
+            )
+        } else if (25_000_000..=200_000_000).contains(&self.ref_freq) {
+            // Reference clocks higher than 25 MHz can't be doubled
+            (
+                ReferencePathRule::NoDoubling,
+                path != ReferenceClockPath::Doubled,
+            )
+        } else {
+            (ReferencePathRule::NoDoubling, true)
+        };
+        // Doubling isn't applicable in differential mode
+        let differential_doubled = self.ref_type == crate::ReferenceType::Differential
+            && path == ReferenceClockPath::Doubled;
+        match (supported, differential_doubled) {
+            (false, _) => Some(rule),
+            (true, true) => Some(ReferencePathRule::NoDifferentialDoubling),
+            (true, false) => None,
+        }
+    }
+
+    /// The reference paths the reference frequency and type allow, e.g. for the options of
+    /// a settings menu
+    pub fn legal_reference_paths(&self) -> ReferencePaths {
+        let mut paths = ReferencePaths::default();
+        for path in ReferenceClockPath::ALL {
+            if self.reference_path_supported(path) {
+                paths.insert(path);
+            }
+        }
+        paths
+    }
+
+    /// The error for using reference path `path`, `None` if it can be used
+    pub(crate) fn reference_path_error(&self, path: ReferenceClockPath) -> Option<ValidationError> {
+        Some(ValidationError::ReferencePathUnsupported {
+            path,
+            ref_freq: hz(self.ref_freq),
+            rule: self.reference_path_rule(path)?,
+            legal: self.legal_reference_paths(),
+        })
+    }
+
     /// Sets the reference clock path
     /// This setting in combination with `set_reference_clock_divider` controls the frequency
     /// of the PFD. The result of which can be found with `get_pfd_frequency`
+    /// If the reference divider has already been set, the calibrator division is updated
+    /// to follow the new PFD frequency (see `update_calibrator`)
     pub fn set_reference_clock_path(
         &mut self,
         path: ReferenceClockPath,
     ) -> Result<(), Error<SPI, LE>> {
-        if (self.ref_freq >= 400e6) && (self.ref_freq <= 800e6) {
-            assert!(
-                matches!(path, ReferenceClockPath::Quartered),
-                "Reference clock path must be Quartered for reference clocks higher than 400 MHz"
-            );
-        } else if (self.ref_freq >= 200e6) && (self.ref_freq <= 400e6) {
-            assert!(matches!(
-                path,
-                ReferenceClockPath::Halved | ReferenceClockPath::Quartered
-            ),"Reference clock path must be Halved or Quartered for reference clocks between 200 and 400 MHz");
-        } else if (self.ref_freq >= 25e6) && (self.ref_freq <= 200e6) {
-            assert!(matches!(
-                path,
-                ReferenceClockPath::Halved | ReferenceClockPath::Quartered | ReferenceClockPath::Direct
-            ),"Reference clock path cannot be doubled if the reference clock is higher than 25 MHz");
-        }
-        if self.ref_type == crate::ReferenceType::Differential {
-            assert!(
-                path != ReferenceClockPath::Doubled,
-                "Reference clock path of doubled is not applicable in differential mode"
-            );
+        if let Some(error) = self.reference_path_error(path) {
+            return Err(error.into());
         }
 
         let mut st3: regs::ST3 = self.read_reg()?;
         st3.ref_path_sel = path as u32;
         self.write_reg(&st3)?;
+        self.forget_tuning();
+        // R of 0 means the divider hasn't been configured yet, so there's no PFD to follow
+        if st3.r != 0 {
+            self.update_calibrator()?;
+        }
         Ok(())
     }
 
     /// Sets the reference clock divider for the PFD. This must be between 1 and 8191.
+    /// The calibrator division is updated to follow the new PFD frequency (see `update_calibrator`)
     pub fn set_reference_clock_divider(&mut self, r: u32) -> Result<(), Error<SPI, LE>> {
-        assert!(
-            (1..=8191).contains(&r),
-            "The reference clock divider ratio must be between 1 and 8191"
-        );
+        if !(1..=8191).contains(&r) {
+            return Err(ValidationError::RefDividerOutOfRange {
+                given: r,
+                min: 1,
+                max: 8191,
+            }
+            .into());
+        }
         let mut st3: regs::ST3 = self.read_reg()?;
         st3.r = r;
-        self.write_reg(&st3)
+        self.write_reg(&st3)?;
+        self.forget_tuning();
+        self.update_calibrator()
     }
 
     /// Gets the internal phase-frequency detector (PFD) frequency in Hz
-    pub fn get_pfd_frequency(&mut self) -> Result<f32, Error<SPI, LE>> {
+    /// While R is 0 there is no PFD frequency, this is then infinite, or 0 with `no-float`
+    pub fn get_pfd_frequency(&mut self) -> Result<Hz, Error<SPI, LE>> {
         let st3: regs::ST3 = self.read_reg()?;
-        let r = st3.r as f32;
+        Ok(self.pfd_frequency_of(&st3))
+    }
+
+    /// The PFD frequency from the cached register values, without any SPI transfers
+    /// `None` until ST3 has been written or read, see `cached_reg`
+    pub fn pfd_frequency_cached(&self) -> Option<Hz> {
+        Some(self.pfd_frequency_of(&self.cached_reg()?))
+    }
+
+    /// The PFD frequency in Hz for the reference path and divider in `st3`
+    #[cfg(not(feature = "no-float"))]
+    fn pfd_frequency_of(&self, st3: &regs::ST3) -> Hz {
+        let r = st3.r as Float;
+        let ref_freq = self.ref_freq as Float;
         let first_stage = match st3.ref_path_sel {
-            0 => self.ref_freq,
-            1 => self.ref_freq * 2f32,
-            2 => self.ref_freq / 2f32,
-            3 => self.ref_freq / 4f32,
-            _ => unreachable!(),
+            0 => ref_freq,
+            1 => ref_freq * 2.0,
+            2 => ref_freq / 2.0,
+            // 3, the field is only two bits wide
+            _ => ref_freq / 4.0,
         };
-        Ok(first_stage / r)
+        first_stage / r
+    }
+
+    /// The PFD frequency for the reference path and divider in `st3`, rounded to the nearest Hz
+    /// 0 while R is 0
+    #[cfg(feature = "no-float")]
+    fn pfd_frequency_of(&self, st3: &regs::ST3) -> Hz {
+        if st3.r == 0 {
+            return 0;
+        }
+        let (num, den) = math::pfd_ratio(st3.ref_path_sel, st3.r);
+        fixed::mul_div_round(self.ref_freq, num, den)
+    }
+
+    /// Reads ST3 for a PFD frequency calculation
+    /// Returns `ValidationError::RefDividerOutOfRange` while R is 0, as it is after `init`
+    fn pfd_reg(&mut self) -> Result<regs::ST3, Error<SPI, LE>> {
+        let st3: regs::ST3 = self.read_reg()?;
+        if st3.r == 0 {
+            return Err(ValidationError::RefDividerOutOfRange {
+                given: 0,
+                min: 1,
+                max: 8191,
+            }
+            .into());
+        }
+        Ok(st3)
+    }
+
+    /// The PFD frequency as the ratio `ref_freq * num / den`
+    /// Returns `ValidationError::RefDividerOutOfRange` while R is 0
+    fn pfd_ratio(&mut self) -> Result<(u64, u64), Error<SPI, LE>> {
+        let st3 = self.pfd_reg()?;
+        Ok(math::pfd_ratio(st3.ref_path_sel, st3.r))
     }
 
     /// Gets the current output frequency in Hz
-    pub fn get_output_frequency(&mut self) -> Result<f32, Error<SPI, LE>> {
+    pub fn get_output_frequency(&mut self) -> Result<Hz, Error<SPI, LE>> {
         // Grab all the registers we need to calculate this
         let st0: regs::ST0 = self.read_reg()?;
         let st1: regs::ST1 = self.read_reg()?;
         let st2: regs::ST2 = self.read_reg()?;
+        let st3: regs::ST3 = self.read_reg()?;
         let st6: regs::ST6 = self.read_reg()?;
-        // Depending if the output is > 6 GHz (in which case PLL_SEL will be set high)
-        // this is doubled
-        let n_int = st0.n as f32;
-        let frac = st1.frac as f32;
-        let modu = st2.modu as f32;
-        let dithering = (st6.dithering as u32) as f32;
-        let n = n_int + frac / modu + dithering / (2f32 * modu);
-        let f_out = self.get_pfd_frequency()? * n as f32;
-        if st1.pll_sel {
-            Ok(2f32 * f_out)
-        } else {
-            Ok(f_out)
+        Ok(self.output_frequency_of(&st0, &st1, &st2, &st3, &st6))
+    }
+
+    /// The output frequency from the cached register values, without any SPI transfers
+    /// `None` until ST0, ST1, ST2, ST3 and ST6 have each been written or read, see `cached_reg`
+    pub fn output_frequency_cached(&self) -> Option<Hz> {
+        Some(self.output_frequency_of(
+            &self.cached_reg()?,
+            &self.cached_reg()?,
+            &self.cached_reg()?,
+            &self.cached_reg()?,
+            &self.cached_reg()?,
+        ))
+    }
+
+    /// The output frequency in Hz for the given divider, path and dithering settings
+    #[cfg(not(feature = "no-float"))]
+    pub(crate) fn output_frequency_of(
+        &self,
+        st0: &regs::ST0,
+        st1: &regs::ST1,
+        st2: &regs::ST2,
+        st3: &regs::ST3,
+        st6: &regs::ST6,
+    ) -> Hz {
+        let n_int = st0.n as Float;
+        let frac = st1.frac as Float;
+        let modu = st2.modu as Float;
+        let dithering = (st6.dithering as u32) as Float;
+        let n = n_int + frac / modu + dithering / (2.0 * modu);
+        // The divider ratio sets the frequency at the N divider input, not the VCO's
+        let mut f_out = self.pfd_frequency_of(st3) * n * PllPath::of(st1).divisor() as Float;
+        if st1.rf1_sel {
+            f_out /= 2.0;
         }
+        f_out
+    }
+
+    /// The output frequency for the given divider, path and dithering settings, rounded to
+    /// the nearest Hz, 0 while R or MOD is 0
+    #[cfg(feature = "no-float")]
+    pub(crate) fn output_frequency_of(
+        &self,
+        st0: &regs::ST0,
+        st1: &regs::ST1,
+        st2: &regs::ST2,
+        st3: &regs::ST3,
+        st6: &regs::ST6,
+    ) -> Hz {
+        if st3.r == 0 || st2.modu == 0 {
+            return 0;
+        }
+        let (num, den) = math::pfd_ratio(st3.ref_path_sel, st3.r);
+        let num = num * PllPath::of(st1).divisor() as u64;
+        let den = if st1.rf1_sel { 2 * den } else { den };
+        // f = fpfd * (N + FRAC/MOD + DITHERING/(2*MOD)), over a common denominator of 2*MOD
+        let modu = st2.modu as u64;
+        let n = 2 * st0.n as u64 * modu + 2 * st1.frac as u64 + st6.dithering as u64;
+        fixed::mul_div_round(self.ref_freq, num * n, den * 2 * modu)
     }
 
     /// Set the dithering function, used to reduce the fractional spur tones by
     /// spreading the DSM sequence and consequently the energy of the spurs over
     /// a wider bandwidth
+    /// The device has no controls for the dither amplitude or the DSM seed, and no way to
+    /// reset the DSM, the remaining ST6 bits are reserved. Only the order and this switch can
+    /// be set
     pub fn set_dithering(&mut self, active: bool) -> Result<(), Error<SPI, LE>> {
         let mut st6: regs::ST6 = self.read_reg()?;
         st6.dithering = active;
-        self.write_reg(&st6)
+        self.write_reg(&st6)?;
+        self.forget_tuning();
+        Ok(())
     }
 
     /// Sets the delta-sigma modulator order. Only has an effect when the divider ratio has
@@ -192,94 +682,179 @@ where
         self.write_reg(&st6)
     }
 
+    /// Sets the integer part of the divider ratio, leaving FRAC and MOD alone
+    /// With a FRAC other than 0 it is checked against the limits of the current DSM order,
+    /// integer ratios of 512 and up run in integer mode and may go up to 131071
+    pub fn set_n(&mut self, n_int: u32) -> Result<(), Error<SPI, LE>> {
+        let st1: regs::ST1 = self.read_reg()?;
+        let st6: regs::ST6 = self.read_reg()?;
+        check_divider_ratio(n_int, st1.frac != 0, st6.dsm_order)?;
+        let mut st0: regs::ST0 = self.read_reg()?;
+        st0.n = n_int;
+        self.write_reg(&st0)?;
+        self.forget_tuning();
+        Ok(())
+    }
+
     /// Sets the MOD value for Fractional-N operation
     pub fn set_mod(&mut self, modu: u32) -> Result<(), Error<SPI, LE>> {
-        assert!(
-            (2..=MAX_MOD).contains(&modu),
-            "MOD must be between 2 and 2097151"
-        );
+        if !(2..=MAX_MOD).contains(&modu) {
+            return Err(ValidationError::ModOutOfRange {
+                given: modu,
+                min: 2,
+                max: MAX_MOD,
+            }
+            .into());
+        }
         let mut st2: regs::ST2 = self.read_reg()?;
         st2.modu = modu;
-        self.write_reg(&st2)
+        self.write_reg(&st2)?;
+        self.forget_tuning();
+        Ok(())
     }
 
     /// Sets the FRAC value for Fractional-N operation, MOD must be set first
+    /// Use `set_frac_mod` to change both
     pub fn set_frac(&mut self, frac: u32) -> Result<(), Error<SPI, LE>> {
         let st2: regs::ST2 = self.read_reg()?;
-        assert!(
-            frac <= st2.modu,
-            "FRAC must be between 0 and MOD-1, set MOD first",
-        );
+        if frac >= st2.modu {
+            return Err(ValidationError::FracOutOfRange {
+                given: frac,
+                max: st2.modu.saturating_sub(1),
+            }
+            .into());
+        }
         let mut st1: regs::ST1 = self.read_reg()?;
         st1.frac = frac;
-        self.write_reg(&st1)
+        self.write_reg(&st1)?;
+        self.forget_tuning();
+        Ok(())
+    }
+
+    /// Sets FRAC and MOD together, in any order relative to their current values
+    /// The pair is validated before anything is written, then MOD is written before FRAC
+    pub fn set_frac_mod(&mut self, frac: u32, modu: u32) -> Result<(), Error<SPI, LE>> {
+        if !(2..=MAX_MOD).contains(&modu) {
+            return Err(ValidationError::ModOutOfRange {
+                given: modu,
+                min: 2,
+                max: MAX_MOD,
+            }
+            .into());
+        }
+        if frac >= modu {
+            return Err(ValidationError::FracOutOfRange {
+                given: frac,
+                max: modu - 1,
+            }
+            .into());
+        }
+        self.batched(|vco| {
+            let mut st2: regs::ST2 = vco.read_reg()?;
+            st2.modu = modu;
+            vco.write_reg(&st2)?;
+            let mut st1: regs::ST1 = vco.read_reg()?;
+            st1.frac = frac;
+            vco.write_reg(&st1)
+        })?;
+        self.forget_tuning();
+        Ok(())
     }
 
     /// Sets the divider ratio, maximizing MOD to reduce frequency error
     /// Also, the calibrator frequency is set accordingly to the maximum of 250 kHz
-    pub fn set_divider_ratio(&mut self, n: f32) -> Result<(), Error<SPI, LE>> {
-        assert!(n >= 24f32, "Division ratio must be greater than 23");
-        // Valid divider ratios are controlled by the DSM, if there is a fraction part
-        let n_int = n.trunc();
-        let n_frac = n.fract();
-        if n_int >= 512f32 {
-            assert!(
-                n_frac == 0f32,
-                "Division ratios larger than 512 can't have fractional components"
-            );
+    /// N has to be in `math::n_range_for` the DSM order, see `math::dsm_orders_for`
+    #[cfg(not(feature = "no-float"))]
+    pub fn set_divider_ratio(&mut self, n: Float) -> Result<(), Error<SPI, LE>> {
+        let (n_int, frac, fractional) = self.split_divider_ratio(n)?;
+        let words = self.divider_words(n_int, frac, MAX_MOD, fractional)?;
+        self.write_divider(words)?;
+        self.forget_tuning();
+        Ok(())
+    }
+
+    /// Sets the divider ratio to exactly N = `n_int` + `frac` / `modu`, plus half an LSB of
+    /// FRAC while dithering is enabled
+    /// The ratio is checked against the limits of the current DSM order like `set_divider_ratio`
+    pub fn set_divider_ratio_parts(
+        &mut self,
+        n_int: u32,
+        frac: u32,
+        modu: u32,
+    ) -> Result<(), Error<SPI, LE>> {
+        if !(2..=MAX_MOD).contains(&modu) {
+            return Err(ValidationError::ModOutOfRange {
+                given: modu,
+                min: 2,
+                max: MAX_MOD,
+            }
+            .into());
+        }
+        if frac >= modu {
+            return Err(ValidationError::FracOutOfRange {
+                given: frac,
+                max: modu - 1,
+            }
+            .into());
+        }
+        let words = self.divider_words(n_int, frac, modu, frac != 0)?;
+        self.write_divider(words)?;
+        self.forget_tuning();
+        Ok(())
+    }
+
+    /// Writes the divider registers as one batch, so MOD lands before FRAC and N goes last
+    fn write_divider(
+        &mut self,
+        (st0, st1, st2): (regs::ST0, regs::ST1, regs::ST2),
+    ) -> Result<(), Error<SPI, LE>> {
+        self.batched(|vco| {
+            vco.write_reg(&st0)?;
+            vco.write_reg(&st1)?;
+            vco.write_reg(&st2)
+        })
+    }
+
+    /// Splits divider ratio `n` into the integer part and the FRAC value for the maximum MOD
+    /// Returns `(n_int, frac, fractional)` where `fractional` is true if `n` isn't an integer
+    #[cfg(not(feature = "no-float"))]
+    fn split_divider_ratio(&mut self, n: Float) -> Result<(u32, u32, bool), Error<SPI, LE>> {
+        if n < 24.0 {
+            return Err(ValidationError::NOutOfRange {
+                given: n as u32,
+                min: 24,
+                max: MAX_N_INT,
+            }
+            .into());
         }
         let st6: regs::ST6 = self.read_reg()?;
+        let n_int = float::trunc(n);
+        let n_frac = n - n_int;
+        // The only float rounding, everything after this is integer
+        let half_lsbs = float::trunc(2.0 * n_frac * MAX_MOD as Float) as u64;
+        let frac = nearest_frac(half_lsbs, MAX_MOD, st6.dithering);
+        Ok((n_int as u32, frac, n_frac != 0.0))
+    }
+
+    /// Checks the divider ratio against the limits of the DSM and computes the ST0, ST1 and ST2
+    /// contents for it without writing them
+    fn divider_words(
+        &mut self,
+        n_int: u32,
+        frac: u32,
+        modu: u32,
+        fractional: bool,
+    ) -> Result<(regs::ST0, regs::ST1, regs::ST2), Error<SPI, LE>> {
+        let st6: regs::ST6 = self.read_reg()?;
+        check_divider_ratio(n_int, fractional, st6.dsm_order)?;
         let mut st0: regs::ST0 = self.read_reg()?;
         let mut st1: regs::ST1 = self.read_reg()?;
         let mut st2: regs::ST2 = self.read_reg()?;
 
-        match st6.dsm_order {
-            0 => assert!(
-                (27f32..=507f32).contains(&n),
-                "Third order DSM requires 27 <= N <= 507"
-            ), // Third Order
-            1 => assert!(
-                (25f32..=509f32).contains(&n),
-                "Second order DSM requires 25 <= N <= 509"
-            ), // Second Order
-            2 => assert!(
-                (24f32..=510f32).contains(&n),
-                "First order DSM requires 24 <= N <= 510"
-            ), // First Order
-            3 => assert!(
-                (31f32..=503f32).contains(&n),
-                "Third order DSM requires 31 <= N <= 503"
-            ), // Fourth Order
-            _ => unreachable!(),
-        };
-
-        let modu = MAX_MOD;
-        let frac = (((2f32 * n_frac * (modu as f32)) - ((st6.dithering as u32) as f32)) / 2f32)
-            .round() as u32;
-
-        st0.n = n_int as u32;
+        st0.n = n_int;
         st1.frac = frac;
         st2.modu = modu;
-
-        self.write_reg(&st0)?;
-        self.write_reg(&st1)?;
-        self.write_reg(&st2)
-    }
-
-    /// Sets the signal path to the PLL. This must be `Halved` for VCO operation above 6 GHz.
-    pub fn set_pll_path(&mut self, path: PllPath) -> Result<(), Error<SPI, LE>> {
-        let mut st1: regs::ST1 = self.read_reg()?;
-        st1.pll_sel = path as u8 == 1;
-        self.write_reg(&st1)
-    }
-
-    /// Gets the signal path to the PLL
-    pub fn get_pll_path(&mut self) -> Result<PllPath, Error<SPI, LE>> {
-        let st1: regs::ST1 = self.read_reg()?;
-        Ok(match st1.pll_sel {
-            true => PllPath::Direct,
-            false => PllPath::Halved,
-        })
+        Ok((st0, st1, st2))
     }
 
     /// Sets the desired output frequency
@@ -287,37 +862,411 @@ where
     /// There are an infinite number of solutions for the various configurations in this device,
     /// so the strategy here is to minimize spurs. It does this by maximizing FRAC and MOD, keeping the
     /// same FRAC/MOD ratio and setting DITHERING to 1. As a drawback, there will be small frequency error.
+    /// By default the nearest frequency the divider can produce is picked, see `set_rounding`
     /// Also, the calibrator frequency is set accordingly to the maximum of 250 kHz
     ///
     /// This function may fail if the computed divider ratio isn't feasable, in which case changes to the DSM order
     /// and reference divider network may be necessary
-    pub fn set_output_frequency(&mut self, f: f32) -> Result<(), Error<SPI, LE>> {
-        self.set_dithering(true)?;
+    ///
+    /// The new settings are worked out in memory first and sent as at most five writes, ST4 and
+    /// ST6 first, then MOD, FRAC and finally N, which starts the VCO calibration
+    ///
+    /// Returns `Error::FrequencyOutOfRange` if `f` is outside of the RF1 range of the device variant
+    pub fn set_output_frequency(&mut self, f: Hz) -> Result<(), Error<SPI, LE>> {
+        self.set_output_frequency_planned(f, false)
+    }
+
+    /// `set_output_frequency`, switching to integer mode for divider ratios of 512 and up if
+    /// `allow_integer`, see `PlanStrategy::AllowInteger`
+    pub(crate) fn set_output_frequency_planned(
+        &mut self,
+        f: Hz,
+        allow_integer: bool,
+    ) -> Result<(), Error<SPI, LE>> {
+        self.batched(|vco| {
+            let words = vco.plan_output_frequency(f, allow_integer)?;
+            vco.write_divider(words)
+        })?;
+        self.requested_freq = Some(f);
+        // Everything it depends on was just written or read, so this is usually free, unless
+        // the writes are still held back by an outer batch
+        let cached = match self.batch {
+            None => self.output_frequency_cached(),
+            Some(_) => None,
+        };
+        let programmed = match cached {
+            Some(programmed) => programmed,
+            None => self.get_output_frequency()?,
+        };
+        self.programmed_freq = Some(programmed);
+        debug!("retuned to {} Hz, programmed {} Hz", f, programmed);
+        Ok(())
+    }
+
+    /// Sets the frequency of output `port`, see `set_output_frequency`
+    pub fn set_frequency(&mut self, port: Port, f: Hz) -> Result<(), Error<SPI, LE>> {
+        match port {
+            Port::Rf1 => self.set_output_frequency(f),
+        }
+    }
+
+    /// Gets the current frequency of output `port` in Hz
+    pub fn get_frequency(&mut self, port: Port) -> Result<Hz, Error<SPI, LE>> {
+        match port {
+            Port::Rf1 => self.get_output_frequency(),
+        }
+    }
+
+    /// Writes every setting `set_output_frequency` needs apart from the divider registers,
+    /// and returns the divider register contents for the caller to write
+    pub(crate) fn prepare_output_frequency(
+        &mut self,
+        f: Hz,
+    ) -> Result<(regs::ST0, regs::ST1, regs::ST2), Error<SPI, LE>> {
+        self.plan_output_frequency(f, false)
+    }
+
+    /// `prepare_output_frequency`, switching to integer mode for divider ratios of 512 and up
+    /// if `allow_integer`
+    fn plan_output_frequency(
+        &mut self,
+        f: Hz,
+        allow_integer: bool,
+    ) -> Result<(regs::ST0, regs::ST1, regs::ST2), Error<SPI, LE>> {
+        let (min, max) = self.output_frequency_range();
+        // The VCO runs at twice the output frequency on the divided RF1 path
+        let st1: regs::ST1 = self.read_reg()?;
+        let div = if st1.rf1_sel { 2 } else { 1 };
+        let (min, max) = (min / div as Hz, max / div as Hz);
+        if !(min..=max).contains(&f) {
+            return Err(Error::FrequencyOutOfRange { min, max });
+        }
+        let f = f * div as Hz;
+        self.set_dithering(self.rounding != Rounding::Exact)?;
+        let path = PllPath::for_vco(f);
+        let (n_int, frac, modu, fractional) = match self.rounding {
+            Rounding::Exact => self.exact_divider_ratio(f, path)?,
+            _ => {
+                let (n_int, frac, fractional) = self.plan_divider_ratio(f, path)?;
+                (n_int, frac, MAX_MOD, fractional)
+            }
+        };
+        // 511 and a fraction may round up to 512, which only integer mode can produce
+        let (n_int, frac, fractional) =
+            if allow_integer && (n_int >= 512 || (n_int == 511 && fractional)) {
+                // The DSM is bypassed, so there's no FRAC and nothing to dither
+                self.set_dithering(false)?;
+                let up = match self.rounding {
+                    // FRAC was planned with dithering, which adds half an LSB
+                    Rounding::Nearest => 2 * frac as u64 + 1 >= modu as u64,
+                    Rounding::Down => false,
+                    Rounding::Up => fractional,
+                    Rounding::Exact if fractional => {
+                        return Err(ValidationError::InexactFrequency {
+                            frequency: f / div as Hz,
+                        }
+                        .into())
+                    }
+                    Rounding::Exact => false,
+                };
+                (n_int + up as u32, 0, false)
+            } else {
+                (n_int, frac, fractional)
+            };
+        // Integer mode ratios are checked with the rest in `divider_words`
+        if !allow_integer && (n_int > 512 || (n_int == 512 && fractional)) {
+            return Err(ValidationError::IntegerModeRequired { n: n_int }.into());
+        }
+        self.update_calibrator()?;
+
+        let mut st4: regs::ST4 = self.read_reg()?;
+        match self.supply_voltage {
+            crate::SupplyVoltage::LowVoltage => st4.vcalb_mode = true,
+            crate::SupplyVoltage::HighVoltage => st4.vcalb_mode = f > hz(4_500_000_000),
+        };
+        self.write_reg(&st4)?;
+
+        let (st0, mut st1, st2) = self.divider_words(n_int, frac, modu, fractional)?;
+        path.select(&mut st1);
+        Ok((st0, st1, st2))
+    }
+
+    /// Computes the divider ratio for output frequency `f` at the current PFD frequency,
+    /// split like `split_divider_ratio`
+    #[cfg(not(feature = "no-float"))]
+    fn plan_divider_ratio(
+        &mut self,
+        f: Hz,
+        path: PllPath,
+    ) -> Result<(u32, u32, bool), Error<SPI, LE>> {
+        // Rounding in a direction needs the exact ratio, which the float one doesn't keep. The
+        // casts truncate, `float::trunc` may not be exact for values this large
+        let whole = f as u64;
+        match self.rounding {
+            Rounding::Down => return self.integer_divider_ratio(whole, path),
+            Rounding::Up => {
+                let ceil = whole + ((whole as Float) < f) as u64;
+                return self.integer_divider_ratio(ceil, path);
+            }
+            _ => {}
+        }
+        let st3 = self.pfd_reg()?;
+        let fpfd = self.pfd_frequency_of(&st3);
+        let n = f / (fpfd * path.divisor() as Float);
+        self.split_divider_ratio(n)
+    }
+
+    /// Computes the divider ratio for output frequency `f` at the current PFD frequency,
+    /// split into the integer part, FRAC and whether the ratio has a fractional part
+    #[cfg(feature = "no-float")]
+    fn plan_divider_ratio(
+        &mut self,
+        f: Hz,
+        path: PllPath,
+    ) -> Result<(u32, u32, bool), Error<SPI, LE>> {
+        self.integer_divider_ratio(f, path)
+    }
+
+    /// Computes the divider ratio for output frequency `f` in whole Hz with integer math,
+    /// split like `plan_divider_ratio` and rounded in the direction of `set_rounding`
+    fn integer_divider_ratio(
+        &mut self,
+        f: u64,
+        path: PllPath,
+    ) -> Result<(u32, u32, bool), Error<SPI, LE>> {
+        let st6: regs::ST6 = self.read_reg()?;
+        let (num, den) = self.pfd_ratio()?;
+        let num = num * path.divisor() as u64;
+        let (n_int, half_lsbs, exact, fractional) =
+            math::divider(f, self.ref_freq, num, den, MAX_MOD);
+        let (frac, carry) = round_frac(half_lsbs, exact, MAX_MOD, st6.dithering, self.rounding);
+        Ok(((n_int as i32 + carry) as u32, frac, fractional))
+    }
+
+    /// Computes the exact divider ratio for output frequency `f` at the current PFD frequency,
+    /// with MOD as small as the fraction allows, see `Rounding::Exact`
+    /// Returns `(n_int, frac, modu, fractional)`
+    fn exact_divider_ratio(
+        &mut self,
+        f: Hz,
+        path: PllPath,
+    ) -> Result<(u32, u32, u32, bool), Error<SPI, LE>> {
+        let inexact = ValidationError::InexactFrequency { frequency: f };
+        #[cfg(not(feature = "no-float"))]
+        let f = match f as u64 as Float == f {
+            true => f as u64,
+            false => return Err(inexact.into()),
+        };
+        let (num, den) = self.pfd_ratio()?;
+        let num = num * path.divisor() as u64;
+        // N = f * den / (ref_freq * num)
+        let (top, bottom) = (f * den, self.ref_freq * num);
+        let (frac, modu) = math::frac_mod_for(top, bottom, MAX_MOD).ok_or(inexact)?;
+        if frac as u64 * bottom != top % bottom * modu as u64 {
+            return Err(inexact.into());
+        }
+        Ok((math::n_for(top, bottom), frac, modu, frac != 0))
+    }
+
+    /// Shifts the output frequency by `delta` Hz by only changing FRAC, keeping N, MOD and R
+    /// constant. This avoids touching the N divider and recalibrating the VCO, so small steps
+    /// don't incur a full lock transient.
+    ///
+    /// Returns `Error::FullRetuneRequired` if the new frequency can't be reached without changing
+    /// N, in which case `set_output_frequency` must be used instead.
+    #[cfg(not(feature = "no-float"))]
+    pub fn retune_nearby(&mut self, delta: HzDelta) -> Result<(), Error<SPI, LE>> {
+        let mut st1: regs::ST1 = self.read_reg()?;
+        let st2: regs::ST2 = self.read_reg()?;
+        let st6: regs::ST6 = self.read_reg()?;
+        let st3 = self.pfd_reg()?;
+        let fpfd = self.pfd_frequency_of(&st3);
+        // Work only on the fractional part of the divider ratio to keep floating point precision
+        let modu = st2.modu as Float;
+        let dithering = (st6.dithering as u32) as Float;
+        let n_frac = (st1.frac as Float + dithering / 2.0) / modu;
+        let f_comp = fpfd * PllPath::of(&st1).divisor() as Float;
+        let f_comp = if st1.rf1_sel { f_comp / 2.0 } else { f_comp };
+        let n_frac = n_frac + delta / f_comp;
+        if !(0.0..1.0).contains(&n_frac) {
+            return Err(Error::FullRetuneRequired);
+        }
+        let frac = float::round((2.0 * n_frac * modu - dithering) / 2.0) as u32;
+        if frac >= st2.modu {
+            return Err(Error::FullRetuneRequired);
+        }
+        st1.frac = frac;
+        self.write_reg(&st1)?;
+        self.requested_freq = self.requested_freq.map(|f| f + delta);
+        self.programmed_freq = Some(self.get_output_frequency()?);
+        Ok(())
+    }
+
+    /// Shifts the output frequency by `delta` Hz by only changing FRAC, keeping N, MOD and R
+    /// constant. This avoids touching the N divider and recalibrating the VCO, so small steps
+    /// don't incur a full lock transient.
+    ///
+    /// Returns `Error::FullRetuneRequired` if the new frequency can't be reached without changing
+    /// N, in which case `set_output_frequency` must be used instead.
+    #[cfg(feature = "no-float")]
+    pub fn retune_nearby(&mut self, delta: HzDelta) -> Result<(), Error<SPI, LE>> {
+        let mut st1: regs::ST1 = self.read_reg()?;
+        let st2: regs::ST2 = self.read_reg()?;
+        let (num, den) = self.pfd_ratio()?;
+        let num = num * PllPath::of(&st1).divisor() as u64;
+        let den = if st1.rf1_sel { 2 * den } else { den };
+        let step = fixed::frac_step(delta, self.ref_freq, num, den, st2.modu);
+        let frac = st1.frac as i64 + step;
+        if !(0..st2.modu as i64).contains(&frac) {
+            return Err(Error::FullRetuneRequired);
+        }
+        st1.frac = frac as u32;
+        self.write_reg(&st1)?;
+        self.requested_freq = self.requested_freq.map(|f| f.saturating_add_signed(delta));
+        self.programmed_freq = Some(self.get_output_frequency()?);
+        Ok(())
+    }
+
+    /// The (min, max) output frequency in Hz accepted by `set_output_frequency` on the
+    /// fundamental RF1 path, the divided path halves both
+    /// Uses the limits of the variant detected by `init`, or of the STUW81300T before that
+    pub fn output_frequency_range(&self) -> (Hz, Hz) {
+        self.variant
+            .unwrap_or(DeviceVariant::Stuw81300T)
+            .rf1_range()
+    }
+
+    /// The frequency last passed to `set_output_frequency`, adjusted by any `retune_nearby` steps
+    /// `None` until a frequency has been set, or after a lower level setter changed the divider
+    /// ratio or PFD frequency directly
+    pub fn requested_frequency(&self) -> Option<Hz> {
+        self.requested_freq
+    }
+
+    /// The output frequency actually programmed for the last requested frequency, in Hz
+    /// This is cached when the frequency is set, so reading it doesn't touch the bus
+    pub fn programmed_frequency(&self) -> Option<Hz> {
+        self.programmed_freq
+    }
+
+    /// Difference between the programmed and requested output frequency in Hz
+    /// Worked out from the cached divider registers rather than `programmed_frequency`, so
+    /// errors well below 1 Hz, like the half LSB dithering adds, show even at GHz frequencies
+    #[cfg(not(feature = "no-float"))]
+    pub fn tuning_error(&self) -> Option<f64> {
+        // Already an f64 with the f64 feature
+        #[allow(clippy::unnecessary_cast)]
+        let requested = self.requested_freq? as f64;
+        self.programmed_freq?;
+        let (num, den) = self.output_ratio_cached()?;
+        let whole = num.checked_div(den)?;
+        // The whole Hz are exact in an f64, only the remainder is rounded
+        Some(whole as f64 - requested + (num % den) as f64 / den as f64)
+    }
+
+    /// Difference between the programmed and requested output frequency in Hz, rounded to
+    /// the nearest Hz
+    /// Worked out from the cached divider registers, see `programmed_frequency`
+    #[cfg(feature = "no-float")]
+    pub fn tuning_error(&self) -> Option<HzDelta> {
+        let requested = self.requested_freq? as i128;
+        self.programmed_freq?;
+        let (num, den) = self.output_ratio_cached()?;
+        let den = (den != 0).then_some(den as i128)?;
+        let error = num as i128 - requested * den;
+        // Round half away from zero, like the planner
+        Some(((error.abs() + den / 2) / den * error.signum()) as i64)
+    }
+
+    /// The output frequency from the cached registers as the exact ratio `num / den` in Hz
+    fn output_ratio_cached(&self) -> Option<(u128, u128)> {
+        let st0: regs::ST0 = self.cached_reg()?;
+        let st1: regs::ST1 = self.cached_reg()?;
+        let st2: regs::ST2 = self.cached_reg()?;
+        let st3: regs::ST3 = self.cached_reg()?;
+        let st6: regs::ST6 = self.cached_reg()?;
+        let (num, den) = math::pfd_ratio(st3.ref_path_sel, st3.r);
+        let num = num * PllPath::of(&st1).divisor() as u64;
+        let den = if st1.rf1_sel { 2 * den } else { den };
+        // Over a common denominator of 2*MOD, like `output_frequency_of`
+        let modu = st2.modu as u128;
+        let n = 2 * st0.n as u128 * modu + 2 * st1.frac as u128 + st6.dithering as u128;
+        Some((
+            self.ref_freq as u128 * num as u128 * n,
+            den as u128 * 2 * modu,
+        ))
+    }
+
+    /// Drift of an externally measured output frequency from the programmed one, in ppm
+    /// `measured` comes from the application, a frequency counter or an estimate from a
+    /// reference comparison. Positive when the output runs fast, `None` until a frequency has
+    /// been set
+    #[cfg(not(feature = "no-float"))]
+    pub fn expected_vs_measured(&self, measured: Hz) -> Option<Float> {
+        let programmed = self.programmed_freq?;
+        Some((measured - programmed) / programmed * 1e6)
+    }
+
+    /// Drift of an externally measured output frequency from the programmed one, in ppm
+    /// rounded to the nearest whole ppm
+    /// `measured` comes from the application, a frequency counter or an estimate from a
+    /// reference comparison. Positive when the output runs fast, `None` until a frequency has
+    /// been set
+    #[cfg(feature = "no-float")]
+    pub fn expected_vs_measured(&self, measured: Hz) -> Option<i64> {
+        let programmed = self.programmed_freq? as i128;
+        let drift = (measured as i128 - programmed) * 1_000_000;
+        // Round half away from zero, like the planner
+        let rounded = (drift.abs() + programmed / 2) / programmed;
+        Some((rounded * drift.signum()) as i64)
+    }
+
+    /// Drops the requested/programmed bookkeeping after the frequency was changed by hand
+    pub(crate) fn forget_tuning(&mut self) {
+        self.requested_freq = None;
+        self.programmed_freq = None;
+    }
+
+    /// Gets the output frequency resolution for the current PFD frequency, MOD and PLL path
+    /// Use this to check that the configuration meets a channelization requirement
+    #[cfg(not(feature = "no-float"))]
+    pub fn frequency_resolution(&mut self) -> Result<FrequencyResolution, Error<SPI, LE>> {
+        let st1: regs::ST1 = self.read_reg()?;
+        let st2: regs::ST2 = self.read_reg()?;
         let fpfd = self.get_pfd_frequency()?;
-        let mut n = f / fpfd;
-        if f > 6e9 {
-            self.set_pll_path(PllPath::Halved)?;
-            n /= 2f32;
-        } else {
-            self.set_pll_path(PllPath::Direct)?;
+        let mut step = fpfd / st2.modu as Float * PllPath::of(&st1).divisor() as Float;
+        if st1.rf1_sel {
+            step /= 2.0;
         }
-        self.set_divider_ratio(n)?;
+        Ok(FrequencyResolution {
+            step,
+            max_error: step / 2.0,
+        })
+    }
 
-        if n <= 512.0 {
-            let caldiv = (fpfd / 250e3).floor() as u32;
-            self.set_calibrator_division(caldiv)?;
-        } else {
-            panic!("Integer-only mode (N>=512) must be configured manually");
+    /// Gets the output frequency resolution for the current PFD frequency, MOD and PLL path,
+    /// rounded to the nearest Hz
+    /// Use this to check that the configuration meets a channelization requirement
+    #[cfg(feature = "no-float")]
+    pub fn frequency_resolution(&mut self) -> Result<FrequencyResolution, Error<SPI, LE>> {
+        let st1: regs::ST1 = self.read_reg()?;
+        let st2: regs::ST2 = self.read_reg()?;
+        if st2.modu == 0 {
+            return Err(ValidationError::ModOutOfRange {
+                given: 0,
+                min: 2,
+                max: MAX_MOD,
+            }
+            .into());
         }
-
-        let mut st4: regs::ST4 = self.read_reg()?;
-        match self.supply_voltage {
-            crate::SupplyVoltage::LowVoltage => st4.vcalb_mode = true,
-            crate::SupplyVoltage::HighVoltage => st4.vcalb_mode = f > 4500e6,
-        };
-        self.write_reg(&st4)?;
-
-        Ok(())
+        let (num, den) = self.pfd_ratio()?;
+        let num = num * PllPath::of(&st1).divisor() as u64;
+        let den = if st1.rf1_sel { 2 * den } else { den };
+        let den = den * st2.modu as u64;
+        Ok(FrequencyResolution {
+            step: fixed::mul_div_round(self.ref_freq, num, den),
+            max_error: fixed::mul_div_round(self.ref_freq, num, 2 * den),
+        })
     }
 
     /// Gets the PFD delay mode
@@ -327,13 +1276,18 @@ where
             0 => PfdDelayMode::NoDelay,
             1 => PfdDelayMode::VcoDivDelay,
             2 => PfdDelayMode::RefDivDelay,
-            _ => unreachable!(),
+            // 3, the field is only two bits wide
+            _ => PfdDelayMode::Reserved,
         })
     }
 
     /// Sets the PFD delay mode
     /// It is recommended to set this to `VcoDivDelay`
+    /// Returns `Error::InvalidFieldValue` for `PfdDelayMode::Reserved`
     pub fn set_pfd_delay_mode(&mut self, mode: PfdDelayMode) -> Result<(), Error<SPI, LE>> {
+        if mode == PfdDelayMode::Reserved {
+            return Err(Error::InvalidFieldValue);
+        }
         let mut st3: regs::ST3 = self.read_reg()?;
         st3.pfd_del_mode = mode as u32;
         self.write_reg(&st3)
@@ -345,9 +1299,9 @@ where
         Ok(match st0.pfd_del {
             0 => PfdDelay::Default,
             1 => PfdDelay::OneNine,
-            3 => PfdDelay::TwoFive,
-            4 => PfdDelay::ThreeZero,
-            _ => unreachable!(),
+            2 => PfdDelay::TwoFive,
+            // 3, the field is only two bits wide
+            _ => PfdDelay::ThreeZero,
         })
     }
 
@@ -361,7 +1315,13 @@ where
 
     /// Sets the charge pump scaling factor to 0..31*Imin
     pub fn set_charge_pump(&mut self, scale: u32) -> Result<(), Error<SPI, LE>> {
-        assert!((scale <= 31), "Charge pump scale must be less than 32");
+        if scale > 31 {
+            return Err(ValidationError::ChargePumpOutOfRange {
+                given: scale,
+                max: 31,
+            }
+            .into());
+        }
         let mut st0: regs::ST0 = self.read_reg()?;
         st0.cp_sel = scale;
         self.write_reg(&st0)
@@ -376,25 +1336,141 @@ where
     // VCO Settings
 
     /// Sets the VCO calibrator division factor
-    /// Must be between 0 and 511
+    /// Must be between 1 and 511
+    /// Returns `Error::CalibratorFrequencyTooHigh` if the resulting calibrator frequency
+    /// would exceed the maximum of 250 kHz for the current PFD frequency
     pub fn set_calibrator_division(&mut self, div: u32) -> Result<(), Error<SPI, LE>> {
-        assert!(div <= 511, "VCO Calibrator division must be less than 512");
+        if !(1..=511).contains(&div) {
+            return Err(ValidationError::CalibratorDivisionOutOfRange {
+                given: div,
+                min: 1,
+                max: 511,
+            }
+            .into());
+        }
+        if self.get_pfd_frequency()? > MAX_CAL_FREQ * div as Hz {
+            return Err(Error::CalibratorFrequencyTooHigh);
+        }
+        let mut st6: regs::ST6 = self.read_reg()?;
+        st6.cal_div = div;
+        self.write_reg(&st6)
+    }
+
+    /// Sets the VCO calibrator division to the smallest factor that keeps the calibrator
+    /// frequency at or below the maximum of 250 kHz for the current PFD frequency
+    /// This is done automatically whenever the reference path or divider changes
+    /// Returns `Error::CalibratorFrequencyTooHigh` if the PFD frequency is too high for any
+    /// valid division factor, and `ValidationError::RefDividerOutOfRange` while R is 0
+    pub fn update_calibrator(&mut self) -> Result<(), Error<SPI, LE>> {
+        let st3 = self.pfd_reg()?;
+        #[cfg(not(feature = "no-float"))]
+        let div = float::ceil(self.pfd_frequency_of(&st3) / MAX_CAL_FREQ) as u32;
+        #[cfg(feature = "no-float")]
+        let div = {
+            let (num, den) = math::pfd_ratio(st3.ref_path_sel, st3.r);
+            fixed::mul_div_ceil(self.ref_freq, num, den * MAX_CAL_FREQ) as u32
+        };
+        if div > 511 {
+            return Err(Error::CalibratorFrequencyTooHigh);
+        }
         let mut st6: regs::ST6 = self.read_reg()?;
         st6.cal_div = div;
         self.write_reg(&st6)
     }
 
+    /// Sets the VCO calibrator precharge delay
+    pub fn set_precharge_delay(&mut self, delay: PrechargeDelay) -> Result<(), Error<SPI, LE>> {
+        let mut st6: regs::ST6 = self.read_reg()?;
+        st6.prchg_del = delay as u32;
+        self.write_reg(&st6)
+    }
+
+    /// Gets the VCO calibrator precharge delay
+    pub fn get_precharge_delay(&mut self) -> Result<PrechargeDelay, Error<SPI, LE>> {
+        let st6: regs::ST6 = self.read_reg()?;
+        Ok(match st6.prchg_del {
+            0 => PrechargeDelay::One,
+            1 => PrechargeDelay::Two,
+            2 => PrechargeDelay::Three,
+            // 3, the field is only two bits wide
+            _ => PrechargeDelay::Four,
+        })
+    }
+
     /// Gets the current VCO calibration division
     pub fn get_calibrator_division(&mut self) -> Result<u32, Error<SPI, LE>> {
         let st6: regs::ST6 = self.read_reg()?;
         Ok(st6.cal_div)
     }
 
-    /// Gets the current VCO calibration frequency
-    pub fn get_calibrator_frequency(&mut self) -> Result<f32, Error<SPI, LE>> {
+    /// Gets the current VCO calibration frequency, 0 while the calibrator division is 0
+    pub fn get_calibrator_frequency(&mut self) -> Result<Hz, Error<SPI, LE>> {
         let st6: regs::ST6 = self.read_reg()?;
+        if st6.cal_div == 0 {
+            return Ok(hz(0));
+        }
         let fpfd = self.get_pfd_frequency()?;
-        Ok(fpfd / st6.cal_div as f32)
+        Ok(fpfd / st6.cal_div as Hz)
+    }
+
+    /// Gets every frequency inside the chip, from the reference input through the PFD and
+    /// the calibrator to the VCO, the N divider input and RF1
+    /// Meant for a configured device, the frequencies are meaningless while R or MOD is 0
+    pub fn clock_tree(&mut self) -> Result<ClockTree, Error<SPI, LE>> {
+        let st0: regs::ST0 = self.read_reg()?;
+        let st1: regs::ST1 = self.read_reg()?;
+        let st2: regs::ST2 = self.read_reg()?;
+        let st3: regs::ST3 = self.read_reg()?;
+        let st6: regs::ST6 = self.read_reg()?;
+        let pfd_freq = self.pfd_frequency_of(&st3);
+        let rf1_freq = self.output_frequency_of(&st0, &st1, &st2, &st3, &st6);
+        let vco_freq = if st1.rf1_sel {
+            rf1_freq * hz(2)
+        } else {
+            rf1_freq
+        };
+        let pll_path = PllPath::of(&st1);
+        let feedback_freq = vco_freq / hz(pll_path.divisor() as u64);
+        Ok(ClockTree {
+            ref_freq: hz(self.ref_freq),
+            reference_path: match st3.ref_path_sel {
+                0 => ReferenceClockPath::Direct,
+                1 => ReferenceClockPath::Doubled,
+                2 => ReferenceClockPath::Halved,
+                _ => ReferenceClockPath::Quartered,
+            },
+            r: st3.r,
+            pfd_freq,
+            calibrator_division: st6.cal_div,
+            calibrator_freq: (st6.cal_div != 0).then(|| pfd_freq / st6.cal_div as Hz),
+            vco_freq,
+            pll_path,
+            feedback_freq,
+            rf1_path: match st1.rf1_sel {
+                false => Rf1Path::Fundamental,
+                true => Rf1Path::Divided,
+            },
+            rf1_freq,
+        })
+    }
+
+    /// Reads back N, FRAC, MOD and the paths `get_output_frequency` works from
+    pub fn divider_state(&mut self) -> Result<DividerState, Error<SPI, LE>> {
+        let st0: regs::ST0 = self.read_reg()?;
+        let st1: regs::ST1 = self.read_reg()?;
+        let st2: regs::ST2 = self.read_reg()?;
+        let st6: regs::ST6 = self.read_reg()?;
+        Ok(DividerState {
+            n: st0.n,
+            frac: st1.frac,
+            modu: st2.modu,
+            dithering: st6.dithering,
+            pll_path: PllPath::of(&st1),
+            rf1_path: match st1.rf1_sel {
+                false => Rf1Path::Fundamental,
+                true => Rf1Path::Divided,
+            },
+        })
     }
 
     /// Set VCO amplitude
@@ -402,24 +1478,84 @@ where
     /// It is recommended for phase noise's sake to set this to the maximum allowed by the supply
     /// Of course, a lower setting here reduces the power consumption
     pub fn set_vco_amplitude(&mut self, amplitude: u32) -> Result<(), Error<SPI, LE>> {
-        match self.supply_voltage {
-            crate::SupplyVoltage::LowVoltage => assert!(
-                amplitude <= 2,
-                "Low voltage supplies must have a maximum amplitude of 2"
-            ),
-            crate::SupplyVoltage::HighVoltage => {
-                assert!(amplitude <= 7, "Amplitude has a maximum value of 7")
+        let max = max_vco_amplitude(self.supply_voltage);
+        if amplitude > max {
+            return Err(ValidationError::VcoAmplitudeOutOfRange {
+                given: amplitude,
+                max,
             }
-        };
+            .into());
+        }
         let mut st4: regs::ST4 = self.read_reg()?;
         st4.vco_amp = amplitude;
         self.write_reg(&st4)
     }
 
+    // Output settings
+
+    /// Enables or powers down the RF1 output buffer
+    pub fn set_rf1_output(&mut self, enabled: bool) -> Result<(), Error<SPI, LE>> {
+        let mut st1: regs::ST1 = self.read_reg()?;
+        st1.rf1_out_pd = !enabled;
+        self.write_reg(&st1)
+    }
+
+    /// Returns true if the RF1 output buffer is enabled
+    pub fn get_rf1_output(&mut self) -> Result<bool, Error<SPI, LE>> {
+        let st1: regs::ST1 = self.read_reg()?;
+        Ok(!st1.rf1_out_pd)
+    }
+
+    /// Selects the signal on RF1
+    /// The output frequency follows the path, `set_output_frequency` afterwards programs the
+    /// VCO for the new path
+    pub fn set_rf1_path(&mut self, path: Rf1Path) -> Result<(), Error<SPI, LE>> {
+        let mut st1: regs::ST1 = self.read_reg()?;
+        st1.rf1_sel = path == Rf1Path::Divided;
+        self.write_reg(&st1)?;
+        self.forget_tuning();
+        Ok(())
+    }
+
+    /// Gets the signal on RF1
+    pub fn get_rf1_path(&mut self) -> Result<Rf1Path, Error<SPI, LE>> {
+        let st1: regs::ST1 = self.read_reg()?;
+        Ok(match st1.rf1_sel {
+            false => Rf1Path::Fundamental,
+            true => Rf1Path::Divided,
+        })
+    }
+
+    /// Mutes or unmutes the RF output
+    /// Drives the hardware mute pin high to mute if one was attached with `with_mute_pin`,
+    /// otherwise powers down the RF1 output buffer (unmuting then enables it)
+    pub fn mute(&mut self, muted: bool) -> Result<(), Error<SPI, LE>> {
+        match self.mute.as_mut() {
+            Some(pin) => {
+                if muted {
+                    pin.set_high().map_err(|_| Error::MutePin)?;
+                } else {
+                    pin.set_low().map_err(|_| Error::MutePin)?;
+                }
+                self.mute_asserted = muted;
+                Ok(())
+            }
+            None => self.set_rf1_output(!muted),
+        }
+    }
+
     // Status stuff
 
     /// Gets the lock state of the PLL
+    /// Samples the LD pin if one is attached (see `with_ld_pin`), otherwise reads ST10
     pub fn is_locked(&mut self) -> Result<bool, Error<SPI, LE>> {
+        let active_low = self
+            .cached_reg::<regs::ST4>()
+            .is_some_and(|st4| st4.ld_activelow);
+        if let Some(ld) = self.ld.as_ref() {
+            let high = ld.is_high().map_err(|_| Error::LockDetectPin)?;
+            return Ok(high != active_low);
+        }
         let st10: regs::ST10 = self.read_reg()?;
         Ok(st10.lock_det)
     }
@@ -438,40 +1574,14 @@ where
         let st10: regs::ST10 = self.read_reg()?;
         Ok(st10.reg_dig_ocp || st10.reg_ref_ocp || st10.reg_rf_ocp || st10.reg_vco_4v5_ocp)
     }
-
-    /* // TODO Fix this
-    /// Dumps the contents of all the registers to stdout
-    pub fn dump_regs(&mut self) -> Result<(), E> {
-        let st0: regs::ST0 = self.read_reg()?;
-        let st1: regs::ST1 = self.read_reg()?;
-        let st2: regs::ST2 = self.read_reg()?;
-        let st3: regs::ST3 = self.read_reg()?;
-        let st4: regs::ST4 = self.read_reg()?;
-        let st5: regs::ST5 = self.read_reg()?;
-        let st6: regs::ST6 = self.read_reg()?;
-        let st7: regs::ST7 = self.read_reg()?;
-        let st8: regs::ST8 = self.read_reg()?;
-        let st10: regs::ST10 = self.read_reg()?;
-
-        println!("{:#?}", st0);
-        println!("{:#?}", st1);
-        println!("{:#?}", st2);
-        println!("{:#?}", st3);
-        println!("{:#?}", st4);
-        println!("{:#?}", st5);
-        println!("{:#?}", st6);
-        println!("{:#?}", st7);
-        println!("{:#?}", st8);
-        println!("{:#?}", st10);
-        Ok(())
-    }
-    */
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::mock::{MockStuw81300LE, MockStuw81300SPI};
+    use crate::mock::{
+        assert_freq_eq, initialized_tester, tester, tester_with, MockStuw81300LE, MockStuw81300SPI,
+    };
     use embedded_hal_mock as mock;
     use mock::pin::{Mock as PinMock, State as PinState, Transaction as PinTransaction};
     use mock::spi::{Mock as SpiMock, Transaction as SpiTransaction};
@@ -487,23 +1597,96 @@ mod tests {
         let spi = SpiMock::new(&spi_expectations);
         let le = PinMock::new(&pin_expectations);
 
-        STuW81300 {
+        STuW81300::new(
             spi,
             le,
-            supply_voltage: crate::SupplyVoltage::HighVoltage,
-            ref_freq: 100e6,
-            ref_type: crate::ReferenceType::SingleEnded,
-        }
+            crate::SupplyVoltage::HighVoltage,
+            crate::mock::REF_FREQ,
+            crate::ReferenceType::SingleEnded,
+        )
     }
 
-    fn mock_tester() -> STuW81300<MockStuw81300SPI, MockStuw81300LE> {
-        STuW81300 {
-            spi: MockStuw81300SPI::default(),
-            le: MockStuw81300LE::default(),
-            supply_voltage: crate::SupplyVoltage::HighVoltage,
-            ref_freq: 100e6,
-            ref_type: crate::ReferenceType::SingleEnded,
-        }
+    #[test]
+    fn exact_reference() {
+        let mut vco = tester_with(crate::SupplyVoltage::HighVoltage, 122_880_000);
+        vco.set_reference_clock_path(ReferenceClockPath::Direct)
+            .unwrap();
+        vco.set_reference_clock_divider(3).unwrap();
+        assert_eq!(vco.get_pfd_frequency().unwrap(), hz(40_960_000));
+    }
+
+    #[test]
+    fn coalesced_retune() {
+        let mut vco = tester();
+        vco.init().unwrap();
+        vco.set_reference_clock_path(ReferenceClockPath::Direct)
+            .unwrap();
+        vco.set_reference_clock_divider(2).unwrap();
+        vco.spi.take_writes();
+        vco.set_output_frequency(hz(7_000_000_000)).unwrap();
+        assert_eq!(vco.spi.take_writes(), [4, 6, 2, 1, 0]);
+        assert_freq_eq!(vco.programmed_frequency().unwrap(), hz(7_000_000_000));
+    }
+
+    #[test]
+    fn cached_frequencies() {
+        let mut vco = tester();
+        assert_eq!(vco.output_frequency_cached(), None);
+        vco.init().unwrap();
+        vco.set_reference_clock_path(ReferenceClockPath::Direct)
+            .unwrap();
+        vco.set_reference_clock_divider(2).unwrap();
+        assert_eq!(vco.pfd_frequency_cached(), Some(hz(50_000_000)));
+        vco.set_output_frequency(hz(5_000_000_000)).unwrap();
+        let f = vco.get_output_frequency().unwrap();
+        assert_eq!(vco.output_frequency_cached(), Some(f));
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-float"))]
+    fn resolution() {
+        let mut vco = tester();
+        vco.init().unwrap();
+        vco.set_reference_clock_path(ReferenceClockPath::Direct)
+            .unwrap();
+        vco.set_reference_clock_divider(2).unwrap();
+        vco.set_output_frequency(5e9).unwrap();
+        let res = vco.frequency_resolution().unwrap();
+        assert_eq!(res.step, 50e6 / MAX_MOD as Float);
+        assert_eq!(res.max_error, res.step / 2.0);
+        // The PLL sees half the VCO frequency above 6 GHz, doubling the step
+        vco.set_output_frequency(7e9).unwrap();
+        assert_eq!(vco.frequency_resolution().unwrap().step, 2.0 * res.step);
+    }
+
+    #[test]
+    fn mute_fallback() {
+        let mut vco = tester();
+        vco.set_rf1_output(true).unwrap();
+        vco.mute(true).unwrap();
+        assert!(!vco.get_rf1_output().unwrap());
+        vco.mute(false).unwrap();
+        assert!(vco.get_rf1_output().unwrap());
+    }
+
+    #[test]
+    fn mute_pin() {
+        let pin = PinMock::new(&[
+            PinTransaction::set(PinState::High),
+            PinTransaction::set(PinState::Low),
+        ]);
+        let mut vco = tester().with_mute_pin(pin);
+        vco.set_rf1_output(true).unwrap();
+        vco.mute(true).unwrap();
+        // The output buffer is left alone when the pin is used
+        assert!(vco.get_rf1_output().unwrap());
+        vco.mute(false).unwrap();
+    }
+
+    #[test]
+    fn driver_is_send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<STuW81300<MockStuw81300SPI, MockStuw81300LE>>();
     }
 
     #[test]
@@ -512,15 +1695,46 @@ mod tests {
         assert_eq!(vco.device_id().unwrap(), 0x8052);
     }
 
+    #[test]
+    fn probe() {
+        let pins = [
+            PinTransaction::set(PinState::Low),
+            PinTransaction::set(PinState::High),
+        ];
+        let (mut spi, mut le, variant) = STuW81300::probe(
+            SpiMock::new(&[SpiTransaction::transfer(
+                vec![0xd8, 0, 0, 0],
+                vec![0, 0, 0x80, 0x4B],
+            )]),
+            PinMock::new(&pins),
+        )
+        .unwrap();
+        assert_eq!(variant, DeviceVariant::Stuw81300T1);
+        spi.done();
+        le.done();
+
+        // Nothing on the bus
+        let Err((mut spi, mut le, e)) = STuW81300::probe(
+            SpiMock::new(&[SpiTransaction::transfer(vec![0xd8, 0, 0, 0], vec![0; 4])]),
+            PinMock::new(&pins),
+        ) else {
+            panic!("probed a missing chip");
+        };
+        assert!(matches!(e, Error::UnknownDevice(0)));
+        spi.done();
+        le.done();
+    }
+
     #[test]
     fn mock_device_id() {
-        let mut vco = mock_tester();
+        let mut vco = tester();
         assert_eq!(vco.device_id().unwrap(), 0x8052);
     }
 
     #[test]
+    #[cfg(not(feature = "no-float"))]
     fn complete_mock() {
-        let mut vco = mock_tester();
+        let mut vco = tester();
         vco.init().unwrap();
 
         vco.set_reference_clock_path(ReferenceClockPath::Direct)
@@ -535,17 +1749,432 @@ mod tests {
         vco.set_vco_amplitude(7).unwrap();
 
         vco.set_output_frequency(7625e6).unwrap();
-        assert_eq!(vco.get_output_frequency().unwrap(), 7625e6);
+        assert_freq_eq!(vco.get_output_frequency().unwrap(), 7625e6);
 
         vco.set_output_frequency(3151e6).unwrap();
-        assert_eq!(vco.get_output_frequency().unwrap(), 3151e6);
+        assert_freq_eq!(vco.get_output_frequency().unwrap(), 3151e6);
 
-        // 43.3 Hz of error in this case
+        // Not a whole number of FRAC steps, the synthesis error is below what f32 resolves
         vco.set_output_frequency(3150123456.7).unwrap();
-        assert_eq!(vco.get_output_frequency().unwrap(), 3150123500.0);
+        assert_freq_eq!(vco.get_output_frequency().unwrap(), 3150123456.7);
 
         vco.set_output_frequency(8e9).unwrap();
-        assert_eq!(vco.get_output_frequency().unwrap(), 8e9);
+        assert_freq_eq!(vco.get_output_frequency().unwrap(), 8e9);
         assert_eq!(vco.get_calibrator_frequency().unwrap(), 250e3);
     }
+
+    #[test]
+    #[cfg(not(feature = "no-float"))]
+    fn retune_nearby() {
+        let mut vco = tester();
+        vco.init().unwrap();
+        vco.set_reference_clock_path(ReferenceClockPath::Direct)
+            .unwrap();
+        vco.set_reference_clock_divider(2).unwrap();
+        vco.set_output_frequency(3151e6).unwrap();
+
+        let st0: regs::ST0 = vco.read_reg().unwrap();
+        vco.retune_nearby(1e6).unwrap();
+        assert_freq_eq!(vco.get_output_frequency().unwrap(), 3152e6);
+        assert_eq!(vco.read_reg::<regs::ST0>().unwrap(), st0);
+
+        // Crossing an integer boundary needs N to change
+        assert!(matches!(
+            vco.retune_nearby(100e6),
+            Err(Error::FullRetuneRequired)
+        ));
+        assert_freq_eq!(vco.get_output_frequency().unwrap(), 3152e6);
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-float"))]
+    fn tuning_bookkeeping() {
+        let mut vco = tester();
+        vco.init().unwrap();
+        vco.set_reference_clock_path(ReferenceClockPath::Direct)
+            .unwrap();
+        vco.set_reference_clock_divider(2).unwrap();
+        assert_eq!(vco.requested_frequency(), None);
+        assert_eq!(vco.tuning_error(), None);
+        assert_eq!(vco.expected_vs_measured(3e9), None);
+
+        vco.set_output_frequency(3150123456.7).unwrap();
+        assert_eq!(vco.requested_frequency(), Some(3150123456.7));
+        let programmed = vco.programmed_frequency().unwrap();
+        assert_freq_eq!(programmed, 3150123456.7);
+        // N 63 and MOD 2097151 at 50 MHz plus the half LSB of dithering. In an f32 the
+        // request is 3150123520, which the planner gives a FRAC of 5183
+        #[cfg(not(feature = "f64"))]
+        let (frac, expected) = (5183, 19272640.0 / 299593.0);
+        #[cfg(feature = "f64")]
+        let (frac, expected) = (5178, 176581383.0 / 20971510.0);
+        assert_eq!(vco.read_reg::<regs::ST1>().unwrap().frac, frac);
+        assert!((vco.tuning_error().unwrap() - expected).abs() < 1e-6);
+        assert_eq!(vco.expected_vs_measured(programmed), Some(0.0));
+        let drift = vco.expected_vs_measured(programmed * (1.0 + 2e-6)).unwrap();
+        assert!((drift - 2.0).abs() < 0.1);
+        assert!(vco.expected_vs_measured(programmed * (1.0 - 2e-6)).unwrap() < 0.0);
+
+        vco.retune_nearby(1e6).unwrap();
+        assert_eq!(vco.requested_frequency(), Some(3150123456.7 + 1e6));
+        assert_eq!(
+            vco.programmed_frequency(),
+            Some(vco.get_output_frequency().unwrap())
+        );
+
+        // Changing the divider by hand invalidates the bookkeeping
+        vco.set_frac(0).unwrap();
+        assert_eq!(vco.programmed_frequency(), None);
+    }
+
+    #[test]
+    fn calibrator_follows_pfd() {
+        let mut vco = tester();
+        vco.init().unwrap();
+        vco.set_reference_clock_path(ReferenceClockPath::Direct)
+            .unwrap();
+        vco.set_reference_clock_divider(2).unwrap();
+        assert_eq!(vco.get_calibrator_division().unwrap(), 200);
+
+        // 33.3 MHz PFD doesn't divide evenly, so round the division up
+        vco.set_reference_clock_divider(3).unwrap();
+        assert_eq!(vco.get_calibrator_division().unwrap(), 134);
+        assert!(vco.get_calibrator_frequency().unwrap() <= MAX_CAL_FREQ);
+
+        vco.set_reference_clock_path(ReferenceClockPath::Halved)
+            .unwrap();
+        assert_eq!(vco.get_calibrator_division().unwrap(), 67);
+
+        assert!(matches!(
+            vco.set_calibrator_division(10),
+            Err(Error::CalibratorFrequencyTooHigh)
+        ));
+        // 0 would divide the calibrator frequency by zero rather than be "too high"
+        assert!(matches!(
+            vco.set_calibrator_division(0),
+            Err(Error::Validation(
+                ValidationError::CalibratorDivisionOutOfRange {
+                    given: 0,
+                    min: 1,
+                    max: 511
+                }
+            ))
+        ));
+    }
+
+    #[test]
+    fn clock_tree() {
+        let mut vco = tester();
+        vco.init().unwrap();
+        vco.set_reference_clock_path(ReferenceClockPath::Halved)
+            .unwrap();
+        vco.set_reference_clock_divider(2).unwrap();
+        vco.set_output_frequency(hz(7_000_000_000)).unwrap();
+        let tree = vco.clock_tree().unwrap();
+        assert_eq!(tree.ref_freq, hz(100_000_000));
+        assert_eq!(tree.reference_path, ReferenceClockPath::Halved);
+        assert_eq!((tree.r, tree.pfd_freq), (2, hz(25_000_000)));
+        assert_eq!(tree.calibrator_division, 100);
+        assert_eq!(tree.calibrator_freq, Some(hz(250_000)));
+        // Above 6 GHz the VCO is halved for the N divider
+        assert_eq!(tree.pll_path, PllPath::Halved);
+        assert_eq!(tree.rf1_path, Rf1Path::Fundamental);
+        assert_eq!(tree.vco_freq, tree.rf1_freq);
+        assert_freq_eq!(tree.rf1_freq, hz(7_000_000_000));
+        assert_freq_eq!(tree.feedback_freq, hz(3_500_000_000));
+    }
+
+    #[test]
+    fn unconfigured_dividers() {
+        let mut vco = tester();
+        vco.init().unwrap();
+        // R is 0 after init, nothing may divide by it
+        #[cfg(feature = "no-float")]
+        assert_eq!(vco.get_pfd_frequency().unwrap(), 0);
+        vco.get_output_frequency().unwrap();
+        vco.clock_tree().unwrap();
+        for result in [
+            vco.set_output_frequency(hz(5_000_000_000)),
+            vco.update_calibrator(),
+        ] {
+            assert!(matches!(
+                result,
+                Err(Error::Validation(ValidationError::RefDividerOutOfRange {
+                    given: 0,
+                    ..
+                }))
+            ));
+        }
+        let mut st6: regs::ST6 = vco.read_reg().unwrap();
+        st6.cal_div = 0;
+        vco.write_reg(&st6).unwrap();
+        assert_eq!(vco.get_calibrator_frequency().unwrap(), hz(0));
+    }
+
+    #[test]
+    fn divider_state() {
+        let mut vco = initialized_tester();
+        vco.set_output_frequency(hz(7_025_000_000)).unwrap();
+        let state = vco.divider_state().unwrap();
+        // Halved for the N divider: 3.5125 GHz / 50 MHz = 70.25
+        assert_eq!(state.pll_path, PllPath::Halved);
+        assert_eq!(state.rf1_path, Rf1Path::Fundamental);
+        assert_eq!(state.n, 70);
+        assert!((state.frac * 4).abs_diff(state.modu) <= 4);
+        // The planner always dithers
+        assert!(state.dithering);
+    }
+
+    #[test]
+    fn pfd_delay_round_trip() {
+        let mut vco = tester();
+        for delay in [
+            PfdDelay::Default,
+            PfdDelay::OneNine,
+            PfdDelay::TwoFive,
+            PfdDelay::ThreeZero,
+        ] {
+            vco.set_pfd_delay(delay).unwrap();
+            assert_eq!(vco.get_pfd_delay().unwrap(), delay);
+        }
+        // The reserved delay mode reads back, but can't be set
+        let st3: regs::ST3 = vco.read_reg().unwrap();
+        vco.write_reg(&regs::ST3 {
+            pfd_del_mode: 3,
+            ..st3
+        })
+        .unwrap();
+        assert_eq!(vco.get_pfd_delay_mode().unwrap(), PfdDelayMode::Reserved);
+        assert!(matches!(
+            vco.set_pfd_delay_mode(PfdDelayMode::Reserved),
+            Err(Error::InvalidFieldValue)
+        ));
+    }
+
+    #[test]
+    fn precharge_delay_round_trip() {
+        let mut vco = tester();
+        for delay in [
+            PrechargeDelay::One,
+            PrechargeDelay::Two,
+            PrechargeDelay::Three,
+            PrechargeDelay::Four,
+        ] {
+            vco.set_precharge_delay(delay).unwrap();
+            assert_eq!(vco.get_precharge_delay().unwrap(), delay);
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-float"))]
+    fn out_of_range() {
+        let mut vco = tester();
+        vco.init().unwrap();
+        vco.set_reference_clock_path(ReferenceClockPath::Direct)
+            .unwrap();
+        vco.set_reference_clock_divider(2).unwrap();
+        for f in [2.9e9, 8.1e9] {
+            assert!(matches!(
+                vco.set_output_frequency(f),
+                Err(Error::FrequencyOutOfRange { min, max }) if min == 3e9 && max == 8e9
+            ));
+        }
+        vco.set_output_frequency(3e9).unwrap();
+        vco.set_output_frequency(8e9).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "no-float")]
+    fn integer_planning() {
+        let mut vco = tester();
+        vco.init().unwrap();
+        vco.set_reference_clock_path(ReferenceClockPath::Direct)
+            .unwrap();
+        vco.set_reference_clock_divider(2).unwrap();
+        assert_eq!(vco.get_pfd_frequency().unwrap(), 50_000_000);
+
+        // An integer ratio is off by the half LSB dithering adds
+        vco.set_output_frequency(5_000_000_000).unwrap();
+        assert_eq!(vco.get_output_frequency().unwrap(), 5_000_000_012);
+        assert_eq!(vco.tuning_error(), Some(12));
+        assert_eq!(vco.expected_vs_measured(5_000_000_012), Some(0));
+        assert_eq!(vco.expected_vs_measured(5_000_010_012), Some(2));
+        assert_eq!(vco.expected_vs_measured(4_999_982_499), Some(-4));
+
+        vco.set_output_frequency(3_150_123_457).unwrap();
+        assert!(vco.tuning_error().unwrap().abs() <= 12);
+        vco.set_output_frequency(7_625_000_000).unwrap();
+        assert_freq_eq!(vco.get_output_frequency().unwrap(), 7_625_000_000);
+
+        let res = vco.frequency_resolution().unwrap();
+        assert_eq!((res.step, res.max_error), (48, 24));
+
+        vco.set_output_frequency(3_151_000_000).unwrap();
+        vco.retune_nearby(-1_000_000).unwrap();
+        assert_eq!(vco.requested_frequency(), Some(3_150_000_000));
+        assert_freq_eq!(vco.get_output_frequency().unwrap(), 3_150_000_000);
+        assert!(matches!(
+            vco.retune_nearby(100_000_000),
+            Err(Error::FullRetuneRequired)
+        ));
+
+        assert!(matches!(
+            vco.set_output_frequency(8_100_000_000),
+            Err(Error::FrequencyOutOfRange {
+                min: 3_000_000_000,
+                max: 8_000_000_000
+            })
+        ));
+    }
+
+    #[test]
+    fn frac_rounding() {
+        // FRAC + DITHERING/2 is the closest to half_lsbs / 2
+        assert_eq!(nearest_frac(0, MAX_MOD, true), 0);
+        assert_eq!(nearest_frac(1, MAX_MOD, true), 0);
+        assert_eq!(nearest_frac(2, MAX_MOD, true), 1);
+        assert_eq!(nearest_frac(2, MAX_MOD, false), 1);
+        assert_eq!(nearest_frac(3, MAX_MOD, false), 2);
+        assert_eq!(
+            nearest_frac(2 * MAX_MOD as u64 - 1, MAX_MOD, false),
+            MAX_MOD - 1
+        );
+    }
+
+    #[test]
+    fn legal_reference_paths() {
+        let mut vco = tester();
+        let legal = vco.legal_reference_paths();
+        assert!(legal.iter().eq([
+            ReferenceClockPath::Direct,
+            ReferenceClockPath::Halved,
+            ReferenceClockPath::Quartered
+        ]));
+        assert!(matches!(
+            vco.set_reference_clock_path(ReferenceClockPath::Doubled),
+            Err(Error::Validation(ValidationError::ReferencePathUnsupported {
+                path: ReferenceClockPath::Doubled,
+                rule: ReferencePathRule::NoDoubling,
+                legal: error_legal,
+                ..
+            })) if error_legal == legal
+        ));
+
+        // 20 MHz could be doubled, but not as a differential reference
+        let vco = STuW81300::new_hz(
+            MockStuw81300SPI::default(),
+            MockStuw81300LE::default(),
+            crate::SupplyVoltage::HighVoltage,
+            20_000_000,
+            crate::ReferenceType::Differential,
+        );
+        assert_eq!(
+            vco.reference_path_rule(ReferenceClockPath::Doubled),
+            Some(ReferencePathRule::NoDifferentialDoubling)
+        );
+        assert!(!vco
+            .legal_reference_paths()
+            .contains(ReferenceClockPath::Doubled));
+    }
+
+    #[test]
+    fn divider_ratio_parts() {
+        let mut vco = tester();
+        vco.init().unwrap();
+        vco.set_dithering(false).unwrap();
+        vco.set_divider_ratio_parts(100, 1, 4).unwrap();
+        assert_eq!(vco.read_reg::<regs::ST0>().unwrap().n, 100);
+        assert_eq!(vco.read_reg::<regs::ST1>().unwrap().frac, 1);
+        assert_eq!(vco.read_reg::<regs::ST2>().unwrap().modu, 4);
+        // Integer ratios up to the DSM limit are fine, fractional ones aren't
+        vco.set_divider_ratio_parts(507, 0, 4).unwrap();
+        assert!(matches!(
+            vco.set_divider_ratio_parts(507, 1, 4),
+            Err(Error::Validation(ValidationError::NOutOfRange {
+                given: 507,
+                min: 27,
+                max: 507
+            }))
+        ));
+        assert!(matches!(
+            vco.set_divider_ratio_parts(100, 4, 4),
+            Err(Error::Validation(ValidationError::FracOutOfRange {
+                given: 4,
+                max: 3
+            }))
+        ));
+    }
+
+    #[test]
+    fn set_n() {
+        let mut vco = tester();
+        vco.init().unwrap();
+        vco.set_n(100).unwrap();
+        assert_eq!(vco.read_reg::<regs::ST0>().unwrap().n, 100);
+        // FRAC is 0, so N may go past the DSM limits in integer mode
+        vco.set_n(MAX_N_INT).unwrap();
+        assert_eq!(vco.read_reg::<regs::ST0>().unwrap().n, MAX_N_INT);
+        vco.set_mod(4).unwrap();
+        vco.set_frac(1).unwrap();
+        assert!(matches!(
+            vco.set_n(600),
+            Err(Error::Validation(ValidationError::FractionalIntegerMode {
+                n: 600
+            }))
+        ));
+    }
+
+    #[test]
+    fn set_frac_mod() {
+        let mut vco = tester();
+        vco.init().unwrap();
+        vco.spi.take_writes();
+        // FRAC above the current MOD of 0 is fine when both change together
+        vco.set_frac_mod(7, 10).unwrap();
+        assert_eq!(vco.spi.take_writes(), [2, 1]);
+        assert_eq!(vco.read_reg::<regs::ST1>().unwrap().frac, 7);
+        assert_eq!(vco.read_reg::<regs::ST2>().unwrap().modu, 10);
+        assert!(matches!(
+            vco.set_frac_mod(10, 10),
+            Err(Error::Validation(ValidationError::FracOutOfRange {
+                given: 10,
+                max: 9
+            }))
+        ));
+        assert!(vco.set_frac_mod(0, 1).is_err());
+        assert!(vco.spi.take_writes().is_empty());
+    }
+
+    #[test]
+    fn rf1_path() {
+        let mut vco = tester();
+        vco.init().unwrap();
+        vco.set_reference_clock_path(ReferenceClockPath::Direct)
+            .unwrap();
+        vco.set_reference_clock_divider(2).unwrap();
+        assert_eq!(vco.get_rf1_path().unwrap(), Rf1Path::Fundamental);
+        vco.set_output_frequency(hz(5_000_000_000)).unwrap();
+        vco.set_rf1_path(Rf1Path::Divided).unwrap();
+        assert_eq!(vco.get_rf1_path().unwrap(), Rf1Path::Divided);
+        assert_freq_eq!(vco.get_output_frequency().unwrap(), hz(2_500_000_000));
+
+        // The VCO is programmed for twice the requested frequency
+        vco.set_output_frequency(hz(2_000_000_000)).unwrap();
+        assert_freq_eq!(vco.get_output_frequency().unwrap(), hz(2_000_000_000));
+        assert!(matches!(
+            vco.set_output_frequency(hz(5_000_000_000)),
+            Err(Error::FrequencyOutOfRange { .. })
+        ));
+        assert_eq!(
+            vco.get_frequency(Port::Rf1).unwrap(),
+            vco.get_output_frequency().unwrap()
+        );
+    }
+
+    #[test]
+    fn unknown_device() {
+        let mut vco = tester();
+        vco.spi.write(11, 0x1234);
+        assert!(matches!(vco.init(), Err(Error::UnknownDevice(0x1234))));
+    }
 }
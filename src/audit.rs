@@ -0,0 +1,203 @@
+//! Checks of the shadow registers against the device
+//!
+//! The driver keeps a shadow copy of every writable register it sends or reads, which cached
+//! reads, deferred writes and `WriteOnly` mode answer from. Nothing tells the driver when the
+//! device's registers change behind its back, e.g. after an ESD event, a brown-out reset or
+//! a write from another SPI master, so `audit_cache` reads them back and compares.
+//!
+//! `scrub` goes a step further for deployments exposed to single event upsets: called from a
+//! timer, it writes the shadow copy back to the device, so a flipped bit lasts one period at
+//! most. N is only written when it has to be, since that restarts the VCO calibration.
+use crate::error::Error;
+use crate::logging::warning;
+use crate::order::WRITE_ORDER;
+use crate::registers::RegisterAddr;
+use crate::{Interface, STuW81300};
+use embedded_hal as hal;
+use hal::blocking::spi::Transfer;
+use hal::digital::v2::OutputPin;
+
+/// A register whose contents on the device differ from the shadow copy
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RegisterMismatch {
+    /// The register
+    pub addr: RegisterAddr,
+    /// Contents of the shadow copy
+    pub expected: u32,
+    /// Contents read from the device
+    pub actual: u32,
+}
+
+impl RegisterMismatch {
+    /// Iterates over `(name, expected, actual)` for each field that differs
+    /// Differences in bits outside the modeled fields only show in `expected ^ actual`
+    pub fn fields(&self) -> impl Iterator<Item = (&'static str, u32, u32)> + '_ {
+        self.addr.fields().iter().filter_map(move |field| {
+            let value = |raw: u32| (raw >> field.start) & (u32::MAX >> (32 - field.size));
+            let (expected, actual) = (value(self.expected), value(self.actual));
+            (expected != actual).then_some((field.name, expected, actual))
+        })
+    }
+}
+
+/// Outcome of `audit_cache`, indexed by address
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CacheAudit {
+    /// The registers that were compared, bit n set for STn
+    pub checked: u16,
+    /// The mismatch found in each of ST0 to ST8
+    pub mismatches: [Option<RegisterMismatch>; 9],
+}
+
+impl CacheAudit {
+    /// Returns true if every compared register matched its shadow copy
+    pub fn is_consistent(&self) -> bool {
+        self.mismatches.iter().all(Option::is_none)
+    }
+
+    /// Iterates over the registers that didn't match
+    pub fn iter(&self) -> impl Iterator<Item = &RegisterMismatch> {
+        self.mismatches.iter().flatten()
+    }
+}
+
+impl<SPI, LE, PD, MUTE, D, LD> STuW81300<SPI, LE, PD, MUTE, D, LD>
+where
+    SPI: Transfer<u8>,
+    LE: OutputPin,
+{
+    /// Reads ST0 to ST8 from the device and compares them with the shadow copy
+    /// Registers without a shadow value, and ones with deferred writes that haven't been
+    /// flushed, are skipped. The shadow keeps the driver's values, so a mismatch can be put
+    /// right by writing them again
+    /// Returns `Error::Unreadable` in the `WriteOnly` interface mode, which has nothing to
+    /// compare against
+    pub fn audit_cache(&mut self) -> Result<CacheAudit, Error<SPI, LE>> {
+        let mut audit = CacheAudit::default();
+        for (addr, mismatch) in RegisterAddr::iter().zip(audit.mismatches.iter_mut()) {
+            let Some(expected) = self.shadow[addr as usize] else {
+                continue;
+            };
+            if self.is_dirty(addr) {
+                continue;
+            }
+            let actual = self.read_device(addr)?;
+            audit.checked |= 1 << addr as u16;
+            if actual != expected {
+                warning!(
+                    "{} is {:#09X}, expected {:#09X}",
+                    addr.name(),
+                    actual,
+                    expected
+                );
+                *mismatch = Some(RegisterMismatch {
+                    addr,
+                    expected,
+                    actual,
+                });
+            }
+        }
+        Ok(audit)
+    }
+
+    /// Writes ST1 to ST8 again from the shadow copy, in datasheet order
+    /// ST0 is written last if `audit_cache` finds it, or one of the double buffered ST1 to ST3
+    /// whose changes wait for it, didn't match. Registers without a shadow value or with
+    /// deferred writes pending are left alone, as is ST9
+    /// Returns the audit, `None` in the `WriteOnly` interface mode where there is nothing to
+    /// audit and ST0 is never written
+    pub fn scrub(&mut self) -> Result<Option<CacheAudit>, Error<SPI, LE>> {
+        let audit = match self.interface {
+            Interface::FourWire => Some(self.audit_cache()?),
+            Interface::WriteOnly { .. } => None,
+        };
+        let divider_upset =
+            audit.is_some_and(|audit| audit.mismatches[..4].iter().any(Option::is_some));
+        for addr in WRITE_ORDER {
+            if addr == RegisterAddr::ST9 || (addr == RegisterAddr::ST0 && !divider_upset) {
+                continue;
+            }
+            if let (Some(data), false) = (self.shadow[addr as usize], self.is_dirty(addr)) {
+                self.write_device(addr, data)?;
+            }
+        }
+        Ok(audit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::float::hz;
+    use crate::mock::initialized_tester;
+    use crate::registers::{self as regs, Register};
+    use crate::{DeviceVariant, Interface};
+
+    #[test]
+    fn audit_cache() {
+        let mut vco = initialized_tester();
+        vco.set_output_frequency(hz(5_000_000_000)).unwrap();
+        // Only registers in the shadow are compared, this reads the rest
+        vco.export_image().unwrap();
+        let audit = vco.audit_cache().unwrap();
+        assert!(audit.is_consistent());
+        assert_eq!(audit.checked, 0x1FF);
+
+        // An upset flips the charge pump current
+        let expected = vco.cached_reg::<regs::ST0>().unwrap();
+        let upset = regs::ST0 {
+            cp_sel: expected.cp_sel ^ 1,
+            ..expected
+        };
+        vco.spi.write(0, upset.encode());
+        let audit = vco.audit_cache().unwrap();
+        let mismatches: Vec<_> = audit.iter().collect();
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].addr, RegisterAddr::ST0);
+        assert_eq!(
+            mismatches[0].fields().collect::<Vec<_>>(),
+            [("cp_sel", expected.cp_sel, expected.cp_sel ^ 1)]
+        );
+        assert_eq!(vco.cached_reg::<regs::ST0>(), Some(expected));
+
+        // Deferred writes aren't on the device yet
+        vco.defer_writes(true);
+        vco.set_charge_pump(3).unwrap();
+        assert!(vco.audit_cache().unwrap().checked & 1 == 0);
+
+        vco.set_interface(Interface::WriteOnly {
+            variant: DeviceVariant::Stuw81300T,
+        });
+        assert!(matches!(vco.audit_cache(), Err(Error::Unreadable(_))));
+    }
+
+    #[test]
+    fn scrub() {
+        let mut vco = initialized_tester();
+        vco.set_output_frequency(hz(5_000_000_000)).unwrap();
+        vco.export_image().unwrap();
+        let image: Vec<_> = (0..9).map(|addr| vco.spi.read(addr)).collect();
+
+        // A flipped VCO amplitude bit is put right without recalibrating
+        vco.spi.write(4, image[4] ^ (1 << 15));
+        vco.spi.take_writes();
+        let audit = vco.scrub().unwrap().unwrap();
+        assert_eq!(audit.iter().count(), 1);
+        assert_eq!(vco.spi.take_writes(), [3, 4, 5, 6, 7, 8, 2, 1]);
+        assert_eq!(vco.spi.read(4), image[4]);
+
+        // FRAC only applies with N
+        vco.spi.write(1, image[1] ^ 1);
+        vco.scrub().unwrap();
+        assert_eq!(vco.spi.take_writes(), [3, 4, 5, 6, 7, 8, 2, 1, 0]);
+        assert!((0..9).all(|addr| vco.spi.read(addr) == image[addr]));
+
+        vco.set_interface(Interface::WriteOnly {
+            variant: crate::DeviceVariant::Stuw81300T,
+        });
+        assert_eq!(vco.scrub().unwrap(), None);
+        assert_eq!(vco.spi.take_writes(), [3, 4, 5, 6, 7, 8, 2, 1]);
+    }
+}
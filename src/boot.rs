@@ -0,0 +1,162 @@
+//! Fast boot from a stored register image
+//!
+//! Bringing a device up with `init`, `apply_config` and `set_output_frequency` reads every
+//! register and works out the frequency plan on the target. A `RegisterPlan` captured once
+//! from a configured device (offline, or at a previous boot) and kept in flash replaces all
+//! of that with the ST9 initialization write and one write per register.
+use crate::error::Error;
+use crate::order::WRITE_ORDER;
+use crate::registers::{self as regs, RegisterAddr};
+use crate::{DeviceVariant, STuW81300};
+use embedded_hal as hal;
+use hal::blocking::delay::DelayUs;
+use hal::blocking::spi::Transfer;
+use hal::digital::v2::{InputPin, OutputPin};
+
+/// A complete image of the writable registers, see `init_from`
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RegisterPlan {
+    /// The variant the image was captured on, taken as is by `init_from`
+    pub variant: DeviceVariant,
+    /// Contents of ST0 to ST8, indexed by address
+    pub registers: [u32; 9],
+}
+
+impl<SPI, LE, PD, MUTE, D, LD> STuW81300<SPI, LE, PD, MUTE, D, LD>
+where
+    SPI: Transfer<u8>,
+    LE: OutputPin,
+    PD: OutputPin,
+    MUTE: OutputPin,
+    LD: InputPin,
+{
+    /// The raw contents of the writable registers ST0 to ST8, indexed by address
+    /// ST9 isn't part of the image, restoring it can't touch the test register
+    /// Registers already in the shadow aren't read again
+    pub fn export_image(&mut self) -> Result<[u32; 9], Error<SPI, LE>> {
+        let mut registers = [0; 9];
+        for (addr, register) in RegisterAddr::iter().zip(registers.iter_mut()) {
+            *register = match self.shadow[addr as usize] {
+                Some(data) => data,
+                None => self.read(addr)?,
+            };
+        }
+        Ok(registers)
+    }
+
+    /// Writes the raw contents of ST0 to ST8 from `image`, in datasheet order with N last
+    /// The words are taken as they are, without decoding them into fields, so an image from a
+    /// register dump or an EEPROM loads unchanged. Unlike `init_from`, the device has to be
+    /// initialized already
+    pub fn import_image(&mut self, image: &[u32; 9]) -> Result<(), Error<SPI, LE>> {
+        self.batched(|vco| {
+            for (addr, &data) in RegisterAddr::iter().zip(image.iter()) {
+                vco.write(addr, data)?;
+            }
+            Ok(())
+        })?;
+        self.programmed_freq = self.output_frequency_cached();
+        self.requested_freq = self.programmed_freq;
+        Ok(())
+    }
+
+    /// Captures the current settings as a `RegisterPlan`
+    /// Registers already in the shadow aren't read again, nor is the device ID after `init`
+    pub fn register_plan(&mut self) -> Result<RegisterPlan, Error<SPI, LE>> {
+        let registers = self.export_image()?;
+        let variant = match self.variant {
+            Some(variant) => variant,
+            None => {
+                let device_id = self.device_id()?;
+                DeviceVariant::from_id(device_id).ok_or(Error::UnknownDevice(device_id))?
+            }
+        };
+        Ok(RegisterPlan { variant, registers })
+    }
+}
+
+impl<SPI, LE, PD, MUTE, D, LD> STuW81300<SPI, LE, PD, MUTE, D, LD>
+where
+    SPI: Transfer<u8>,
+    LE: OutputPin,
+    PD: OutputPin,
+    MUTE: OutputPin,
+    LD: InputPin,
+    D: DelayUs<u32>,
+{
+    /// Initializes the device straight from `plan`, in place of `init` and the setters
+    /// Releases the power down pin and waits for the regulators if one is attached, then
+    /// writes ST9 followed by every register of the image in datasheet order, N last
+    /// Nothing is read, so the device ID isn't checked. The output locks like after any
+    /// ST0 write, wait for it with `is_locked` as usual
+    pub fn init_from(&mut self, plan: &RegisterPlan) -> Result<(), Error<SPI, LE>> {
+        if self.pd.is_some() {
+            self.power_up()?;
+        }
+        self.batched(|vco| {
+            for addr in WRITE_ORDER {
+                let data = match addr {
+                    RegisterAddr::ST9 => regs::ST9::INIT.into(),
+                    _ => plan.registers[addr as usize],
+                };
+                vco.write(addr, data)?;
+            }
+            Ok(())
+        })?;
+        self.variant = Some(plan.variant);
+        self.programmed_freq = self.output_frequency_cached();
+        self.requested_freq = self.programmed_freq;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::float::hz;
+    use crate::mock::{initialized_tester, tester};
+
+    #[test]
+    fn boot_from_plan() {
+        let mut configured = initialized_tester();
+        configured.set_output_frequency(hz(5_000_000_000)).unwrap();
+        let plan = configured.register_plan().unwrap();
+
+        let mut vco = tester();
+        vco.init_from(&plan).unwrap();
+        assert_eq!(vco.spi.take_writes(), [9, 3, 4, 5, 6, 7, 8, 2, 1, 0]);
+        for addr in 0..9 {
+            assert_eq!(vco.spi.read(addr), configured.spi.read(addr));
+        }
+        assert_eq!(vco.variant(), Some(DeviceVariant::Stuw81300T));
+        assert_eq!(
+            vco.programmed_frequency(),
+            configured.programmed_frequency()
+        );
+        assert_eq!(
+            vco.spi.simulated_output_hz(),
+            configured.spi.simulated_output_hz()
+        );
+    }
+
+    #[test]
+    fn image_round_trip() {
+        let mut configured = tester();
+        configured.init().unwrap();
+        configured.set_reference_clock_divider(4).unwrap();
+        configured.set_output_frequency(hz(4_200_000_000)).unwrap();
+        let image = configured.export_image().unwrap();
+
+        let mut vco = tester();
+        vco.init().unwrap();
+        vco.spi.take_writes();
+        vco.import_image(&image).unwrap();
+        assert_eq!(vco.spi.take_writes(), [3, 4, 5, 6, 7, 8, 2, 1, 0]);
+        assert_eq!(vco.export_image().unwrap(), image);
+        assert_eq!(
+            vco.programmed_frequency(),
+            configured.programmed_frequency()
+        );
+    }
+}
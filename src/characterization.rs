@@ -0,0 +1,130 @@
+//! Lock time measurements for bench characterization
+use crate::error::Error;
+use crate::{Hz, STuW81300};
+use embedded_hal as hal;
+use hal::blocking::delay::DelayUs;
+use hal::blocking::spi::Transfer;
+use hal::digital::v2::{InputPin, OutputPin};
+
+/// One row of a lock time sweep
+#[derive(Debug, Default, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LockTimeSample {
+    /// Output frequency the device was tuned to, in Hz
+    pub frequency: Hz,
+    /// Time from the end of the retune to lock in us, `None` if it didn't lock in time
+    pub lock_time_us: Option<u32>,
+}
+
+/// The `i`th of `n` frequencies spread evenly from `start` to `stop`
+#[cfg(not(feature = "no-float"))]
+fn sweep_point(start: Hz, stop: Hz, i: usize, n: usize) -> Hz {
+    if n < 2 {
+        return start;
+    }
+    start + (stop - start) * i as Hz / (n - 1) as Hz
+}
+
+/// The `i`th of `n` frequencies spread evenly from `start` to `stop`, rounded down to the Hz
+#[cfg(feature = "no-float")]
+fn sweep_point(start: Hz, stop: Hz, i: usize, n: usize) -> Hz {
+    if n < 2 {
+        return start;
+    }
+    let (i, span) = (i as u64, n as u64 - 1);
+    if stop >= start {
+        start + (stop - start) * i / span
+    } else {
+        start - (start - stop) * i / span
+    }
+}
+
+impl<SPI, LE, PD, MUTE, D, LD> STuW81300<SPI, LE, PD, MUTE, D, LD>
+where
+    SPI: Transfer<u8>,
+    LE: OutputPin,
+    PD: OutputPin,
+    MUTE: OutputPin,
+    LD: InputPin,
+    D: DelayUs<u32>,
+{
+    /// Retunes to `f` and measures the time until the PLL reports lock
+    /// The lock detector is read every `resolution_us` using the delay attached with
+    /// `with_delay`, giving up after `timeout_us`. Returns `None` on timeout
+    /// The time spent on the retune's own SPI transfers isn't included
+    pub fn measure_lock_time(
+        &mut self,
+        f: Hz,
+        resolution_us: u32,
+        timeout_us: u32,
+    ) -> Result<Option<u32>, Error<SPI, LE>> {
+        self.set_output_frequency(f)?;
+        self.poll_lock(resolution_us, timeout_us)
+    }
+
+    /// Measures the lock time at `table.len()` frequencies spread evenly from `start` to
+    /// `stop`, filling in one row per frequency, see `measure_lock_time`
+    /// Each row retunes from the previous one, so the order of the sweep affects the results
+    pub fn sweep_lock_time(
+        &mut self,
+        start: Hz,
+        stop: Hz,
+        resolution_us: u32,
+        timeout_us: u32,
+        table: &mut [LockTimeSample],
+    ) -> Result<(), Error<SPI, LE>> {
+        let n = table.len();
+        for (i, sample) in table.iter_mut().enumerate() {
+            let frequency = sweep_point(start, stop, i, n);
+            *sample = LockTimeSample {
+                frequency,
+                lock_time_us: self.measure_lock_time(frequency, resolution_us, timeout_us)?,
+            };
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::ReferenceClockPath;
+    use crate::float::hz;
+    use crate::mock::{initialized_tester, MockStuw81300LE, MockStuw81300SPI};
+    use crate::registers::{self as regs, Register};
+
+    fn direct_tester() -> STuW81300<MockStuw81300SPI, MockStuw81300LE> {
+        let mut vco = initialized_tester();
+        vco.set_reference_clock_path(ReferenceClockPath::Direct)
+            .unwrap();
+        vco
+    }
+
+    #[test]
+    fn sweep() {
+        let mut vco = direct_tester();
+        let st10 = regs::ST10 {
+            lock_det: true,
+            ..Default::default()
+        };
+        vco.spi.write(10, st10.encode());
+        let mut table = [LockTimeSample::default(); 3];
+        vco.sweep_lock_time(hz(4_000_000_000), hz(5_000_000_000), 1, 100, &mut table)
+            .unwrap();
+        let frequencies = table.map(|sample| sample.frequency);
+        assert_eq!(
+            frequencies,
+            [hz(4_000_000_000), hz(4_500_000_000), hz(5_000_000_000)]
+        );
+        assert!(table.iter().all(|sample| sample.lock_time_us == Some(0)));
+    }
+
+    #[test]
+    fn no_lock() {
+        let mut vco = direct_tester();
+        assert_eq!(
+            vco.measure_lock_time(hz(5_000_000_000), 25, 100).unwrap(),
+            None
+        );
+    }
+}
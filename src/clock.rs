@@ -0,0 +1,165 @@
+//! Deadline-based timeouts measured on a free running clock instead of a delay provider
+//!
+//! RTOS and async runtimes keep a monotonic tick counter (an RTIC monotonic, Embassy's
+//! `Instant`) that measures real time even when the caller is preempted, unlike delays
+//! that count cycles. `wait_for_lock_until` polls the lock detector against a deadline on
+//! such a clock, and `ClockDelay` turns one into the delay provider the settling waits in
+//! `power_up` and `init_from` use, so those measure real time as well.
+//!
+//! Both are still blocking waits: they spin on `now` until the deadline and never yield.
+//! Use the `embassy` module for waits that let other tasks run.
+use crate::error::Error;
+use crate::lock::LOCK_POLL_US;
+use crate::STuW81300;
+use embedded_hal as hal;
+use hal::blocking::delay::DelayUs;
+use hal::blocking::spi::Transfer;
+use hal::digital::v2::{InputPin, OutputPin};
+
+/// A free running monotonic clock
+pub trait Clock {
+    /// Tick rate of `now`, in ticks per second
+    const TICK_HZ: u32;
+
+    /// The current time in ticks, wrapping around at `u64::MAX`
+    fn now(&self) -> u64;
+}
+
+impl<C: Clock> Clock for &C {
+    const TICK_HZ: u32 = C::TICK_HZ;
+
+    fn now(&self) -> u64 {
+        C::now(self)
+    }
+}
+
+/// Microseconds between two readings of `clock`, rounded down
+fn elapsed_us<C: Clock>(start: u64, now: u64) -> u64 {
+    (now.wrapping_sub(start) as u128 * 1_000_000 / C::TICK_HZ as u128) as u64
+}
+
+/// A delay provider that waits for a deadline on a `Clock`, see `with_delay`
+/// Spins on `now` until the deadline passes, so it blocks like any other `DelayUs`
+pub struct ClockDelay<C> {
+    clock: C,
+}
+
+impl<C: Clock> ClockDelay<C> {
+    /// Wraps `clock`
+    pub fn new(clock: C) -> Self {
+        ClockDelay { clock }
+    }
+
+    /// Releases the clock
+    pub fn free(self) -> C {
+        self.clock
+    }
+}
+
+impl<C: Clock> DelayUs<u32> for ClockDelay<C> {
+    fn delay_us(&mut self, us: u32) {
+        let start = self.clock.now();
+        while elapsed_us::<C>(start, self.clock.now()) < us as u64 {}
+    }
+}
+
+impl<SPI, LE, PD, MUTE, D, LD> STuW81300<SPI, LE, PD, MUTE, D, LD>
+where
+    SPI: Transfer<u8>,
+    LE: OutputPin,
+    PD: OutputPin,
+    MUTE: OutputPin,
+    LD: InputPin,
+{
+    /// Polls the lock detector every 10 us on `clock` until the PLL locks or `timeout_us`
+    /// has passed, without the attached delay
+    /// Spins on `clock` between polls, so this blocks the caller just like `wait_for_lock`
+    /// Returns the time it took to lock as measured by `clock`, `None` on timeout
+    pub fn wait_for_lock_until<C: Clock>(
+        &mut self,
+        clock: &C,
+        timeout_us: u32,
+    ) -> Result<Option<u32>, Error<SPI, LE>> {
+        let timeout = timeout_us as u64;
+        let start = clock.now();
+        let mut next_poll = 0;
+        loop {
+            // Sample the clock first, so a lock seen on the last poll still counts
+            let elapsed = elapsed_us::<C>(start, clock.now());
+            if elapsed < next_poll.min(timeout) {
+                continue;
+            }
+            if self.is_locked()? {
+                return Ok(Some(elapsed.min(u32::MAX as u64) as u32));
+            }
+            if elapsed >= timeout {
+                return Ok(None);
+            }
+            next_poll = elapsed + LOCK_POLL_US as u64;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::tester;
+    use crate::registers::{self as regs, Register};
+    use std::cell::Cell;
+
+    /// A 32 kHz clock that advances by one tick every time it is read
+    struct TickClock(Cell<u64>);
+
+    impl Clock for TickClock {
+        const TICK_HZ: u32 = 32_768;
+
+        fn now(&self) -> u64 {
+            let now = self.0.get();
+            self.0.set(now.wrapping_add(1));
+            now
+        }
+    }
+
+    /// A 1 MHz clock that advances by one tick every time it is read
+    struct MicroClock(Cell<u64>);
+
+    impl Clock for MicroClock {
+        const TICK_HZ: u32 = 1_000_000;
+
+        fn now(&self) -> u64 {
+            let now = self.0.get();
+            self.0.set(now + 1);
+            now
+        }
+    }
+
+    #[test]
+    fn poll_interval() {
+        let mut vco = tester();
+        let clock = MicroClock(Cell::new(0));
+        assert_eq!(vco.wait_for_lock_until(&clock, 100).unwrap(), None);
+        // Polls at 1, 11, .. 91 us and a last one at the deadline, not one per clock read
+        assert_eq!(vco.spi.take_reads(), [10; 11]);
+    }
+
+    #[test]
+    fn deadlines() {
+        let mut vco = tester();
+        // Starting just before the wrap around
+        let clock = TickClock(Cell::new(u64::MAX - 2));
+        assert_eq!(vco.wait_for_lock_until(&clock, 100).unwrap(), None);
+        assert_eq!(clock.0.get(), 2);
+
+        let st10 = regs::ST10 {
+            lock_det: true,
+            ..Default::default()
+        };
+        vco.spi.write(10, st10.encode());
+        assert_eq!(vco.wait_for_lock_until(&clock, 100).unwrap(), Some(30));
+
+        let mut delay = ClockDelay::new(&clock);
+        delay.delay_us(1000);
+        // 1 ms is 32.8 ticks
+        assert_eq!(clock.0.get(), 4 + 33 + 1);
+    }
+}
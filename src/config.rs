@@ -0,0 +1,372 @@
+//! Bulk configuration of the PLL and VCO settings
+use crate::api::{DsmOrder, PfdDelay, PfdDelayMode, PrechargeDelay, ReferenceClockPath};
+use crate::error::Error;
+use crate::fast_lock::FastLock;
+use crate::lock::LockDetector;
+use crate::registers as regs;
+use crate::supply::max_vco_amplitude;
+use crate::STuW81300;
+use embedded_hal as hal;
+use hal::blocking::spi::Transfer;
+use hal::digital::v2::{InputPin, OutputPin};
+
+/// VCO calibration settings
+#[derive(Debug, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Calibration {
+    /// Recalibrate the VCO automatically whenever N is written
+    pub auto: bool,
+    /// See `set_precharge_delay`
+    pub precharge_delay: PrechargeDelay,
+    /// Compensate the calibration for the die temperature
+    pub temperature_compensation: bool,
+    /// Enables the high accuracy calibration mode
+    pub high_accuracy: bool,
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Calibration {
+            auto: true,
+            precharge_delay: PrechargeDelay::Four,
+            temperature_compensation: true,
+            high_accuracy: true,
+        }
+    }
+}
+
+/// Every setting that isn't derived from the output frequency, applied at once with `apply_config`
+/// The default is the datasheet recommended configuration for a reference of 25 to 200 MHz
+#[derive(Debug, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Config {
+    /// See `set_reference_clock_path`
+    pub reference_path: ReferenceClockPath,
+    /// See `set_reference_clock_divider`
+    pub reference_divider: u32,
+    /// See `set_dsm_order`
+    pub dsm_order: DsmOrder,
+    /// See `set_dithering`
+    pub dithering: bool,
+    /// See `set_pfd_delay`
+    pub pfd_delay: PfdDelay,
+    /// See `set_pfd_delay_mode`
+    pub pfd_delay_mode: PfdDelayMode,
+    /// See `set_charge_pump`
+    pub charge_pump: u32,
+    /// See `set_vco_amplitude`
+    /// The default of 2 is valid for both supply voltages
+    pub vco_amplitude: u32,
+    /// See `set_calibration`
+    pub calibration: Calibration,
+    /// See `set_lock_detector`
+    pub lock_detector: LockDetector,
+    /// See `set_fast_lock`
+    pub fast_lock: Option<FastLock>,
+    /// See `set_cycle_slip_reduction`, only used when `fast_lock` is `None`
+    /// Otherwise the setting in `FastLock` takes precedence
+    pub cycle_slip_reduction: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            reference_path: ReferenceClockPath::Direct,
+            reference_divider: 1,
+            dsm_order: DsmOrder::ThirdOrder,
+            dithering: true,
+            pfd_delay: PfdDelay::Default,
+            pfd_delay_mode: PfdDelayMode::VcoDivDelay,
+            charge_pump: 31,
+            vco_amplitude: 2,
+            calibration: Calibration::default(),
+            lock_detector: LockDetector::default(),
+            fast_lock: None,
+            cycle_slip_reduction: false,
+        }
+    }
+}
+
+impl Config {
+    /// The settings that differ between `old` and `new`, for switching with `apply_delta`
+    /// The cycle slip settings are included whenever fast lock is turned off
+    pub fn diff(old: &Config, new: &Config) -> ConfigDelta {
+        fn changed<T: PartialEq + Copy>(old: T, new: T) -> Option<T> {
+            (old != new).then_some(new)
+        }
+        let fast_lock_changed = old.fast_lock != new.fast_lock;
+        ConfigDelta {
+            reference_path: changed(old.reference_path, new.reference_path),
+            reference_divider: changed(old.reference_divider, new.reference_divider),
+            dsm_order: changed(old.dsm_order, new.dsm_order),
+            dithering: changed(old.dithering, new.dithering),
+            pfd_delay: changed(old.pfd_delay, new.pfd_delay),
+            pfd_delay_mode: changed(old.pfd_delay_mode, new.pfd_delay_mode),
+            charge_pump: changed(old.charge_pump, new.charge_pump),
+            vco_amplitude: changed(old.vco_amplitude, new.vco_amplitude),
+            calibration: changed(old.calibration, new.calibration),
+            lock_detector: changed(old.lock_detector, new.lock_detector),
+            fast_lock: changed(old.fast_lock, new.fast_lock),
+            cycle_slip_reduction: ((old.cycle_slip_reduction != new.cycle_slip_reduction
+                || fast_lock_changed)
+                && new.fast_lock.is_none())
+            .then_some(new.cycle_slip_reduction),
+        }
+    }
+}
+
+/// The settings that change between two `Config`s, see `Config::diff`
+/// Every field is `None` if the setting stays the same, or holds the new value
+#[derive(Debug, Default, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ConfigDelta {
+    /// See `Config::reference_path`
+    pub reference_path: Option<ReferenceClockPath>,
+    /// See `Config::reference_divider`
+    pub reference_divider: Option<u32>,
+    /// See `Config::dsm_order`
+    pub dsm_order: Option<DsmOrder>,
+    /// See `Config::dithering`
+    pub dithering: Option<bool>,
+    /// See `Config::pfd_delay`
+    pub pfd_delay: Option<PfdDelay>,
+    /// See `Config::pfd_delay_mode`
+    pub pfd_delay_mode: Option<PfdDelayMode>,
+    /// See `Config::charge_pump`
+    pub charge_pump: Option<u32>,
+    /// See `Config::vco_amplitude`
+    pub vco_amplitude: Option<u32>,
+    /// See `Config::calibration`
+    pub calibration: Option<Calibration>,
+    /// See `Config::lock_detector`
+    pub lock_detector: Option<LockDetector>,
+    /// See `Config::fast_lock`
+    pub fast_lock: Option<Option<FastLock>>,
+    /// See `Config::cycle_slip_reduction`
+    pub cycle_slip_reduction: Option<bool>,
+}
+
+impl ConfigDelta {
+    /// Whether nothing changes
+    pub fn is_empty(&self) -> bool {
+        *self == ConfigDelta::default()
+    }
+}
+
+impl<SPI, LE, PD, MUTE, D, LD> STuW81300<SPI, LE, PD, MUTE, D, LD>
+where
+    SPI: Transfer<u8>,
+    LE: OutputPin,
+    PD: OutputPin,
+    MUTE: OutputPin,
+    LD: InputPin,
+{
+    /// Applies the VCO calibration settings
+    pub fn set_calibration(&mut self, calibration: &Calibration) -> Result<(), Error<SPI, LE>> {
+        let mut st6: regs::ST6 = self.read_reg()?;
+        st6.en_autocal = calibration.auto;
+        st6.prchg_del = calibration.precharge_delay as u32;
+        st6.cal_temp_comp = calibration.temperature_compensation;
+        st6.cal_acc_en = calibration.high_accuracy;
+        self.write_reg(&st6)
+    }
+
+    /// Applies every setting in `config`
+    /// The output frequency is left for `set_output_frequency`, call it afterwards
+    /// The registers are written once each at the end, in datasheet order, and not at all if
+    /// any setting fails or the result doesn't match the supply (see `validate_against_supply`)
+    pub fn apply_config(&mut self, config: &Config) -> Result<(), Error<SPI, LE>> {
+        self.batched(|vco| {
+            vco.set_reference_clock_divider(config.reference_divider)?;
+            vco.set_reference_clock_path(config.reference_path)?;
+            vco.set_dsm_order(config.dsm_order)?;
+            vco.set_dithering(config.dithering)?;
+            vco.set_pfd_delay(config.pfd_delay)?;
+            vco.set_pfd_delay_mode(config.pfd_delay_mode)?;
+            vco.set_charge_pump(config.charge_pump)?;
+            vco.set_vco_amplitude(config.vco_amplitude)?;
+            vco.set_calibration(&config.calibration)?;
+            vco.set_lock_detector(config.lock_detector)?;
+            match config.fast_lock {
+                Some(fl) => vco.set_fast_lock(Some(fl))?,
+                None => {
+                    vco.set_fast_lock(None)?;
+                    vco.set_cycle_slip_reduction(config.cycle_slip_reduction)?;
+                }
+            }
+            vco.validate_against_supply()
+        })
+    }
+
+    /// Applies only the settings in `delta`, see `Config::diff`
+    /// Only the registers holding a changed setting are written, in datasheet order, and
+    /// nothing is written if any setting fails or the result doesn't match the supply
+    pub fn apply_delta(&mut self, delta: &ConfigDelta) -> Result<(), Error<SPI, LE>> {
+        self.batched(|vco| {
+            if let Some(divider) = delta.reference_divider {
+                vco.set_reference_clock_divider(divider)?;
+            }
+            if let Some(path) = delta.reference_path {
+                vco.set_reference_clock_path(path)?;
+            }
+            if let Some(order) = delta.dsm_order {
+                vco.set_dsm_order(order)?;
+            }
+            if let Some(dithering) = delta.dithering {
+                vco.set_dithering(dithering)?;
+            }
+            if let Some(delay) = delta.pfd_delay {
+                vco.set_pfd_delay(delay)?;
+            }
+            if let Some(mode) = delta.pfd_delay_mode {
+                vco.set_pfd_delay_mode(mode)?;
+            }
+            if let Some(scale) = delta.charge_pump {
+                vco.set_charge_pump(scale)?;
+            }
+            if let Some(amplitude) = delta.vco_amplitude {
+                vco.set_vco_amplitude(amplitude)?;
+            }
+            if let Some(calibration) = delta.calibration {
+                vco.set_calibration(&calibration)?;
+            }
+            if let Some(ld) = delta.lock_detector {
+                vco.set_lock_detector(ld)?;
+            }
+            if let Some(fast_lock) = delta.fast_lock {
+                vco.set_fast_lock(fast_lock)?;
+            }
+            if let Some(enabled) = delta.cycle_slip_reduction {
+                vco.set_cycle_slip_reduction(enabled)?;
+            }
+            vco.validate_against_supply()
+        })
+    }
+
+    /// Programs the datasheet recommended settings that don't depend on the board
+    /// Third order DSM with dithering, default PFD delay in `VcoDivDelay` mode, automatic
+    /// calibration, the default lock detector and the largest VCO amplitude the supply allows
+    /// The reference path, divider and output frequency are left alone, so a working chip
+    /// only needs `init`, this, the reference setup and `set_output_frequency`
+    pub fn apply_recommended_defaults(&mut self) -> Result<(), Error<SPI, LE>> {
+        let defaults = Config::default();
+        self.batched(|vco| {
+            vco.set_dsm_order(defaults.dsm_order)?;
+            vco.set_dithering(defaults.dithering)?;
+            vco.set_pfd_delay(defaults.pfd_delay)?;
+            vco.set_pfd_delay_mode(defaults.pfd_delay_mode)?;
+            vco.set_charge_pump(defaults.charge_pump)?;
+            vco.set_calibration(&defaults.calibration)?;
+            vco.set_lock_detector(defaults.lock_detector)?;
+            vco.set_vco_amplitude(max_vco_amplitude(vco.supply_voltage))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::float::hz;
+    use crate::mock::{assert_freq_eq, tester};
+
+    #[test]
+    fn apply() {
+        let mut vco = tester();
+        vco.init().unwrap();
+        let config = Config {
+            reference_divider: 2,
+            charge_pump: 12,
+            cycle_slip_reduction: true,
+            ..Default::default()
+        };
+        vco.apply_config(&config).unwrap();
+        assert_eq!(vco.get_pfd_frequency().unwrap(), hz(50_000_000));
+        assert_eq!(vco.get_charge_pump().unwrap(), 12);
+        assert_eq!(vco.get_calibrator_division().unwrap(), 200);
+        assert!(vco.get_cycle_slip_reduction().unwrap());
+        let st6: regs::ST6 = vco.read_reg().unwrap();
+        assert!(st6.dithering);
+        assert!(st6.en_autocal);
+        assert_eq!(vco.get_precharge_delay().unwrap(), PrechargeDelay::Four);
+
+        let fl = FastLock {
+            charge_pump: 31,
+            count: 100,
+            cycle_slip_reduction: false,
+        };
+        vco.apply_config(&Config {
+            fast_lock: Some(fl),
+            ..config
+        })
+        .unwrap();
+        assert_eq!(vco.get_fast_lock().unwrap(), Some(fl));
+        assert!(!vco.get_cycle_slip_reduction().unwrap());
+
+        vco.set_output_frequency(hz(5_000_000_000)).unwrap();
+        assert_freq_eq!(vco.get_output_frequency().unwrap(), hz(5_000_000_000));
+    }
+
+    #[test]
+    fn delta() {
+        let mock = || {
+            let mut vco = tester();
+            vco.init().unwrap();
+            vco
+        };
+        let rx = Config::default();
+        let tx = Config {
+            charge_pump: 20,
+            dsm_order: DsmOrder::FourthOrder,
+            fast_lock: Some(FastLock {
+                charge_pump: 31,
+                count: 100,
+                cycle_slip_reduction: true,
+            }),
+            ..rx
+        };
+        assert!(Config::diff(&rx, &rx).is_empty());
+        let delta = Config::diff(&rx, &tx);
+        assert_eq!(delta.charge_pump, Some(20));
+        assert_eq!(delta.pfd_delay, None);
+        assert_eq!(delta.reference_divider, None);
+
+        let mut vco = mock();
+        vco.apply_config(&rx).unwrap();
+        vco.spi.take_writes();
+        vco.apply_delta(&delta).unwrap();
+        assert_eq!(vco.spi.take_writes(), [6, 7, 0]);
+        let mut reference = mock();
+        reference.apply_config(&tx).unwrap();
+        assert_eq!(
+            vco.export_image().unwrap(),
+            reference.export_image().unwrap()
+        );
+
+        // Back to RX turns fast lock off and restores the cycle slip setting
+        let delta = Config::diff(&tx, &rx);
+        assert_eq!(delta.cycle_slip_reduction, Some(false));
+        vco.apply_delta(&delta).unwrap();
+        reference.apply_config(&rx).unwrap();
+        assert_eq!(
+            vco.export_image().unwrap(),
+            reference.export_image().unwrap()
+        );
+    }
+
+    #[test]
+    fn recommended_defaults() {
+        let mut vco = tester();
+        vco.init().unwrap();
+        vco.apply_recommended_defaults().unwrap();
+        let st4: regs::ST4 = vco.read_reg().unwrap();
+        assert_eq!(st4.vco_amp, 7);
+        assert_eq!(vco.get_lock_detector().unwrap(), LockDetector::default());
+        assert_eq!(vco.get_pfd_delay_mode().unwrap(), PfdDelayMode::VcoDivDelay);
+        let st6: regs::ST6 = vco.read_reg().unwrap();
+        assert_eq!(st6.dsm_order, DsmOrder::ThirdOrder as u32);
+        assert!(st6.en_autocal);
+        // The reference network is board specific and left untouched
+        let st3: regs::ST3 = vco.read_reg().unwrap();
+        assert_eq!(st3.r, 0);
+    }
+}
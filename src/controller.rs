@@ -0,0 +1,348 @@
+//! A local oscillator controller that keeps the device on a target frequency
+//!
+//! `Controller` owns the driver and is driven by calling `poll` periodically, e.g. from a
+//! timer task. Each call does at most one step: retune to a new target, check whether the
+//! PLL has locked, retry after a lock timeout, retune after losing lock or recalibrate on
+//! schedule. Nothing blocks, so all timing is counted in calls to `poll`.
+use crate::error::Error;
+use crate::fast_lock::FastLock;
+use crate::{Hz, NoDelay, NoPin, STuW81300};
+use embedded_hal as hal;
+use hal::blocking::spi::Transfer;
+use hal::digital::v2::{InputPin, OutputPin};
+
+/// How a `Controller` tracks its target
+#[derive(Debug, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ControllerPolicy {
+    /// Targets within this many Hz of the tuned frequency don't cause a retune
+    pub max_error: Hz,
+    /// Fast lock settings for large jumps, `None` leaves fast lock alone
+    pub fast_lock: Option<FastLock>,
+    /// Jumps of at least this many Hz use `fast_lock`, smaller ones turn it off
+    pub fast_lock_threshold: Hz,
+    /// Polls spent locked between recalibrations, `None` never recalibrates
+    /// Recalibrating reprograms the target, which reruns VCO calibration when autocalibration
+    /// is enabled
+    pub recalibration_interval: Option<u32>,
+    /// Polls to wait for lock after programming before retrying
+    pub lock_timeout: u32,
+    /// Consecutive retries before giving up and reporting `ControllerState::Fault`
+    pub max_retries: u32,
+}
+
+impl Default for ControllerPolicy {
+    /// Retunes on any change, leaves fast lock alone, never recalibrates and retries three
+    /// times with a timeout of ten polls
+    fn default() -> Self {
+        ControllerPolicy {
+            max_error: crate::float::hz(0),
+            fast_lock: None,
+            fast_lock_threshold: crate::float::hz(100_000_000),
+            recalibration_interval: None,
+            lock_timeout: 10,
+            max_retries: 3,
+        }
+    }
+}
+
+/// What the controller is doing
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ControllerState {
+    /// No target has been set
+    Idle,
+    /// The target was programmed and the PLL hasn't locked yet
+    Settling,
+    /// The PLL is locked on the target
+    Locked,
+    /// The PLL didn't lock after `max_retries` retries, cleared by a new target or
+    /// `clear_fault`
+    Fault,
+}
+
+/// Keeps a `STuW81300` on a target frequency, see the module documentation
+pub struct Controller<'a, SPI, LE, PD = NoPin, MUTE = NoPin, D = NoDelay, LD = NoPin> {
+    vco: STuW81300<SPI, LE, PD, MUTE, D, LD>,
+    policy: ControllerPolicy,
+    hops: &'a [Hz],
+    target: Option<Hz>,
+    /// The target as of the last retune
+    tuned: Option<Hz>,
+    state: ControllerState,
+    /// Polls spent in the current state
+    polls: u32,
+    retries: u32,
+    /// Whether fast lock was last turned on or off, `None` before the first retune
+    fast_lock_on: Option<bool>,
+}
+
+impl<'a, SPI, LE, PD, MUTE, D, LD> Controller<'a, SPI, LE, PD, MUTE, D, LD>
+where
+    SPI: Transfer<u8>,
+    LE: OutputPin,
+    PD: OutputPin,
+    MUTE: OutputPin,
+    LD: InputPin,
+{
+    /// Takes over an initialized and configured driver
+    pub fn new(vco: STuW81300<SPI, LE, PD, MUTE, D, LD>, policy: ControllerPolicy) -> Self {
+        Controller {
+            vco,
+            policy,
+            hops: &[],
+            target: None,
+            tuned: None,
+            state: ControllerState::Idle,
+            polls: 0,
+            retries: 0,
+            fast_lock_on: None,
+        }
+    }
+
+    /// Sets the frequencies `hop` picks from
+    pub fn set_hop_table(&mut self, hops: &'a [Hz]) {
+        self.hops = hops;
+    }
+
+    /// Sets the target to entry `index` of the hop table
+    /// Returns the new target, `None` if `index` is out of range
+    pub fn hop(&mut self, index: usize) -> Option<Hz> {
+        let f = *self.hops.get(index)?;
+        self.set_target(f);
+        Some(f)
+    }
+
+    /// Sets the output frequency to track, programmed on the next `poll`
+    pub fn set_target(&mut self, f: Hz) {
+        self.target = Some(f);
+        if self.state == ControllerState::Fault {
+            self.clear_fault();
+        }
+    }
+
+    /// The output frequency being tracked
+    pub fn target(&self) -> Option<Hz> {
+        self.target
+    }
+
+    /// What the controller is doing as of the last `poll`
+    pub fn state(&self) -> ControllerState {
+        self.state
+    }
+
+    /// Replaces the policy, it applies from the next `poll`
+    pub fn set_policy(&mut self, policy: ControllerPolicy) {
+        self.policy = policy;
+    }
+
+    /// The policy in use
+    pub fn policy(&self) -> ControllerPolicy {
+        self.policy
+    }
+
+    /// Leaves the fault state and retries the target from scratch on the next `poll`
+    pub fn clear_fault(&mut self) {
+        self.tuned = None;
+        self.retries = 0;
+        self.enter(ControllerState::Idle);
+    }
+
+    /// Does the next step towards keeping the PLL locked on the target and returns the new
+    /// state
+    /// Bus errors and rejected targets are returned as `Err` and leave the state unchanged
+    pub fn poll(&mut self) -> Result<ControllerState, Error<SPI, LE>> {
+        let target = match self.target {
+            Some(target) => target,
+            None => return Ok(self.state),
+        };
+        if self.state == ControllerState::Fault {
+            return Ok(self.state);
+        }
+        let off_target = self
+            .tuned
+            .is_none_or(|tuned| distance(tuned, target) > self.policy.max_error);
+        if off_target {
+            self.retries = 0;
+            self.retune(target)?;
+            return Ok(self.state);
+        }
+        self.polls += 1;
+        let locked = self.vco.is_locked()?;
+        match self.state {
+            ControllerState::Settling if locked => {
+                self.retries = 0;
+                self.enter(ControllerState::Locked);
+            }
+            ControllerState::Settling if self.polls > self.policy.lock_timeout => {
+                if self.retries >= self.policy.max_retries {
+                    self.enter(ControllerState::Fault);
+                } else {
+                    self.retries += 1;
+                    self.retune(target)?;
+                }
+            }
+            ControllerState::Locked if !locked => self.retune(target)?,
+            ControllerState::Locked => {
+                let due = self
+                    .policy
+                    .recalibration_interval
+                    .is_some_and(|interval| self.polls >= interval);
+                if due {
+                    self.retune(target)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(self.state)
+    }
+
+    /// The driver, for settings the controller doesn't manage
+    pub fn vco(&mut self) -> &mut STuW81300<SPI, LE, PD, MUTE, D, LD> {
+        &mut self.vco
+    }
+
+    /// Releases the driver
+    pub fn free(self) -> STuW81300<SPI, LE, PD, MUTE, D, LD> {
+        self.vco
+    }
+
+    /// Programs `target`, with fast lock set for the size of the jump
+    fn retune(&mut self, target: Hz) -> Result<(), Error<SPI, LE>> {
+        if let Some(fast_lock) = self.policy.fast_lock {
+            let jump = self
+                .vco
+                .programmed_frequency()
+                .map_or(target, |from| distance(from, target));
+            let on = jump >= self.policy.fast_lock_threshold;
+            if self.fast_lock_on != Some(on) {
+                self.vco.set_fast_lock(on.then_some(fast_lock))?;
+                self.fast_lock_on = Some(on);
+            }
+        }
+        self.vco.set_output_frequency(target)?;
+        self.tuned = Some(target);
+        self.enter(ControllerState::Settling);
+        Ok(())
+    }
+
+    fn enter(&mut self, state: ControllerState) {
+        self.state = state;
+        self.polls = 0;
+    }
+}
+
+/// The absolute difference of two frequencies
+#[cfg(not(feature = "no-float"))]
+fn distance(a: Hz, b: Hz) -> Hz {
+    if a > b {
+        a - b
+    } else {
+        b - a
+    }
+}
+
+/// The absolute difference of two frequencies
+#[cfg(feature = "no-float")]
+fn distance(a: Hz, b: Hz) -> Hz {
+    a.abs_diff(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::float::hz;
+    use crate::mock::{initialized_tester, MockStuw81300LE, MockStuw81300SPI};
+    use crate::registers::{self as regs, Register};
+
+    fn controller(
+        policy: ControllerPolicy,
+    ) -> Controller<'static, MockStuw81300SPI, MockStuw81300LE> {
+        Controller::new(initialized_tester(), policy)
+    }
+
+    fn set_lock(controller: &mut Controller<MockStuw81300SPI, MockStuw81300LE>, locked: bool) {
+        let st10 = regs::ST10 {
+            lock_det: locked,
+            ..Default::default()
+        };
+        controller.vco().spi.write(10, st10.encode());
+    }
+
+    #[test]
+    fn tracks_target() {
+        static HOPS: [Hz; 2] = [hz(5_000_000_000), hz(6_000_000_000)];
+        let mut lo = controller(ControllerPolicy {
+            max_error: hz(1_000_000),
+            fast_lock: Some(FastLock {
+                charge_pump: 31,
+                count: 100,
+                cycle_slip_reduction: false,
+            }),
+            ..Default::default()
+        });
+        assert_eq!(lo.poll().unwrap(), ControllerState::Idle);
+        lo.set_hop_table(&HOPS);
+        assert_eq!(lo.hop(2), None);
+        assert_eq!(lo.hop(0), Some(hz(5_000_000_000)));
+        assert_eq!(lo.poll().unwrap(), ControllerState::Settling);
+        // The first jump counts from 0 Hz
+        assert!(lo.vco().get_fast_lock().unwrap().is_some());
+        assert_eq!(lo.poll().unwrap(), ControllerState::Settling);
+        set_lock(&mut lo, true);
+        assert_eq!(lo.poll().unwrap(), ControllerState::Locked);
+
+        // Within max_error, nothing is written
+        lo.set_target(hz(5_000_500_000));
+        lo.vco().spi.take_writes();
+        assert_eq!(lo.poll().unwrap(), ControllerState::Locked);
+        assert!(lo.vco().spi.take_writes().is_empty());
+
+        lo.hop(1);
+        assert_eq!(lo.poll().unwrap(), ControllerState::Settling);
+        assert_eq!(lo.vco().requested_frequency(), Some(hz(6_000_000_000)));
+
+        // Losing lock retunes
+        assert_eq!(lo.poll().unwrap(), ControllerState::Locked);
+        set_lock(&mut lo, false);
+        assert_eq!(lo.poll().unwrap(), ControllerState::Settling);
+        assert_eq!(lo.vco().spi.take_writes().last(), Some(&0));
+    }
+
+    #[test]
+    fn faults_without_lock() {
+        let mut lo = controller(ControllerPolicy {
+            lock_timeout: 1,
+            max_retries: 1,
+            ..Default::default()
+        });
+        lo.set_target(hz(5_000_000_000));
+        assert_eq!(lo.poll().unwrap(), ControllerState::Settling);
+        assert_eq!(lo.poll().unwrap(), ControllerState::Settling);
+        // Timed out, retried once
+        assert_eq!(lo.poll().unwrap(), ControllerState::Settling);
+        assert_eq!(lo.poll().unwrap(), ControllerState::Settling);
+        assert_eq!(lo.poll().unwrap(), ControllerState::Fault);
+        assert_eq!(lo.poll().unwrap(), ControllerState::Fault);
+        set_lock(&mut lo, true);
+        lo.clear_fault();
+        assert_eq!(lo.poll().unwrap(), ControllerState::Settling);
+        assert_eq!(lo.poll().unwrap(), ControllerState::Locked);
+    }
+
+    #[test]
+    fn recalibrates() {
+        let mut lo = controller(ControllerPolicy {
+            recalibration_interval: Some(2),
+            ..Default::default()
+        });
+        set_lock(&mut lo, true);
+        lo.set_target(hz(5_000_000_000));
+        lo.poll().unwrap();
+        assert_eq!(lo.poll().unwrap(), ControllerState::Locked);
+        assert_eq!(lo.poll().unwrap(), ControllerState::Locked);
+        lo.vco().spi.take_writes();
+        assert_eq!(lo.poll().unwrap(), ControllerState::Settling);
+        assert_eq!(lo.vco().spi.take_writes().last(), Some(&0));
+    }
+}
@@ -0,0 +1,87 @@
+//! Debug formatting of the driver state, for fault handlers and logs
+//!
+//! Only the stored configuration and the shadow registers are printed, so formatting never
+//! touches the bus. Registers that haven't been written or read yet are left out.
+use crate::registers::{decode, RegisterAddr};
+use crate::STuW81300;
+use core::fmt;
+
+/// The shadow registers, decoded field by field
+struct Shadow<'a>(&'a [Option<u32>; 12]);
+
+impl Shadow<'_> {
+    fn decoded(&self) -> impl Iterator<Item = crate::DecodedRegister> + '_ {
+        RegisterAddr::iter().filter_map(|addr| Some(decode(addr, self.0[addr as usize]?)))
+    }
+}
+
+impl fmt::Debug for Shadow<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.decoded()).finish()
+    }
+}
+
+impl<SPI, LE, PD, MUTE, D, LD> fmt::Debug for STuW81300<SPI, LE, PD, MUTE, D, LD> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("STuW81300")
+            .field("supply_voltage", &self.supply_voltage)
+            .field("ref_freq", &self.ref_freq)
+            .field("ref_type", &self.ref_type)
+            .field("variant", &self.variant)
+            .field("interface", &self.interface)
+            .field("requested_freq", &self.requested_freq)
+            .field("programmed_freq", &self.programmed_freq)
+            .field("strict", &self.strict)
+            .field("deferred", &self.deferred)
+            .field("dirty", &format_args!("{:#05X}", self.dirty))
+            .field("registers", &Shadow(&self.shadow))
+            .finish()
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl<SPI, LE, PD, MUTE, D, LD> defmt::Format for STuW81300<SPI, LE, PD, MUTE, D, LD> {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(
+            f,
+            "STuW81300 {{ supply_voltage: {}, ref_freq: {=u64}, ref_type: {}, variant: {}, \
+             interface: {}, requested_freq: {}, programmed_freq: {}, strict: {=bool}, \
+             deferred: {=bool}, dirty: {=u16:#X}, registers: [",
+            self.supply_voltage,
+            self.ref_freq,
+            self.ref_type,
+            self.variant,
+            self.interface,
+            self.requested_freq,
+            self.programmed_freq,
+            self.strict,
+            self.deferred,
+            self.dirty,
+        );
+        for register in Shadow(&self.shadow).decoded() {
+            defmt::write!(f, " {}", register);
+        }
+        defmt::write!(f, " ] }}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mock::tester;
+
+    #[test]
+    fn register_dump() {
+        let mut vco = tester();
+        let dump = format!("{:?}", vco);
+        assert!(dump.starts_with("STuW81300 { supply_voltage: HighVoltage"));
+        assert!(dump.ends_with("registers: [] }"));
+
+        vco.set_charge_pump(12).unwrap();
+        vco.spi.take_writes();
+        let dump = format!("{:?}", vco);
+        assert!(dump.contains("registers: [ST0 { "));
+        assert!(dump.contains("cp_sel: 12"));
+        // Formatting doesn't touch the bus
+        assert!(vco.spi.take_writes().is_empty());
+    }
+}
@@ -0,0 +1,253 @@
+//! Insight into the VCO calibration result and the register contents, for debugging marginal
+//! lock and bring-up
+use crate::api::{check_divider_ratio, ReferenceClockPath};
+use crate::error::{Error, ValidationError};
+use crate::float::hz;
+use crate::math::{self, MAX_CALIBRATOR_FREQ, MAX_MOD};
+use crate::registers as regs;
+use crate::supply::check_supply;
+use crate::{Hz, STuW81300, SupplyVoltage};
+use embedded_hal as hal;
+use hal::blocking::spi::Transfer;
+use hal::digital::v2::{InputPin, OutputPin};
+
+/// Largest calibration word, the word field is 5 bits wide
+const MAX_WORD: u32 = 31;
+/// Calibration words this close to either end of a core's range count as near its edge
+const BAND_EDGE_MARGIN: u32 = 2;
+/// Number of checks `lint_configuration` runs, each reports at most one finding
+const LINT_CHECKS: usize = 7;
+
+/// The end of a VCO core's tuning range the calibration landed near
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BandEdge {
+    /// Near the lowest band of the core
+    Low,
+    /// Near the highest band of the core
+    High,
+}
+
+/// Snapshot of the VCO band selection, see `vco_band_report`
+#[derive(Debug, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct VcoBandReport {
+    /// The frequency last requested with `set_output_frequency`, if still valid
+    pub target_frequency: Option<Hz>,
+    /// The output frequency the registers program, in Hz
+    pub output_frequency: Hz,
+    /// VCO core chosen by the calibration, ST10 VCO_SEL
+    pub core: u32,
+    /// Band within the core chosen by the calibration, ST10 WORD
+    pub word: u32,
+    /// Supply voltage the driver was configured for
+    pub supply_voltage: SupplyVoltage,
+    /// VCO amplitude setting, ST4 VCO_AMP
+    pub vco_amplitude: u32,
+    /// Calibration runs in the high VCO amplitude mode, ST4 VCALB_MODE
+    pub vcalb_mode: bool,
+    /// Set if the band is close to the edge of the core's range
+    /// A drift in temperature can then push the VCO outside the core and lose lock, a
+    /// recalibration at the operating temperature (or a slightly different frequency plan)
+    /// helps
+    pub band_edge: Option<BandEdge>,
+}
+
+/// Suspicious register combinations found by `lint_configuration`
+#[derive(Debug, PartialEq, Copy, Clone, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Lints {
+    found: [Option<ValidationError>; LINT_CHECKS],
+}
+
+impl Lints {
+    /// Every finding, in the order the checks ran
+    pub fn iter(&self) -> impl Iterator<Item = &ValidationError> {
+        self.found.iter().flatten()
+    }
+
+    /// Returns true if nothing suspicious was found
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+
+    fn push(&mut self, lint: ValidationError) {
+        if let Some(slot) = self.found.iter_mut().find(|slot| slot.is_none()) {
+            *slot = Some(lint);
+        }
+    }
+}
+
+impl<SPI, LE, PD, MUTE, D, LD> STuW81300<SPI, LE, PD, MUTE, D, LD>
+where
+    SPI: Transfer<u8>,
+    LE: OutputPin,
+    PD: OutputPin,
+    MUTE: OutputPin,
+    LD: InputPin,
+{
+    /// Reads back the VCO core and band the last calibration selected, together with the
+    /// settings that influence it
+    pub fn vco_band_report(&mut self) -> Result<VcoBandReport, Error<SPI, LE>> {
+        let st4: regs::ST4 = self.read_reg()?;
+        let st10: regs::ST10 = self.read_reg()?;
+        let band_edge = if st10.word < BAND_EDGE_MARGIN {
+            Some(BandEdge::Low)
+        } else if st10.word > MAX_WORD - BAND_EDGE_MARGIN {
+            Some(BandEdge::High)
+        } else {
+            None
+        };
+        Ok(VcoBandReport {
+            target_frequency: self.requested_freq,
+            output_frequency: self.get_output_frequency()?,
+            core: st10.vco_sel,
+            word: st10.word,
+            supply_voltage: self.supply_voltage,
+            vco_amplitude: st4.vco_amp,
+            vcalb_mode: st4.vcalb_mode,
+            band_edge,
+        })
+    }
+
+    /// Scans the registers for combinations the device can't run with
+    /// Checks MOD and FRAC, N against the DSM order, R, the reference path against the
+    /// reference, the calibrator frequency and the supply-dependent settings, with the same
+    /// rules as strict mode. Meant for bring-up after raw register writes, registers are read
+    /// from the shadow where cached reads allow it
+    pub fn lint_configuration(&mut self) -> Result<Lints, Error<SPI, LE>> {
+        let st0: regs::ST0 = self.read_reg()?;
+        let st1: regs::ST1 = self.read_reg()?;
+        let st2: regs::ST2 = self.read_reg()?;
+        let st3: regs::ST3 = self.read_reg()?;
+        let st4: regs::ST4 = self.read_reg()?;
+        let st6: regs::ST6 = self.read_reg()?;
+        let mut lints = Lints::default();
+        if st2.modu < 2 {
+            lints.push(ValidationError::ModOutOfRange {
+                given: st2.modu,
+                min: 2,
+                max: MAX_MOD,
+            });
+        }
+        if st1.frac != 0 && st1.frac >= st2.modu {
+            lints.push(ValidationError::FracOutOfRange {
+                given: st1.frac,
+                max: st2.modu.saturating_sub(1),
+            });
+        }
+        if let Err(lint) = check_divider_ratio(st0.n, st1.frac != 0, st6.dsm_order) {
+            lints.push(lint);
+        }
+        if st3.r == 0 {
+            lints.push(ValidationError::RefDividerOutOfRange {
+                given: 0,
+                min: 1,
+                max: 8191,
+            });
+        }
+        let path = match st3.ref_path_sel {
+            0 => ReferenceClockPath::Direct,
+            1 => ReferenceClockPath::Doubled,
+            2 => ReferenceClockPath::Halved,
+            _ => ReferenceClockPath::Quartered,
+        };
+        if let Some(lint) = self.reference_path_error(path) {
+            lints.push(lint);
+        }
+        // Compared as ref_freq * num / (den * cal_div), a division of 0 counts as 1
+        let (num, den) = math::pfd_ratio(st3.ref_path_sel, st3.r.max(1));
+        let cal_div = st6.cal_div.max(1) as u64;
+        if self.ref_freq * num > MAX_CALIBRATOR_FREQ * den * cal_div {
+            lints.push(ValidationError::CalibratorFrequencyOutOfRange {
+                frequency: hz(self.ref_freq * num / (den * cal_div)),
+                max: hz(MAX_CALIBRATOR_FREQ),
+            });
+        }
+        if let Err(lint) = check_supply(&st4, self.supply_voltage, self.variant) {
+            lints.push(lint);
+        }
+        Ok(lints)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{initialized_tester, tester};
+    use crate::registers::Register;
+
+    #[test]
+    fn band_report() {
+        let mut vco = tester();
+        vco.init().unwrap();
+        vco.set_reference_clock_path(ReferenceClockPath::Direct)
+            .unwrap();
+        vco.set_reference_clock_divider(2).unwrap();
+        vco.set_output_frequency(hz(5_000_000_000)).unwrap();
+
+        for (word, edge) in [
+            (0, Some(BandEdge::Low)),
+            (1, Some(BandEdge::Low)),
+            (2, None),
+            (29, None),
+            (30, Some(BandEdge::High)),
+        ] {
+            let st10 = regs::ST10 {
+                vco_sel: 1,
+                word,
+                ..Default::default()
+            };
+            vco.spi.write(10, st10.encode());
+            let report = vco.vco_band_report().unwrap();
+            assert_eq!(report.band_edge, edge);
+            assert_eq!((report.core, report.word), (1, word));
+        }
+        let report = vco.vco_band_report().unwrap();
+        assert_eq!(report.target_frequency, Some(hz(5_000_000_000)));
+        assert!(report.vcalb_mode);
+        assert_eq!(report.supply_voltage, SupplyVoltage::HighVoltage);
+    }
+
+    #[test]
+    fn lint() {
+        let mut vco = initialized_tester();
+        vco.set_output_frequency(hz(5_000_000_000)).unwrap();
+        assert!(vco.lint_configuration().unwrap().is_empty());
+
+        // Raw pokes: MOD below FRAC, the doubler on a 100 MHz reference and no calibrator division
+        let st1: regs::ST1 = vco.read_reg().unwrap();
+        vco.write_reg(&regs::ST1 { frac: 20, ..st1 }).unwrap();
+        let st2: regs::ST2 = vco.read_reg().unwrap();
+        vco.write_reg(&regs::ST2 { modu: 10, ..st2 }).unwrap();
+        let st3: regs::ST3 = vco.read_reg().unwrap();
+        vco.write_reg(&regs::ST3 {
+            ref_path_sel: ReferenceClockPath::Doubled as u32,
+            ..st3
+        })
+        .unwrap();
+        let st6: regs::ST6 = vco.read_reg().unwrap();
+        vco.write_reg(&regs::ST6 { cal_div: 0, ..st6 }).unwrap();
+        let lints = vco.lint_configuration().unwrap();
+        let mut lints = lints.iter();
+        assert_eq!(
+            lints.next(),
+            Some(&ValidationError::FracOutOfRange { given: 20, max: 9 })
+        );
+        assert!(matches!(
+            lints.next(),
+            Some(ValidationError::ReferencePathUnsupported {
+                path: ReferenceClockPath::Doubled,
+                ..
+            })
+        ));
+        assert_eq!(
+            lints.next(),
+            Some(&ValidationError::CalibratorFrequencyOutOfRange {
+                frequency: hz(100_000_000),
+                max: hz(250_000),
+            })
+        );
+        assert_eq!(lints.next(), None);
+    }
+}
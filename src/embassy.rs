@@ -0,0 +1,241 @@
+//! Async helpers for Embassy tasks
+//!
+//! SPI transfers to the device are short, so the bus access itself stays blocking inside
+//! `SharedStuw81300::lock`. What these helpers make async are the waits: the regulator
+//! settling time after power up and the polling for lock, which sleep on `embassy-time`
+//! timers with the mutex released, so other tasks can use the device in the meantime.
+//!
+//! ```ignore
+//! static VCO: StaticCell<SharedStuw81300<CriticalSectionRawMutex, Spi, Le>> = StaticCell::new();
+//!
+//! #[embassy_executor::main]
+//! async fn main(spawner: Spawner) {
+//!     let p = embassy_nrf::init(Default::default());
+//!     let vco = VCO.init(SharedStuw81300::new(STuW81300::new(spi, le, supply, ref_freq, ref_type)));
+//!     vco.init().await.unwrap();
+//!     vco.lock(|vco| vco.set_reference_clock_divider(2)).unwrap();
+//!     spawner.spawn(lock_monitor(vco)).unwrap();
+//!     // Retune and wait up to 1 ms for lock
+//!     if !vco.retune(5e9, Duration::from_millis(1)).await.unwrap() {
+//!         defmt::warn!("no lock at 5 GHz");
+//!     }
+//! }
+//!
+//! #[embassy_executor::task]
+//! async fn lock_monitor(vco: &'static SharedStuw81300<CriticalSectionRawMutex, Spi, Le>) {
+//!     let mut events = vco.lock_events(Duration::from_millis(10)).unwrap();
+//!     loop {
+//!         let event = events.next().await.unwrap();
+//!         defmt::info!("{}", event);
+//!     }
+//! }
+//! ```
+use crate::error::Error;
+use crate::lock::{LockEvent, LOCK_POLL_US};
+use crate::power::POWER_UP_SETTLE_US;
+use crate::{Hz, SharedStuw81300};
+use embassy_sync::blocking_mutex::raw::RawMutex;
+use embassy_time::{Duration, Instant, Timer};
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+
+/// Lock state changes of a shared driver, see `SharedStuw81300::lock_events`
+pub struct LockEvents<'a, M, SPI, LE, PD, MUTE, D, LD>
+where
+    M: RawMutex,
+{
+    shared: &'a SharedStuw81300<M, SPI, LE, PD, MUTE, D, LD>,
+    interval: Duration,
+    locked: bool,
+}
+
+impl<M, SPI, LE, PD, MUTE, D, LD> LockEvents<'_, M, SPI, LE, PD, MUTE, D, LD>
+where
+    M: RawMutex,
+    SPI: Transfer<u8>,
+    LE: OutputPin,
+    PD: OutputPin,
+    MUTE: OutputPin,
+    LD: InputPin,
+{
+    /// Waits for the next change of the lock state
+    /// Changes shorter than the polling interval can be missed, two in a row are never
+    /// reported the same way
+    pub async fn next(&mut self) -> Result<LockEvent, Error<SPI, LE>> {
+        self.locked = self
+            .shared
+            .wait_for_lock_change(self.locked, self.interval)
+            .await?;
+        Ok(match self.locked {
+            true => LockEvent::Locked,
+            false => LockEvent::Unlocked,
+        })
+    }
+
+    /// The lock state as of the last event
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+}
+
+impl<M, SPI, LE, PD, MUTE, D, LD> SharedStuw81300<M, SPI, LE, PD, MUTE, D, LD>
+where
+    M: RawMutex,
+    SPI: Transfer<u8>,
+    LE: OutputPin,
+    PD: OutputPin,
+    MUTE: OutputPin,
+    LD: InputPin,
+{
+    /// Brings the device out of power down and sleeps while the regulators settle
+    /// See `STuW81300::power_up`
+    pub async fn power_up(&self) -> Result<(), Error<SPI, LE>> {
+        self.lock(|vco| vco.release_power_down())?;
+        Timer::after(Duration::from_micros(POWER_UP_SETTLE_US as u64)).await;
+        Ok(())
+    }
+
+    /// Powers the device up and initializes it, see `STuW81300::init`
+    pub async fn init(&self) -> Result<(), Error<SPI, LE>> {
+        self.power_up().await?;
+        self.lock(|vco| vco.init_powered())
+    }
+
+    /// Polls the lock detector until the PLL locks or `timeout` has passed
+    /// Returns false on timeout
+    pub async fn wait_for_lock(&self, timeout: Duration) -> Result<bool, Error<SPI, LE>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if self.lock(|vco| vco.is_locked())? {
+                return Ok(true);
+            }
+            if Instant::now() >= deadline {
+                return Ok(false);
+            }
+            Timer::after(Duration::from_micros(LOCK_POLL_US as u64)).await;
+        }
+    }
+
+    /// Sets the output frequency and waits up to `timeout` for lock
+    /// Returns false if the PLL didn't lock in time
+    pub async fn retune(&self, f: Hz, timeout: Duration) -> Result<bool, Error<SPI, LE>> {
+        self.lock(|vco| vco.set_output_frequency(f))?;
+        self.wait_for_lock(timeout).await
+    }
+
+    /// Polls the lock detector every `interval` until the lock state differs from `locked`
+    /// Returns the new state, for a lock monitor task
+    pub async fn wait_for_lock_change(
+        &self,
+        locked: bool,
+        interval: Duration,
+    ) -> Result<bool, Error<SPI, LE>> {
+        loop {
+            let now = self.lock(|vco| vco.is_locked())?;
+            if now != locked {
+                return Ok(now);
+            }
+            Timer::after(interval).await;
+        }
+    }
+
+    /// Lock and unlock transitions, polled every `interval` like `wait_for_lock_change`
+    /// Seeded with the current lock state, so the first event is the first change after this
+    /// call. The lock state comes from the LD pin if one is attached, otherwise from ST10
+    #[allow(clippy::type_complexity)]
+    pub fn lock_events(
+        &self,
+        interval: Duration,
+    ) -> Result<LockEvents<'_, M, SPI, LE, PD, MUTE, D, LD>, Error<SPI, LE>> {
+        let locked = self.lock(|vco| vco.is_locked())?;
+        Ok(LockEvents {
+            shared: self,
+            interval,
+            locked,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::float::hz;
+    use crate::mock::{tester, MockStuw81300LE, MockStuw81300SPI};
+    use crate::registers::{self as regs, Register};
+    use embassy_futures::{block_on, join::join};
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+    fn shared_tester() -> SharedStuw81300<NoopRawMutex, MockStuw81300SPI, MockStuw81300LE> {
+        SharedStuw81300::new(tester())
+    }
+
+    /// Flips the mock's lock detector after `after`, like a PLL settling
+    async fn set_locked(
+        vco: &SharedStuw81300<NoopRawMutex, MockStuw81300SPI, MockStuw81300LE>,
+        locked: bool,
+        after: Duration,
+    ) {
+        Timer::after(after).await;
+        let st10 = regs::ST10 {
+            lock_det: locked,
+            ..Default::default()
+        };
+        vco.lock(|vco| vco.spi.write(10, st10.encode()));
+    }
+
+    #[test]
+    fn init_and_retune() {
+        let vco = shared_tester();
+        block_on(async {
+            vco.init().await.unwrap();
+            assert!(vco.lock(|vco| vco.variant()).is_some());
+            vco.lock(|vco| vco.set_reference_clock_divider(2)).unwrap();
+
+            let short = Duration::from_micros(100);
+            assert!(!vco.retune(hz(5_000_000_000), short).await.unwrap());
+            let (locked, _) = join(
+                vco.retune(hz(6_000_000_000), Duration::from_secs(1)),
+                set_locked(&vco, true, short),
+            )
+            .await;
+            assert!(locked.unwrap());
+        });
+    }
+
+    #[test]
+    fn lock_monitor() {
+        let vco = shared_tester();
+        block_on(async {
+            let interval = Duration::from_micros(50);
+            let (locked, _) = join(
+                vco.wait_for_lock_change(false, interval),
+                set_locked(&vco, true, Duration::from_micros(200)),
+            )
+            .await;
+            assert!(locked.unwrap());
+            let (locked, _) = join(
+                vco.wait_for_lock_change(true, interval),
+                set_locked(&vco, false, Duration::from_micros(200)),
+            )
+            .await;
+            assert!(!locked.unwrap());
+        });
+    }
+
+    #[test]
+    fn lock_events() {
+        let vco = shared_tester();
+        block_on(async {
+            let mut events = vco.lock_events(Duration::from_micros(50)).unwrap();
+            assert!(!events.is_locked());
+            for (locked, event) in [(true, LockEvent::Locked), (false, LockEvent::Unlocked)] {
+                let (next, _) = join(
+                    events.next(),
+                    set_locked(&vco, locked, Duration::from_micros(200)),
+                )
+                .await;
+                assert_eq!(next.unwrap(), event);
+            }
+        });
+    }
+}
@@ -1,3 +1,5 @@
+use crate::registers::RegisterAddr;
+use crate::{Hz, PllPath, ReferenceClockPath, ReferencePathRule, ReferencePaths, SupplyVoltage};
 use core::fmt;
 use embedded_hal::{blocking::spi, digital::v2::OutputPin};
 
@@ -11,6 +13,307 @@ where
     Transfer(<SPI as spi::Transfer<u8>>::Error),
     /// Error during Latch Enable
     LatchEnable(<LE as OutputPin>::Error),
+    /// The requested frequency change can't be reached by only changing FRAC
+    FullRetuneRequired,
+    /// The VCO calibrator frequency would exceed its maximum of 250 kHz
+    CalibratorFrequencyTooHigh,
+    /// Attempted to write to a read-only register
+    ReadOnly,
+    /// The device reported an ID that doesn't match any known variant
+    UnknownDevice(u32),
+    /// A register field value with no defined meaning was given
+    InvalidFieldValue,
+    /// Error driving the hardware power down pin
+    PowerDownPin,
+    /// Error driving the hardware mute pin
+    MutePin,
+    /// Error reading the lock detect pin
+    LockDetectPin,
+    /// The register can't be read in the `WriteOnly` interface mode, it is a status register
+    /// or hasn't been written yet
+    Unreadable(RegisterAddr),
+    /// The requested frequency is outside the range the output can produce, in Hz
+    FrequencyOutOfRange { min: Hz, max: Hz },
+    /// The PLL didn't lock within the allowed time
+    LockTimeout,
+    /// The operation has to reach the device, but writes are deferred, see `defer_writes`
+    WritesDeferred,
+    /// A setting was rejected before anything was written
+    Validation(ValidationError),
+}
+
+/// A setting that is invalid for the device, with the value given and the limits it broke
+#[derive(Debug, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ValidationError {
+    /// The divider ratio N is outside the range of the DSM order or integer mode
+    NOutOfRange { given: u32, min: u32, max: u32 },
+    /// Divider ratios of 512 and up are integer only, but a fraction was requested
+    FractionalIntegerMode { n: u32 },
+    /// The planned divider ratio needs integer mode, which `PlanStrategy::AllowInteger` enables
+    IntegerModeRequired { n: u32 },
+    /// MOD is outside 2..=2097151
+    ModOutOfRange { given: u32, min: u32, max: u32 },
+    /// FRAC isn't less than MOD
+    FracOutOfRange { given: u32, max: u32 },
+    /// The reference divider R is outside 1..=8191
+    RefDividerOutOfRange { given: u32, min: u32, max: u32 },
+    /// The reference path can't be used with the reference frequency or type
+    /// `rule` is the rule it breaks, `legal` the paths that can be used instead
+    ReferencePathUnsupported {
+        path: ReferenceClockPath,
+        ref_freq: Hz,
+        rule: ReferencePathRule,
+        legal: ReferencePaths,
+    },
+    /// The charge pump scale is above 31
+    ChargePumpOutOfRange { given: u32, max: u32 },
+    /// The VCO calibrator division is outside 1..=511
+    CalibratorDivisionOutOfRange { given: u32, min: u32, max: u32 },
+    /// The VCO amplitude is above the maximum for the supply voltage
+    VcoAmplitudeOutOfRange { given: u32, max: u32 },
+    /// The lock detector precision is above 7
+    LockPrecisionOutOfRange { given: u32, max: u32 },
+    /// The lock detector count is above 7
+    LockCountOutOfRange { given: u32, max: u32 },
+    /// The fast lock charge pump scale is above 31
+    FastLockChargePumpOutOfRange { given: u32, max: u32 },
+    /// The fast lock count is above 8191
+    FastLockCountOutOfRange { given: u32, max: u32 },
+    /// The manually selected VCO core is above 3
+    VcoCoreOutOfRange { given: u32, max: u32 },
+    /// The manually selected VCO word is above 31
+    VcoWordOutOfRange { given: u32, max: u32 },
+    /// The VCO calibrator runs faster than 250 kHz, see `lint_configuration`
+    CalibratorFrequencyOutOfRange { frequency: Hz, max: Hz },
+    /// The N divider can't take the VCO frequency on the PLL path, in Hz
+    PllPathUnsupported { path: PllPath, vco_freq: Hz },
+    /// The frequency can't be programmed exactly, see `Rounding::Exact`
+    InexactFrequency { frequency: Hz },
+    /// ST9 is only written by `init` and `init_from`
+    InitOnlyRegister,
+    /// A numeric register field holds a value wider than its bit width, holds the field name
+    FieldOverflow { field: &'static str },
+    /// `SyncGroup::set_output_frequencies` was given a different number of frequencies than
+    /// the group has chips
+    FrequencyCountMismatch { given: usize, chips: usize },
+    /// `HopTable::hop` was given an index past the end of the table
+    HopOutOfRange { given: usize, hops: usize },
+    /// A supply-dependent field doesn't match the declared supply voltage, holds the field name
+    SupplyMismatch {
+        field: &'static str,
+        supply: SupplyVoltage,
+    },
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ValidationError::NOutOfRange { given, min, max } => {
+                write!(f, "N of {} outside {}..={}", given, min, max)
+            }
+            ValidationError::FractionalIntegerMode { n } => {
+                write!(f, "N of {} is integer only but has a fraction", n)
+            }
+            ValidationError::IntegerModeRequired { n } => {
+                write!(f, "N of {} needs manually configured integer mode", n)
+            }
+            ValidationError::ModOutOfRange { given, min, max } => {
+                write!(f, "MOD of {} outside {}..={}", given, min, max)
+            }
+            ValidationError::FracOutOfRange { given, max } => {
+                write!(f, "FRAC of {} above {}", given, max)
+            }
+            ValidationError::RefDividerOutOfRange { given, min, max } => {
+                write!(f, "R of {} outside {}..={}", given, min, max)
+            }
+            ValidationError::ReferencePathUnsupported {
+                path,
+                ref_freq,
+                rule,
+                legal,
+            } => {
+                write!(
+                    f,
+                    "{:?} reference path unsupported at {} Hz, {}, legal paths are {:?}",
+                    path, ref_freq, rule, legal
+                )
+            }
+            ValidationError::ChargePumpOutOfRange { given, max } => {
+                write!(f, "charge pump scale of {} above {}", given, max)
+            }
+            ValidationError::CalibratorDivisionOutOfRange { given, min, max } => {
+                write!(
+                    f,
+                    "calibrator division of {} outside {}..={}",
+                    given, min, max
+                )
+            }
+            ValidationError::VcoAmplitudeOutOfRange { given, max } => {
+                write!(f, "VCO amplitude of {} above {}", given, max)
+            }
+            ValidationError::LockPrecisionOutOfRange { given, max } => {
+                write!(f, "lock detector precision of {} above {}", given, max)
+            }
+            ValidationError::LockCountOutOfRange { given, max } => {
+                write!(f, "lock detector count of {} above {}", given, max)
+            }
+            ValidationError::FastLockChargePumpOutOfRange { given, max } => {
+                write!(f, "fast lock charge pump scale of {} above {}", given, max)
+            }
+            ValidationError::FastLockCountOutOfRange { given, max } => {
+                write!(f, "fast lock count of {} above {}", given, max)
+            }
+            ValidationError::VcoCoreOutOfRange { given, max } => {
+                write!(f, "VCO core of {} above {}", given, max)
+            }
+            ValidationError::VcoWordOutOfRange { given, max } => {
+                write!(f, "VCO word of {} above {}", given, max)
+            }
+            ValidationError::CalibratorFrequencyOutOfRange { frequency, max } => {
+                write!(
+                    f,
+                    "calibrator frequency of {} Hz above {} Hz",
+                    frequency, max
+                )
+            }
+            ValidationError::PllPathUnsupported { path, vco_freq } => {
+                write!(
+                    f,
+                    "{:?} PLL path unsupported at a {} Hz VCO",
+                    path, vco_freq
+                )
+            }
+            ValidationError::InexactFrequency { frequency } => {
+                write!(f, "{} Hz can't be programmed exactly", frequency)
+            }
+            ValidationError::InitOnlyRegister => {
+                write!(f, "ST9 is only written during initialization")
+            }
+            ValidationError::FieldOverflow { field } => {
+                write!(f, "{} doesn't fit in its field", field)
+            }
+            ValidationError::FrequencyCountMismatch { given, chips } => {
+                write!(f, "{} frequencies for {} chips", given, chips)
+            }
+            ValidationError::HopOutOfRange { given, hops } => {
+                write!(f, "hop {} out of a table of {}", given, hops)
+            }
+            ValidationError::SupplyMismatch { field, supply } => {
+                write!(f, "{} doesn't match the {:?} supply", field, supply)
+            }
+        }
+    }
+}
+
+impl core::error::Error for ValidationError {}
+
+impl<SPI, LE> From<ValidationError> for Error<SPI, LE>
+where
+    SPI: spi::Transfer<u8>,
+    LE: OutputPin,
+{
+    fn from(error: ValidationError) -> Self {
+        Error::Validation(error)
+    }
+}
+
+/// The kind of an `Error`, without the HAL error types
+/// Can be stored or reported without carrying the driver's generics around
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ErrorKind {
+    /// Error during SPI Transfer
+    Transfer,
+    /// Error during Latch Enable
+    LatchEnable,
+    /// The requested frequency change can't be reached by only changing FRAC
+    FullRetuneRequired,
+    /// The VCO calibrator frequency would exceed its maximum of 250 kHz
+    CalibratorFrequencyTooHigh,
+    /// Attempted to write to a read-only register
+    ReadOnly,
+    /// The device reported an ID that doesn't match any known variant
+    UnknownDevice,
+    /// A register field value with no defined meaning was given
+    InvalidFieldValue,
+    /// Error driving the hardware power down pin
+    PowerDownPin,
+    /// Error driving the hardware mute pin
+    MutePin,
+    /// Error reading the lock detect pin
+    LockDetectPin,
+    /// The register can't be read in the `WriteOnly` interface mode
+    Unreadable,
+    /// The requested frequency is outside the range the output can produce
+    FrequencyOutOfRange,
+    /// The PLL didn't lock within the allowed time
+    LockTimeout,
+    /// The operation has to reach the device, but writes are deferred
+    WritesDeferred,
+    /// A setting was rejected before anything was written
+    Validation,
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ErrorKind::Transfer => "SPI transfer failed",
+            ErrorKind::LatchEnable => "driving the latch enable pin failed",
+            ErrorKind::FullRetuneRequired => "frequency change needs a full retune",
+            ErrorKind::CalibratorFrequencyTooHigh => "VCO calibrator frequency above 250 kHz",
+            ErrorKind::ReadOnly => "register is read-only",
+            ErrorKind::UnknownDevice => "unknown device ID",
+            ErrorKind::InvalidFieldValue => "undefined register field value",
+            ErrorKind::PowerDownPin => "driving the power down pin failed",
+            ErrorKind::MutePin => "driving the mute pin failed",
+            ErrorKind::LockDetectPin => "reading the lock detect pin failed",
+            ErrorKind::Unreadable => "register can't be read without SDO",
+            ErrorKind::FrequencyOutOfRange => "frequency out of range",
+            ErrorKind::LockTimeout => "PLL didn't lock in time",
+            ErrorKind::WritesDeferred => "writes are deferred",
+            ErrorKind::Validation => "invalid setting",
+        })
+    }
+}
+
+impl core::error::Error for ErrorKind {}
+
+impl<SPI, LE> Error<SPI, LE>
+where
+    SPI: spi::Transfer<u8>,
+    LE: OutputPin,
+{
+    /// The kind of this error
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::Transfer(_) => ErrorKind::Transfer,
+            Error::LatchEnable(_) => ErrorKind::LatchEnable,
+            Error::FullRetuneRequired => ErrorKind::FullRetuneRequired,
+            Error::CalibratorFrequencyTooHigh => ErrorKind::CalibratorFrequencyTooHigh,
+            Error::ReadOnly => ErrorKind::ReadOnly,
+            Error::UnknownDevice(_) => ErrorKind::UnknownDevice,
+            Error::InvalidFieldValue => ErrorKind::InvalidFieldValue,
+            Error::PowerDownPin => ErrorKind::PowerDownPin,
+            Error::MutePin => ErrorKind::MutePin,
+            Error::LockDetectPin => ErrorKind::LockDetectPin,
+            Error::Unreadable(_) => ErrorKind::Unreadable,
+            Error::FrequencyOutOfRange { .. } => ErrorKind::FrequencyOutOfRange,
+            Error::LockTimeout => ErrorKind::LockTimeout,
+            Error::WritesDeferred => ErrorKind::WritesDeferred,
+            Error::Validation(_) => ErrorKind::Validation,
+        }
+    }
+}
+
+impl<SPI, LE> From<Error<SPI, LE>> for ErrorKind
+where
+    SPI: spi::Transfer<u8>,
+    LE: OutputPin,
+{
+    fn from(error: Error<SPI, LE>) -> Self {
+        error.kind()
+    }
 }
 
 impl<SPI, LE> fmt::Debug for Error<SPI, LE>
@@ -24,6 +327,83 @@ where
         match self {
             Error::Transfer(error) => write!(f, "Transfer({:?})", error),
             Error::LatchEnable(error) => write!(f, "LatchEnable({:?})", error),
+            Error::FullRetuneRequired => write!(f, "FullRetuneRequired"),
+            Error::CalibratorFrequencyTooHigh => write!(f, "CalibratorFrequencyTooHigh"),
+            Error::ReadOnly => write!(f, "ReadOnly"),
+            Error::UnknownDevice(id) => write!(f, "UnknownDevice({:#06X})", id),
+            Error::InvalidFieldValue => write!(f, "InvalidFieldValue"),
+            Error::PowerDownPin => write!(f, "PowerDownPin"),
+            Error::MutePin => write!(f, "MutePin"),
+            Error::LockDetectPin => write!(f, "LockDetectPin"),
+            Error::Unreadable(addr) => write!(f, "Unreadable({:?})", addr),
+            Error::FrequencyOutOfRange { min, max } => {
+                write!(f, "FrequencyOutOfRange {{ min: {}, max: {} }}", min, max)
+            }
+            Error::LockTimeout => write!(f, "LockTimeout"),
+            Error::WritesDeferred => write!(f, "WritesDeferred"),
+            Error::Validation(error) => write!(f, "Validation({:?})", error),
+        }
+    }
+}
+
+impl<SPI, LE> fmt::Display for Error<SPI, LE>
+where
+    SPI: spi::Transfer<u8>,
+    SPI::Error: fmt::Debug,
+    LE: OutputPin,
+    <LE as OutputPin>::Error: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Transfer(error) => write!(f, "{}: {:?}", self.kind(), error),
+            Error::LatchEnable(error) => write!(f, "{}: {:?}", self.kind(), error),
+            Error::UnknownDevice(id) => write!(f, "{} {:#06X}", self.kind(), id),
+            Error::Unreadable(addr) => write!(f, "{}: {}", self.kind(), addr.name()),
+            Error::FrequencyOutOfRange { min, max } => {
+                write!(f, "{}, expected {} to {} Hz", self.kind(), min, max)
+            }
+            Error::Validation(error) => write!(f, "{}: {}", self.kind(), error),
+            _ => write!(f, "{}", self.kind()),
+        }
+    }
+}
+
+impl<SPI, LE> core::error::Error for Error<SPI, LE>
+where
+    SPI: spi::Transfer<u8>,
+    SPI::Error: fmt::Debug,
+    LE: OutputPin,
+    <LE as OutputPin>::Error: fmt::Debug,
+{
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockStuw81300LE, MockStuw81300SPI};
+
+    type MockError = Error<MockStuw81300SPI, MockStuw81300LE>;
+
+    #[test]
+    fn kind() {
+        let error: MockError = Error::UnknownDevice(0x1234);
+        assert_eq!(error.kind(), ErrorKind::UnknownDevice);
+        assert_eq!(ErrorKind::from(error), ErrorKind::UnknownDevice);
+        assert_eq!(
+            MockError::UnknownDevice(0x1234).to_string(),
+            "unknown device ID 0x1234"
+        );
+        assert_eq!(MockError::ReadOnly.to_string(), "register is read-only");
+        let error: MockError = ValidationError::ModOutOfRange {
+            given: 1,
+            min: 2,
+            max: 2097151,
         }
+        .into();
+        assert_eq!(error.kind(), ErrorKind::Validation);
+        assert_eq!(
+            error.to_string(),
+            "invalid setting: MOD of 1 outside 2..=2097151"
+        );
     }
 }
@@ -0,0 +1,194 @@
+//! Fast lock and cycle slip reduction, used to speed up acquisition after large frequency jumps
+use crate::error::{Error, ValidationError};
+use crate::registers as regs;
+use crate::STuW81300;
+use embedded_hal as hal;
+use hal::blocking::spi::Transfer;
+use hal::digital::v2::{InputPin, OutputPin};
+
+/// Fast lock settings
+/// After a retune the charge pump runs at `charge_pump` for `count` PFD cycles before
+/// returning to the steady-state setting of `set_charge_pump`
+#[derive(Debug, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FastLock {
+    /// Charge pump scaling factor during fast lock, 0..31*Imin
+    pub charge_pump: u32,
+    /// Duration of the fast lock period in PFD cycles, 0..8191
+    pub count: u32,
+    /// Enables cycle slip reduction alongside fast lock
+    /// Recommended for large frequency jumps, where the phase error otherwise wraps around
+    pub cycle_slip_reduction: bool,
+}
+
+/// Loop gain during and after acquisition, see `acquisition_profile`
+#[derive(Debug, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CpBoost {
+    /// Charge pump scaling factor once locked, 0..31*Imin
+    pub steady_state: u32,
+    /// Raised charge pump scaling factor right after a retune, 0..31*Imin
+    pub boost: u32,
+    /// Duration of the boost in PFD cycles, 0..8191
+    pub count: u32,
+    /// Enables cycle slip reduction during the boost
+    pub cycle_slip_reduction: bool,
+}
+
+impl<SPI, LE, PD, MUTE, D, LD> STuW81300<SPI, LE, PD, MUTE, D, LD>
+where
+    SPI: Transfer<u8>,
+    LE: OutputPin,
+    PD: OutputPin,
+    MUTE: OutputPin,
+    LD: InputPin,
+{
+    /// Enables fast lock with the given settings, or disables it (and cycle slip reduction)
+    /// with `None`
+    pub fn set_fast_lock(&mut self, fast_lock: Option<FastLock>) -> Result<(), Error<SPI, LE>> {
+        if let Some(fl) = fast_lock {
+            if fl.charge_pump > 31 {
+                return Err(ValidationError::FastLockChargePumpOutOfRange {
+                    given: fl.charge_pump,
+                    max: 31,
+                }
+                .into());
+            }
+            if fl.count > 8191 {
+                return Err(ValidationError::FastLockCountOutOfRange {
+                    given: fl.count,
+                    max: 8191,
+                }
+                .into());
+            }
+        }
+        let mut st7: regs::ST7 = self.read_reg()?;
+        match fast_lock {
+            Some(fl) => {
+                st7.fstlck_en = true;
+                st7.cp_sel_fl = fl.charge_pump;
+                st7.fstlck_cnt = fl.count;
+                st7.cycle_slip_en = fl.cycle_slip_reduction;
+            }
+            None => {
+                st7.fstlck_en = false;
+                st7.cycle_slip_en = false;
+            }
+        }
+        self.write_reg(&st7)
+    }
+
+    /// Gets the fast lock settings, `None` if fast lock is disabled
+    pub fn get_fast_lock(&mut self) -> Result<Option<FastLock>, Error<SPI, LE>> {
+        let st7: regs::ST7 = self.read_reg()?;
+        Ok(st7.fstlck_en.then_some(FastLock {
+            charge_pump: st7.cp_sel_fl,
+            count: st7.fstlck_cnt,
+            cycle_slip_reduction: st7.cycle_slip_en,
+        }))
+    }
+
+    /// Sets up the charge pump for fast acquisition, or a fixed charge pump with `None`
+    /// Every N write (i.e. every retune) then runs the charge pump at `boost.boost` for
+    /// `boost.count` PFD cycles and drops back to `boost.steady_state` for low noise once
+    /// locked. The charge pump, fast lock and cycle slip settings are written together, fast
+    /// lock first, and nothing is written if any of them is out of range
+    pub fn acquisition_profile(&mut self, boost: Option<CpBoost>) -> Result<(), Error<SPI, LE>> {
+        self.batched(|vco| match boost {
+            Some(boost) => {
+                vco.set_fast_lock(Some(FastLock {
+                    charge_pump: boost.boost,
+                    count: boost.count,
+                    cycle_slip_reduction: boost.cycle_slip_reduction,
+                }))?;
+                vco.set_charge_pump(boost.steady_state)
+            }
+            None => vco.set_fast_lock(None),
+        })
+    }
+
+    /// Gets the acquisition boost, `None` if fast lock is disabled
+    pub fn get_acquisition_profile(&mut self) -> Result<Option<CpBoost>, Error<SPI, LE>> {
+        let fast_lock = self.get_fast_lock()?;
+        let steady_state = self.get_charge_pump()?;
+        Ok(fast_lock.map(|fl| CpBoost {
+            steady_state,
+            boost: fl.charge_pump,
+            count: fl.count,
+            cycle_slip_reduction: fl.cycle_slip_reduction,
+        }))
+    }
+
+    /// Enables or disables cycle slip reduction on its own, leaving the fast lock settings alone
+    pub fn set_cycle_slip_reduction(&mut self, enabled: bool) -> Result<(), Error<SPI, LE>> {
+        let mut st7: regs::ST7 = self.read_reg()?;
+        st7.cycle_slip_en = enabled;
+        self.write_reg(&st7)
+    }
+
+    /// Returns true if cycle slip reduction is enabled
+    pub fn get_cycle_slip_reduction(&mut self) -> Result<bool, Error<SPI, LE>> {
+        let st7: regs::ST7 = self.read_reg()?;
+        Ok(st7.cycle_slip_en)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::tester;
+
+    #[test]
+    fn fast_lock_round_trip() {
+        let mut vco = tester();
+        assert_eq!(vco.get_fast_lock().unwrap(), None);
+        let fl = FastLock {
+            charge_pump: 31,
+            count: 500,
+            cycle_slip_reduction: true,
+        };
+        vco.set_fast_lock(Some(fl)).unwrap();
+        assert_eq!(vco.get_fast_lock().unwrap(), Some(fl));
+        assert!(vco.get_cycle_slip_reduction().unwrap());
+
+        vco.set_fast_lock(None).unwrap();
+        assert_eq!(vco.get_fast_lock().unwrap(), None);
+        assert!(!vco.get_cycle_slip_reduction().unwrap());
+
+        vco.set_cycle_slip_reduction(true).unwrap();
+        assert!(vco.get_cycle_slip_reduction().unwrap());
+        assert_eq!(vco.get_fast_lock().unwrap(), None);
+    }
+
+    #[test]
+    fn acquisition_profile() {
+        let mut vco = tester();
+        let boost = CpBoost {
+            steady_state: 4,
+            boost: 28,
+            count: 1000,
+            cycle_slip_reduction: true,
+        };
+        vco.acquisition_profile(Some(boost)).unwrap();
+        assert_eq!(vco.spi.take_writes(), [7, 0]);
+        assert_eq!(vco.get_acquisition_profile().unwrap(), Some(boost));
+
+        // Nothing is written when one setting is out of range
+        let result = vco.acquisition_profile(Some(CpBoost {
+            steady_state: 32,
+            ..boost
+        }));
+        assert!(matches!(
+            result,
+            Err(Error::Validation(ValidationError::ChargePumpOutOfRange {
+                given: 32,
+                ..
+            }))
+        ));
+        assert!(vco.spi.take_writes().is_empty());
+
+        vco.acquisition_profile(None).unwrap();
+        assert_eq!(vco.get_acquisition_profile().unwrap(), None);
+        assert_eq!(vco.get_charge_pump().unwrap(), 4);
+    }
+}
@@ -0,0 +1,47 @@
+//! Fixed point frequency math for the `no-float` build
+//!
+//! Every frequency is derived from the reference as `ref_freq * num / den` with the ratio kept
+//! in integers until the very end, so the only rounding happens once, to the nearest Hz.
+
+/// `x * num / den`, rounded to the nearest integer
+pub(crate) fn mul_div_round(x: u64, num: u64, den: u64) -> u64 {
+    let den = den as u128;
+    ((x as u128 * num as u128 + den / 2) / den) as u64
+}
+
+/// `x * num / den`, rounded towards positive infinity
+pub(crate) fn mul_div_ceil(x: u64, num: u64, den: u64) -> u64 {
+    (x as u128 * num as u128).div_ceil(den as u128) as u64
+}
+
+/// The change in FRAC that shifts the output by `delta` Hz when one LSB is
+/// `ref_freq * num / (den * modu)`, rounded to the nearest integer
+pub(crate) fn frac_step(delta: i64, ref_freq: u64, num: u64, den: u64, modu: u32) -> i64 {
+    let top = delta as i128 * den as i128 * modu as i128;
+    let bottom = ref_freq as i128 * num as i128;
+    // Round half away from zero
+    let half = if top < 0 { -bottom / 2 } else { bottom / 2 };
+    ((top + half) / bottom) as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rounding() {
+        assert_eq!(mul_div_round(100_000_000, 1, 3), 33_333_333);
+        assert_eq!(mul_div_round(200_000_000, 1, 3), 66_666_667);
+        assert_eq!(mul_div_ceil(100_000_000, 1, 3 * 250_000), 134);
+        assert_eq!(mul_div_ceil(50_000_000, 1, 250_000), 200);
+    }
+
+    #[test]
+    fn frac_steps() {
+        // One LSB is 50 MHz / 2097151, about 23.8 Hz
+        assert_eq!(frac_step(1_000_000, 100_000_000, 1, 2, 2097151), 41943);
+        assert_eq!(frac_step(-1_000_000, 100_000_000, 1, 2, 2097151), -41943);
+        assert_eq!(frac_step(11, 100_000_000, 1, 2, 2097151), 0);
+        assert_eq!(frac_step(12, 100_000_000, 1, 2, 2097151), 1);
+    }
+}
@@ -0,0 +1,111 @@
+//! The numeric types used for frequencies, and the rounding operations the planner needs
+//!
+//! Frequencies are `f32` by default, which keeps the crate cheap on single precision FPUs.
+//! At several GHz an `f32` only resolves steps of a few hundred Hz though, so the `f64`
+//! feature switches every frequency in the API and all of the planning math to `f64`.
+//! `micromath` only covers `f32`, so the `f64` build rounds with `libm` instead.
+//! The `libm` feature uses `libm` in the `f32` build as well, in place of `micromath`'s
+//! approximations.
+//!
+//! The `no-float` feature removes floating point math entirely: frequencies are integer Hz
+//! and the planner works in fixed point (see `fixed`). It takes precedence over `f64`.
+
+#[cfg(not(any(feature = "no-float", feature = "libm", feature = "micromath")))]
+compile_error!("Enable one of the `micromath`, `libm`, `f64` or `no-float` features");
+
+/// Floating point type of every frequency (in Hz) and divider ratio in the API
+#[cfg(not(any(feature = "f64", feature = "no-float")))]
+pub type Float = f32;
+/// Floating point type of every frequency (in Hz) and divider ratio in the API
+#[cfg(all(feature = "f64", not(feature = "no-float")))]
+pub type Float = f64;
+
+/// A frequency in Hz
+#[cfg(not(feature = "no-float"))]
+pub type Hz = Float;
+/// A frequency in Hz
+#[cfg(feature = "no-float")]
+pub type Hz = u64;
+
+/// A signed frequency difference in Hz
+#[cfg(not(feature = "no-float"))]
+pub type HzDelta = Float;
+/// A signed frequency difference in Hz
+#[cfg(feature = "no-float")]
+pub type HzDelta = i64;
+
+/// `hz` as a `Hz`, for frequency constants that have to work in every build
+#[cfg(not(feature = "no-float"))]
+pub(crate) const fn hz(hz: u64) -> Hz {
+    hz as Hz
+}
+/// `hz` as a `Hz`, for frequency constants that have to work in every build
+#[cfg(feature = "no-float")]
+pub(crate) const fn hz(hz: u64) -> Hz {
+    hz
+}
+
+#[cfg(not(any(feature = "libm", feature = "no-float")))]
+use micromath::F32Ext;
+
+/// Rounds half-way cases away from zero
+/// Built on `trunc` so every backend gives the same result, `micromath` adds 0.5 first,
+/// which rounds values just below a half up
+#[cfg(not(feature = "no-float"))]
+pub(crate) fn round(x: Float) -> Float {
+    // Both differences are exact, so only the comparison decides
+    let t = trunc(x);
+    if x - t >= 0.5 {
+        t + 1.0
+    } else if t - x >= 0.5 {
+        t - 1.0
+    } else {
+        t
+    }
+}
+
+/// Rounds towards zero
+#[cfg(not(feature = "no-float"))]
+pub(crate) fn trunc(x: Float) -> Float {
+    #[cfg(not(feature = "libm"))]
+    return F32Ext::trunc(x);
+    #[cfg(all(feature = "libm", not(feature = "f64")))]
+    return libm::truncf(x);
+    #[cfg(feature = "f64")]
+    return libm::trunc(x);
+}
+
+/// Rounds towards positive infinity
+#[cfg(not(feature = "no-float"))]
+pub(crate) fn ceil(x: Float) -> Float {
+    #[cfg(not(feature = "libm"))]
+    return F32Ext::ceil(x);
+    #[cfg(all(feature = "libm", not(feature = "f64")))]
+    return libm::ceilf(x);
+    #[cfg(feature = "f64")]
+    return libm::ceil(x);
+}
+
+#[cfg(test)]
+#[cfg(not(feature = "no-float"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rounding() {
+        assert_eq!(round(2.5), 3.0);
+        assert_eq!(round(-2.5), -3.0);
+        assert_eq!(trunc(507.9), 507.0);
+        assert_eq!(ceil(133.3), 134.0);
+        assert_eq!(ceil(200.0), 200.0);
+    }
+
+    #[test]
+    fn rounding_boundary() {
+        // The largest f32 below a half, adding 0.5 to it rounds up to 1.0
+        assert_eq!(round(0.49999997), 0.0);
+        assert_eq!(round(-0.49999997), 0.0);
+        assert_eq!(round(41943.5), 41944.0);
+        assert_eq!(round(3e9), 3e9);
+    }
+}
@@ -0,0 +1,174 @@
+//! Frequency hopping without VCO calibration
+//!
+//! Calibrating the VCO takes most of the time of a retune. A `HopTable` tunes each frequency
+//! of a hop set once, records the calibrated VCO core and word together with the registers
+//! that program the frequency, and later replays them with manual calibration enabled. A hop
+//! then costs only the SPI writes and the PLL settling. The calibration words only hold lock
+//! near the temperature they were learned at, learn the table again after a large temperature
+//! change (see `TemperatureTracker`).
+use crate::error::{Error, ValidationError};
+use crate::float::hz;
+use crate::recalibration::VcoWord;
+use crate::registers::{self as regs, Register, RegisterAddr};
+use crate::{Hz, STuW81300};
+use embedded_hal as hal;
+use hal::blocking::delay::DelayUs;
+use hal::blocking::spi::Transfer;
+use hal::digital::v2::{InputPin, OutputPin};
+
+/// One learned frequency of a `HopTable`
+#[derive(Debug, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Hop {
+    /// The frequency requested while learning
+    pub frequency: Hz,
+    /// The output frequency the registers program
+    pub programmed: Hz,
+    /// The VCO core and word the calibration picked
+    pub vco: VcoWord,
+    /// Contents of ST0 to ST8, with the VCO word preloaded and manual calibration enabled
+    pub registers: [u32; 9],
+}
+
+/// A set of frequencies to hop between without calibrating, see `learn`
+#[derive(Debug, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct HopTable<const N: usize> {
+    hops: [Hop; N],
+}
+
+impl<const N: usize> HopTable<N> {
+    /// Tunes `vco` to each of `freqs` in turn with automatic calibration and records the hop
+    /// Waits up to `lock_timeout_us` for lock at every frequency on the attached delay and
+    /// returns `Error::LockTimeout` if one doesn't lock. The device is left on the last
+    /// frequency with manual calibration disabled
+    pub fn learn<SPI, LE, PD, MUTE, D, LD>(
+        vco: &mut STuW81300<SPI, LE, PD, MUTE, D, LD>,
+        freqs: &[Hz; N],
+        lock_timeout_us: u32,
+    ) -> Result<Self, Error<SPI, LE>>
+    where
+        SPI: Transfer<u8>,
+        LE: OutputPin,
+        PD: OutputPin,
+        MUTE: OutputPin,
+        LD: InputPin,
+        D: DelayUs<u32>,
+    {
+        let mut hops = [Hop {
+            frequency: hz(0),
+            programmed: hz(0),
+            vco: VcoWord { core: 0, word: 0 },
+            registers: [0; 9],
+        }; N];
+        vco.set_manual_calibration(false)?;
+        for (hop, &f) in hops.iter_mut().zip(freqs) {
+            vco.set_output_frequency(f)?;
+            if vco.wait_for_lock(lock_timeout_us)?.is_none() {
+                return Err(Error::LockTimeout);
+            }
+            hop.frequency = f;
+            hop.programmed = vco.get_output_frequency()?;
+            hop.vco = vco.capture_vco_word()?;
+            for (addr, register) in RegisterAddr::iter().zip(hop.registers.iter_mut()) {
+                *register = vco.read(addr)?;
+            }
+            let mut st1 = regs::ST1::decode(hop.registers[1]);
+            st1.man_calb_en = true;
+            hop.registers[1] = st1.encode();
+            let mut st6 = regs::ST6::decode(hop.registers[6]);
+            st6.vco_sel = hop.vco.core;
+            st6.vco_word = hop.vco.word;
+            hop.registers[6] = st6.encode();
+        }
+        Ok(HopTable { hops })
+    }
+
+    /// The learned hops, in the order of the frequencies given to `learn`
+    pub fn hops(&self) -> &[Hop; N] {
+        &self.hops
+    }
+
+    /// Index of the hop learned for `frequency`, if any
+    pub fn position(&self, frequency: Hz) -> Option<usize> {
+        self.hops.iter().position(|hop| hop.frequency == frequency)
+    }
+
+    /// Hops `vco` to the frequency learned at `index`
+    /// Returns `ValidationError::HopOutOfRange` without writing anything if there is none
+    /// Only the registers that differ from the current ones are written, then ST0, with the
+    /// VCO word preloaded and manual calibration enabled. Disable manual calibration with
+    /// `set_manual_calibration` before tuning to a frequency outside the table
+    pub fn hop<SPI, LE, PD, MUTE, D, LD>(
+        &self,
+        vco: &mut STuW81300<SPI, LE, PD, MUTE, D, LD>,
+        index: usize,
+    ) -> Result<(), Error<SPI, LE>>
+    where
+        SPI: Transfer<u8>,
+        LE: OutputPin,
+    {
+        let hop = self.hops.get(index).ok_or(ValidationError::HopOutOfRange {
+            given: index,
+            hops: N,
+        })?;
+        vco.batched(|vco| {
+            for (addr, &data) in RegisterAddr::iter().zip(hop.registers.iter()) {
+                if addr == RegisterAddr::ST0 || vco.read(addr)? != data {
+                    vco.write(addr, data)?;
+                }
+            }
+            Ok(())
+        })?;
+        vco.requested_freq = Some(hop.frequency);
+        vco.programmed_freq = Some(hop.programmed);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{assert_freq_eq, initialized_tester};
+
+    #[test]
+    fn learn_and_hop() {
+        let mut vco = initialized_tester();
+        let st10 = regs::ST10 {
+            lock_det: true,
+            vco_sel: 1,
+            word: 9,
+            ..Default::default()
+        };
+        vco.spi.write(10, st10.encode());
+
+        let freqs = [hz(5_000_000_000), hz(5_100_000_000)];
+        let table = HopTable::learn(&mut vco, &freqs, 100).unwrap();
+        assert_eq!(table.hops()[1].vco, VcoWord { core: 1, word: 9 });
+        assert_eq!(table.position(hz(5_000_000_000)), Some(0));
+        assert!(!vco.get_manual_calibration().unwrap());
+        vco.spi.take_writes();
+
+        table.hop(&mut vco, 0).unwrap();
+        assert_eq!(vco.spi.take_writes(), [6, 1, 0]);
+        assert!(vco.get_manual_calibration().unwrap());
+        assert_freq_eq!(vco.get_output_frequency().unwrap(), hz(5_000_000_000));
+        let simulated = vco.spi.simulated_output_hz().unwrap();
+        assert!((simulated - 5e9).abs() < 1e3);
+
+        // Back and forth only rewrites the dividers
+        table.hop(&mut vco, 1).unwrap();
+        table.hop(&mut vco, 0).unwrap();
+        let writes = vco.spi.take_writes();
+        assert!(writes.iter().all(|&addr| addr <= 2));
+
+        assert!(matches!(
+            table.hop(&mut vco, 2),
+            Err(Error::Validation(ValidationError::HopOutOfRange {
+                given: 2,
+                hops: 2
+            }))
+        ));
+        assert!(vco.spi.take_writes().is_empty());
+    }
+}
@@ -0,0 +1,88 @@
+//! How the device's SPI port is wired
+//!
+//! Layouts with a shared SDI/SDO line (3-wire SPI), or without SDO routed at all, can't read
+//! registers back. In the `WriteOnly` mode the driver answers reads of writable registers
+//! from its shadow copy of what it last wrote, and reports `Error::Unreadable` for the status
+//! registers. The lock state is still available through an LD pin, see `with_ld_pin`.
+use crate::{DeviceVariant, STuW81300};
+
+/// The SPI wiring of the device
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Interface {
+    /// SDI and SDO on separate lines, registers are read from the device
+    FourWire,
+    /// Reads never reach the device, set `spi_data_out_disable` in ST7 to keep it off the
+    /// shared line
+    /// `variant` stands in for the device ID, which can't be read
+    WriteOnly { variant: DeviceVariant },
+}
+
+impl<SPI, LE, PD, MUTE, D, LD> STuW81300<SPI, LE, PD, MUTE, D, LD> {
+    /// Sets how the SPI port is wired, `Interface::FourWire` by default
+    /// Switch to `WriteOnly` before `init`, which then programs every writable register from
+    /// a cleared state so the shadow matches the device
+    pub fn set_interface(&mut self, interface: Interface) {
+        if let Interface::WriteOnly { variant } = interface {
+            self.variant = Some(variant);
+        }
+        self.interface = interface;
+    }
+
+    /// How the SPI port is wired
+    pub fn interface(&self) -> Interface {
+        self.interface
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+    use crate::float::hz;
+    use crate::mock::{assert_freq_eq, tester, MockStuw81300LE, MockStuw81300SPI};
+    use crate::registers::RegisterAddr;
+    use embedded_hal_mock as mock;
+    use mock::pin::{Mock as PinMock, State as PinState, Transaction as PinTransaction};
+
+    fn write_only() -> STuW81300<MockStuw81300SPI, MockStuw81300LE> {
+        let mut vco = tester();
+        vco.set_interface(Interface::WriteOnly {
+            variant: DeviceVariant::Stuw81300T,
+        });
+        vco
+    }
+
+    #[test]
+    fn reads_from_shadow() {
+        let mut vco = write_only();
+        assert!(matches!(
+            vco.get_charge_pump(),
+            Err(Error::Unreadable(RegisterAddr::ST0))
+        ));
+        vco.init().unwrap();
+        vco.set_reference_clock_divider(2).unwrap();
+        vco.set_output_frequency(hz(5_000_000_000)).unwrap();
+        // The device disagrees, but is never asked
+        vco.spi.write(3, 0);
+        assert_eq!(vco.get_pfd_frequency().unwrap(), hz(50_000_000));
+        assert_freq_eq!(vco.get_output_frequency().unwrap(), hz(5_000_000_000));
+        assert!(matches!(
+            vco.device_id(),
+            Err(Error::Unreadable(RegisterAddr::ST11))
+        ));
+        assert!(matches!(
+            vco.is_locked(),
+            Err(Error::Unreadable(RegisterAddr::ST10))
+        ));
+    }
+
+    #[test]
+    fn lock_from_pin() {
+        let pin = PinMock::new(&[PinTransaction::get(PinState::High)]);
+        let mut vco = write_only().with_ld_pin(pin);
+        vco.init().unwrap();
+        assert!(vco.is_locked().unwrap());
+        vco.ld.unwrap().done();
+    }
+}
@@ -0,0 +1,132 @@
+//! Latch enable pins behind slow or fallible GPIO
+//!
+//! The driver only drives LE low before each frame and high after it, the device latches the
+//! frame on the rising edge. Nothing depends on how quickly the pin switches, so a pin behind
+//! an I2C or SPI I/O expander works as is, and its errors are reported as
+//! `Error::LatchEnable`. The shadow registers only take frames that went through, so a failed
+//! operation can simply be retried. Expanders that acknowledge a write before the output has
+//! settled can be wrapped in a `SettledPin`.
+use embedded_hal as hal;
+use hal::blocking::delay::DelayUs;
+use hal::digital::v2::OutputPin;
+
+/// An output pin that waits `settle_us` after every edge
+/// Keeps SPI traffic out of the window where the LE output is still switching
+pub struct SettledPin<P, D> {
+    pin: P,
+    delay: D,
+    settle_us: u32,
+}
+
+impl<P, D> SettledPin<P, D>
+where
+    P: OutputPin,
+    D: DelayUs<u32>,
+{
+    /// Wraps `pin`, waiting `settle_us` on `delay` after driving it
+    pub fn new(pin: P, delay: D, settle_us: u32) -> Self {
+        SettledPin {
+            pin,
+            delay,
+            settle_us,
+        }
+    }
+
+    /// Releases the pin and the delay
+    pub fn free(self) -> (P, D) {
+        (self.pin, self.delay)
+    }
+}
+
+impl<P, D> OutputPin for SettledPin<P, D>
+where
+    P: OutputPin,
+    D: DelayUs<u32>,
+{
+    type Error = P::Error;
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.pin.set_low()?;
+        self.delay.delay_us(self.settle_us);
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.pin.set_high()?;
+        self.delay.delay_us(self.settle_us);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+    use crate::float::hz;
+    use crate::mock::MockStuw81300SPI;
+    use crate::STuW81300;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    /// An expander pin whose writes fail on request
+    #[derive(Clone, Default)]
+    struct ExpanderPin {
+        writes: Rc<Cell<u32>>,
+        fail: Rc<Cell<bool>>,
+    }
+
+    impl OutputPin for ExpanderPin {
+        type Error = &'static str;
+
+        fn set_low(&mut self) -> Result<(), Self::Error> {
+            self.set_high()
+        }
+
+        fn set_high(&mut self) -> Result<(), Self::Error> {
+            if self.fail.get() {
+                return Err("I2C NACK");
+            }
+            self.writes.set(self.writes.get() + 1);
+            Ok(())
+        }
+    }
+
+    /// Adds up the time waited, standing in for the expander's bus latency
+    #[derive(Clone, Default)]
+    struct Elapsed(Rc<Cell<u32>>);
+
+    impl DelayUs<u32> for Elapsed {
+        fn delay_us(&mut self, us: u32) {
+            self.0.set(self.0.get() + us);
+        }
+    }
+
+    #[test]
+    fn slow_fallible_latch_enable() {
+        let pin = ExpanderPin::default();
+        let elapsed = Elapsed::default();
+        let mut vco = STuW81300::new(
+            MockStuw81300SPI::default(),
+            SettledPin::new(pin.clone(), elapsed.clone(), 50),
+            crate::SupplyVoltage::HighVoltage,
+            crate::mock::REF_FREQ,
+            crate::ReferenceType::SingleEnded,
+        );
+        vco.init().unwrap();
+        vco.set_reference_clock_divider(2).unwrap();
+        // Two edges per frame, each followed by the settle time
+        assert_eq!(elapsed.0.get(), 50 * pin.writes.get());
+        assert_eq!(pin.writes.get() % 2, 0);
+
+        pin.fail.set(true);
+        assert!(matches!(
+            vco.set_output_frequency(hz(5_000_000_000)),
+            Err(Error::LatchEnable("I2C NACK"))
+        ));
+        // The next attempt goes through once the expander responds again
+        pin.fail.set(false);
+        vco.set_output_frequency(hz(5_000_000_000)).unwrap();
+        let simulated = vco.spi.simulated_output_hz().unwrap();
+        assert!((simulated - 5e9).abs() < 1e3);
+    }
+}
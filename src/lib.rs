@@ -1,20 +1,112 @@
 #![cfg_attr(not(test), no_std)]
 
-///! This is an `embedded-hal` crate for the (STuW81300)[https://www.st.com/en/wireless-connectivity/stuw81300.html] integrated VCO/PLL chip.
-///! Eventually I hope to cover the entire capabilities of the chip as a nice example of a complicated embedded-hal SPI driver.
-///!
-///! # Not yet implemented
-///! * Charge pump leakage current
-///! * Down-split current
-///! * RF2 Output
+//! This is an `embedded-hal` crate for the (STuW81300)[https://www.st.com/en/wireless-connectivity/stuw81300.html] integrated VCO/PLL chip.
+//! Eventually I hope to cover the entire capabilities of the chip as a nice example of a complicated embedded-hal SPI driver.
+//!
+//! # Not yet implemented
+//! * Charge pump leakage current
+//! * Down-split current
+//! * RF2 Output
 mod api;
+mod audit;
+mod boot;
+mod characterization;
+mod clock;
+mod config;
+#[cfg(feature = "controller")]
+mod controller;
+mod debug;
+mod diagnostics;
+#[cfg(feature = "embassy")]
+mod embassy;
 mod error;
+mod fast_lock;
+#[cfg(feature = "no-float")]
+mod fixed;
+mod float;
+mod hop;
+mod interface;
+mod latch;
+mod lock;
+mod logging;
+pub mod math;
+#[cfg(feature = "metadata")]
+pub mod metadata;
+#[cfg(test)]
 mod mock;
-mod registers;
+mod order;
+mod plan;
+mod pll_path;
+mod power;
+pub mod presets;
+mod production;
+mod recalibration;
+mod reference;
+pub mod registers;
+mod rounding;
+#[cfg(feature = "rppal")]
+mod rppal;
+mod self_test;
+#[cfg(feature = "embassy-sync")]
+mod shared;
 mod spi;
+mod startup;
+mod strict;
+mod supervise;
+mod supply;
+mod sync;
+mod telemetry;
+#[cfg(feature = "uom")]
+mod units;
+
+pub use api::{
+    ClockTree, DividerState, DsmOrder, FrequencyResolution, PfdDelay, PfdDelayMode, Port,
+    PrechargeDelay, ReferenceClockPath, ReferencePathRule, ReferencePaths, Rf1Path,
+};
+pub use audit::{CacheAudit, RegisterMismatch};
+pub use boot::RegisterPlan;
+pub use characterization::LockTimeSample;
+pub use clock::{Clock, ClockDelay};
+pub use config::{Calibration, Config, ConfigDelta};
+#[cfg(feature = "controller")]
+pub use controller::{Controller, ControllerPolicy, ControllerState};
+pub use diagnostics::{BandEdge, Lints, VcoBandReport};
+#[cfg(feature = "embassy")]
+pub use embassy::LockEvents;
+pub use error::{Error, ErrorKind, ValidationError};
+pub use fast_lock::{CpBoost, FastLock};
+use float::hz;
+#[cfg(not(feature = "no-float"))]
+pub use float::Float;
+pub use float::{Hz, HzDelta};
+pub use hop::{Hop, HopTable};
+pub use interface::Interface;
+pub use latch::SettledPin;
+pub use lock::{LockDetectPin, LockDetector, LockEvent, OutputGuard};
+pub use plan::{FrequencyPlanner, PlanConstraints, PlanStrategy};
+pub use pll_path::PllPath;
+pub use power::PowerState;
+pub use production::{ProductionProfile, ProductionReport};
+pub use recalibration::{TemperatureTracker, VcoWord, RECALIBRATION_THRESHOLD_MC};
+pub use reference::ReferenceBufferConfig;
+pub use registers::{decode, DecodedRegister, DoubleBuffered, Field, Register, RegisterAddr};
+pub use rounding::Rounding;
+#[cfg(feature = "rppal")]
+pub use rppal::{RppalConfig, RppalLatchEnable, RppalStuw81300};
+pub use self_test::SelfTestReport;
+#[cfg(feature = "embassy-sync")]
+pub use shared::{Outputs, PllControl, SharedStuw81300, Status};
+pub use supervise::{RecoveryPolicy, RecoveryReport};
+pub use sync::SyncGroup;
+pub use telemetry::Telemetry;
+#[cfg(all(feature = "uom", not(feature = "no-float")))]
+pub use units::ElectricCurrent;
+#[cfg(feature = "uom")]
+pub use units::Frequency;
 
 /// Enum representation of the pin 36 supply voltage
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum SupplyVoltage {
     /// Input voltage is 3.6 to 5.4
     LowVoltage,
@@ -25,6 +117,7 @@ pub enum SupplyVoltage {
 /// The connection type of the reference clock
 #[repr(u32)]
 #[derive(Debug, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum ReferenceType {
     /// Ref clock is connected to pin 21
     SingleEnded = 3,
@@ -34,32 +127,239 @@ pub enum ReferenceType {
     Crystal = 2,
 }
 
-pub struct STuW81300<SPI, LE> {
+/// The known variants of the chip, distinguished by their device ID
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DeviceVariant {
+    /// STUW81300T and STUW81300TR, ID 0x8052
+    Stuw81300T,
+    /// STUW81300-1T and STUW81300-1TR, ID 0x804B
+    Stuw81300T1,
+}
+
+impl DeviceVariant {
+    /// Looks up the variant reporting `id`
+    pub fn from_id(id: u32) -> Option<Self> {
+        match id {
+            0x8052 => Some(DeviceVariant::Stuw81300T),
+            0x804B => Some(DeviceVariant::Stuw81300T1),
+            _ => None,
+        }
+    }
+
+    /// The device ID reported in ST11
+    pub fn id(&self) -> u32 {
+        match self {
+            DeviceVariant::Stuw81300T => 0x8052,
+            DeviceVariant::Stuw81300T1 => 0x804B,
+        }
+    }
+
+    /// The (min, max) RF1 output frequency in Hz
+    pub fn rf1_range(&self) -> (Hz, Hz) {
+        match self {
+            DeviceVariant::Stuw81300T | DeviceVariant::Stuw81300T1 => {
+                (hz(3_000_000_000), hz(8_000_000_000))
+            }
+        }
+    }
+}
+
+/// Placeholder for optional pins that aren't connected
+pub struct NoPin;
+
+impl embedded_hal::digital::v2::OutputPin for NoPin {
+    type Error = core::convert::Infallible;
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl embedded_hal::digital::v2::InputPin for NoPin {
+    type Error = core::convert::Infallible;
+
+    fn is_high(&self) -> Result<bool, Self::Error> {
+        Ok(false)
+    }
+
+    fn is_low(&self) -> Result<bool, Self::Error> {
+        Ok(true)
+    }
+}
+
+/// Placeholder for an optional delay provider that isn't attached
+/// Waits are skipped, so the caller is responsible for any settling time
+pub struct NoDelay;
+
+impl embedded_hal::blocking::delay::DelayUs<u32> for NoDelay {
+    fn delay_us(&mut self, _us: u32) {}
+}
+
+pub struct STuW81300<SPI, LE, PD = NoPin, MUTE = NoPin, D = NoDelay, LD = NoPin> {
     spi: SPI,
     le: LE,
+    pd: Option<PD>,
+    mute: Option<MUTE>,
+    /// Last level driven on the power down and mute pins, true for high
+    pd_asserted: bool,
+    mute_asserted: bool,
+    delay: Option<D>,
+    ld: Option<LD>,
     supply_voltage: SupplyVoltage,
-    ref_freq: f32,
+    /// Reference frequency in whole Hz, so the frequency math starts from an exact value
+    ref_freq: u64,
     ref_type: ReferenceType,
+    variant: Option<DeviceVariant>,
+    requested_freq: Option<Hz>,
+    programmed_freq: Option<Hz>,
+    staged: Option<sync::StagedFrequency>,
+    /// Double buffered registers written with `stage`, one bit per address
+    buffered: u16,
+    batch: Option<order::WriteBatch>,
+    strict: bool,
+    rounding: Rounding,
+    /// Last value sent to or read from each writable register, indexed by address
+    shadow: [Option<u32>; 12],
+    deferred: bool,
+    /// Reads of writable registers come from the shadow where it holds a value
+    cached_reads: bool,
+    interface: Interface,
+    /// Registers whose shadow value hasn't been sent yet, bit n set for STn
+    dirty: u16,
 }
 
 impl<SPI, LE> STuW81300<SPI, LE> {
+    /// Creates the driver for a reference of `ref_freq` Hz, rounded to the nearest Hz
     pub fn new(
         spi: SPI,
         le: LE,
         supply_voltage: SupplyVoltage,
-        ref_freq: f32,
+        ref_freq: Hz,
+        ref_type: ReferenceType,
+    ) -> Self {
+        #[cfg(not(feature = "no-float"))]
+        let ref_freq = float::round(ref_freq) as u64;
+        Self::new_hz(spi, le, supply_voltage, ref_freq, ref_type)
+    }
+
+    /// Creates the driver for a reference of exactly `ref_freq` Hz
+    pub fn new_hz(
+        spi: SPI,
+        le: LE,
+        supply_voltage: SupplyVoltage,
+        ref_freq: u64,
         ref_type: ReferenceType,
     ) -> Self {
         assert!(
-            (10e6..=800e6).contains(&ref_freq),
+            (10_000_000..=800_000_000).contains(&ref_freq),
             "Reference frequency out of range"
         );
         STuW81300 {
             spi,
             le,
+            pd: None,
+            mute: None,
+            pd_asserted: false,
+            mute_asserted: false,
+            delay: None,
+            ld: None,
             supply_voltage,
             ref_freq,
             ref_type,
+            variant: None,
+            requested_freq: None,
+            programmed_freq: None,
+            staged: None,
+            buffered: 0,
+            batch: None,
+            strict: false,
+            rounding: Rounding::Nearest,
+            shadow: [None; 12],
+            deferred: false,
+            cached_reads: false,
+            interface: Interface::FourWire,
+            dirty: 0,
         }
     }
 }
+
+impl<SPI, LE, PD, MUTE, D, LD> STuW81300<SPI, LE, PD, MUTE, D, LD> {
+    /// Moves the driver over to other pin and delay types, `pins` maps the attached ones
+    /// The builders below go through here, so a new field is only added here and in `new_hz`
+    fn retype<PD2, MUTE2, D2, LD2>(
+        self,
+        pins: impl FnOnce(
+            Option<PD>,
+            Option<MUTE>,
+            Option<D>,
+            Option<LD>,
+        ) -> (Option<PD2>, Option<MUTE2>, Option<D2>, Option<LD2>),
+    ) -> STuW81300<SPI, LE, PD2, MUTE2, D2, LD2> {
+        let (pd, mute, delay, ld) = pins(self.pd, self.mute, self.delay, self.ld);
+        STuW81300 {
+            spi: self.spi,
+            le: self.le,
+            pd,
+            mute,
+            pd_asserted: self.pd_asserted,
+            mute_asserted: self.mute_asserted,
+            delay,
+            ld,
+            supply_voltage: self.supply_voltage,
+            ref_freq: self.ref_freq,
+            ref_type: self.ref_type,
+            variant: self.variant,
+            requested_freq: self.requested_freq,
+            programmed_freq: self.programmed_freq,
+            staged: self.staged,
+            buffered: self.buffered,
+            batch: self.batch,
+            strict: self.strict,
+            rounding: self.rounding,
+            shadow: self.shadow,
+            deferred: self.deferred,
+            cached_reads: self.cached_reads,
+            interface: self.interface,
+            dirty: self.dirty,
+        }
+    }
+}
+
+impl<SPI, LE, MUTE, D, LD> STuW81300<SPI, LE, NoPin, MUTE, D, LD> {
+    /// Hands the hardware power down pin to the driver
+    /// `power_down`, `power_up` and `init` then drive the pin instead of using the register bit
+    pub fn with_pd_pin<PD>(self, pd: PD) -> STuW81300<SPI, LE, PD, MUTE, D, LD> {
+        self.retype(|_, mute, delay, ld| (Some(pd), mute, delay, ld))
+    }
+}
+
+impl<SPI, LE, PD, D, LD> STuW81300<SPI, LE, PD, NoPin, D, LD> {
+    /// Hands the hardware RF mute pin to the driver
+    /// `mute` then drives the pin instead of powering down the output buffer
+    pub fn with_mute_pin<MUTE>(self, mute: MUTE) -> STuW81300<SPI, LE, PD, MUTE, D, LD> {
+        self.retype(|pd, _, delay, ld| (pd, Some(mute), delay, ld))
+    }
+}
+
+impl<SPI, LE, PD, MUTE, D> STuW81300<SPI, LE, PD, MUTE, D, NoPin> {
+    /// Hands the MCU input connected to LD_SDO to the driver
+    /// `is_locked` then samples the pin instead of reading ST10, which also makes the lock
+    /// state available in the `WriteOnly` interface mode. Route the lock detector to the pin
+    /// first, see `enable_lock_detect_output`
+    pub fn with_ld_pin<LD>(self, ld: LD) -> STuW81300<SPI, LE, PD, MUTE, D, LD> {
+        self.retype(|pd, mute, delay, _| (pd, mute, delay, Some(ld)))
+    }
+}
+
+impl<SPI, LE, PD, MUTE, LD> STuW81300<SPI, LE, PD, MUTE, NoDelay, LD> {
+    /// Hands a delay provider to the driver
+    /// It is used wherever the device needs time to settle, e.g. in `power_up`
+    pub fn with_delay<D>(self, delay: D) -> STuW81300<SPI, LE, PD, MUTE, D, LD> {
+        self.retype(|pd, mute, _, ld| (pd, mute, Some(delay), ld))
+    }
+}
@@ -0,0 +1,401 @@
+//! Interrupt-driven lock notification through the LD_SDO pin
+use crate::error::{Error, ValidationError};
+use crate::logging::{debug, warning};
+use crate::registers as regs;
+use crate::{Hz, STuW81300};
+use embedded_hal as hal;
+use hal::blocking::delay::DelayUs;
+use hal::blocking::spi::Transfer;
+use hal::digital::v2::{InputPin, OutputPin};
+
+/// Interval between lock detector reads while waiting for lock, in microseconds
+pub(crate) const LOCK_POLL_US: u32 = 10;
+
+/// A change in the lock state of the PLL
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LockEvent {
+    /// The PLL acquired lock
+    Locked,
+    /// The PLL lost lock
+    Unlocked,
+}
+
+/// Lock detector settings, written to ST4
+#[derive(Debug, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LockDetector {
+    /// Width of the phase error window that counts as locked, LD_PREC (0..7)
+    pub precision: u32,
+    /// Number of consecutive PFD cycles inside the window before lock is reported, LD_COUNT (0..7)
+    pub count: u32,
+}
+
+impl Default for LockDetector {
+    /// A moderate window and count, suitable for fractional-N operation
+    fn default() -> Self {
+        LockDetector {
+            precision: 3,
+            count: 3,
+        }
+    }
+}
+
+/// RF output buffer sequencing around a retune, see `retune_guarded`
+#[derive(Debug, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct OutputGuard {
+    /// Whether the RF1 output buffer is enabled once the PLL locks
+    pub rf1: bool,
+    /// Whether the RF2 output buffer is enabled once the PLL locks
+    pub rf2: bool,
+    /// Retunes further than this from the programmed frequency power the buffers down first,
+    /// in Hz. So does any retune without a programmed frequency
+    pub max_live_step: Hz,
+    /// How long to wait for lock before enabling the buffers, in microseconds
+    pub lock_timeout_us: u32,
+}
+
+/// Tracks the lock state through an MCU input connected to the LD_SDO pin
+///
+/// Configure the MCU pin to interrupt on both edges and call `on_edge` from the handler.
+/// Clear the pending interrupt flag *before* calling `on_edge`, so an edge arriving while the
+/// pin is being sampled re-arms the interrupt instead of being lost. The pin also carries SPI
+/// read data, so edges caused by register reads are reported as `None` when the level
+/// settles back to the previous lock state.
+pub struct LockDetectPin<P> {
+    pin: P,
+    active_low: bool,
+    locked: bool,
+}
+
+impl<P> LockDetectPin<P>
+where
+    P: InputPin,
+{
+    /// Wraps `pin`, assuming the PLL is currently in the `locked` state
+    pub fn new(pin: P, active_low: bool, locked: bool) -> Self {
+        LockDetectPin {
+            pin,
+            active_low,
+            locked,
+        }
+    }
+
+    /// Samples the pin after an edge interrupt, returning the transition if the lock state changed
+    pub fn on_edge(&mut self) -> Result<Option<LockEvent>, P::Error> {
+        let locked = self.pin.is_high()? != self.active_low;
+        if locked == self.locked {
+            return Ok(None);
+        }
+        self.locked = locked;
+        let event = if locked {
+            LockEvent::Locked
+        } else {
+            LockEvent::Unlocked
+        };
+        debug!("lock detect pin: {:?}", event);
+        Ok(Some(event))
+    }
+
+    /// The lock state as of the last edge
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    /// Releases the input pin
+    pub fn free(self) -> P {
+        self.pin
+    }
+}
+
+impl<SPI, LE, PD, MUTE, D, LD> STuW81300<SPI, LE, PD, MUTE, D, LD>
+where
+    SPI: Transfer<u8>,
+    LE: OutputPin,
+    PD: OutputPin,
+    MUTE: OutputPin,
+    LD: InputPin,
+{
+    /// Sets the lock detector window and count
+    pub fn set_lock_detector(&mut self, ld: LockDetector) -> Result<(), Error<SPI, LE>> {
+        if ld.precision > 7 {
+            return Err(ValidationError::LockPrecisionOutOfRange {
+                given: ld.precision,
+                max: 7,
+            }
+            .into());
+        }
+        if ld.count > 7 {
+            return Err(ValidationError::LockCountOutOfRange {
+                given: ld.count,
+                max: 7,
+            }
+            .into());
+        }
+        let mut st4: regs::ST4 = self.read_reg()?;
+        st4.ld_prec = ld.precision;
+        st4.ld_count = ld.count;
+        self.write_reg(&st4)
+    }
+
+    /// Gets the lock detector window and count
+    pub fn get_lock_detector(&mut self) -> Result<LockDetector, Error<SPI, LE>> {
+        let st4: regs::ST4 = self.read_reg()?;
+        Ok(LockDetector {
+            precision: st4.ld_prec,
+            count: st4.ld_count,
+        })
+    }
+
+    /// Routes the lock detector to the LD_SDO pin
+    /// The pin is actively driven and keeps carrying SPI read data, so register reads still work
+    pub fn enable_lock_detect_output(&mut self, active_low: bool) -> Result<(), Error<SPI, LE>> {
+        let mut st4: regs::ST4 = self.read_reg()?;
+        st4.ld_activelow = active_low;
+        self.write_reg(&st4)?;
+        let mut st7: regs::ST7 = self.read_reg()?;
+        st7.ld_sdo_tristate = false;
+        st7.ld_sdo_mode = false;
+        st7.spi_data_out_disable = false;
+        self.write_reg(&st7)
+    }
+
+    /// Configures LD_SDO as a lock detect output and wraps the MCU input `pin` connected to it
+    /// The returned tracker is seeded with the current lock state, read over SPI
+    pub fn attach_lock_interrupt<P>(
+        &mut self,
+        pin: P,
+        active_low: bool,
+    ) -> Result<LockDetectPin<P>, Error<SPI, LE>>
+    where
+        P: InputPin,
+    {
+        self.enable_lock_detect_output(active_low)?;
+        let locked = self.is_locked()?;
+        Ok(LockDetectPin::new(pin, active_low, locked))
+    }
+}
+
+impl<SPI, LE, PD, MUTE, D, LD> STuW81300<SPI, LE, PD, MUTE, D, LD>
+where
+    SPI: Transfer<u8>,
+    LE: OutputPin,
+    PD: OutputPin,
+    MUTE: OutputPin,
+    LD: InputPin,
+    D: DelayUs<u32>,
+{
+    /// Polls the lock detector until the PLL locks or `timeout_us` has passed
+    /// Returns the time it took to lock, `None` on timeout
+    /// Without a delay attached the timeout becomes a number of back to back polls
+    pub(crate) fn wait_for_lock(&mut self, timeout_us: u32) -> Result<Option<u32>, Error<SPI, LE>> {
+        self.poll_lock(LOCK_POLL_US, timeout_us)
+    }
+
+    /// Retunes to `f` with the output muted until the PLL locks again, so nothing downstream
+    /// sees the VCO slewing through the band
+    /// Enables `mute_lock_en` in ST4 in the same batch as the new divider settings, waits up
+    /// to `lock_timeout_us` for lock on the attached delay, then restores the previous
+    /// setting. On `LockTimeout` mute-until-lock is left enabled, so the output stays muted
+    pub fn retune_muted(&mut self, f: Hz, lock_timeout_us: u32) -> Result<(), Error<SPI, LE>> {
+        let st4: regs::ST4 = self.read_reg()?;
+        self.batched(|vco| {
+            vco.write_reg(&regs::ST4 {
+                mute_lock_en: true,
+                ..st4
+            })?;
+            vco.set_output_frequency(f)
+        })?;
+        if self.wait_for_lock(lock_timeout_us)?.is_none() {
+            return Err(Error::LockTimeout);
+        }
+        if !st4.mute_lock_en {
+            let mut st4: regs::ST4 = self.read_reg()?;
+            st4.mute_lock_en = false;
+            self.write_reg(&st4)?;
+        }
+        Ok(())
+    }
+
+    /// Retunes to `f` without the RF outputs emitting while the PLL is unlocked
+    /// A retune larger than `guard.max_live_step` powers both output buffers down in the same
+    /// batch as the new divider settings, ahead of N, and asserts the mute pin if one is
+    /// attached. The buffers selected in `guard` are only enabled, and the mute pin restored,
+    /// once the PLL locks within `guard.lock_timeout_us`. On `LockTimeout` the buffers stay
+    /// down and the output muted
+    pub fn retune_guarded(&mut self, f: Hz, guard: &OutputGuard) -> Result<(), Error<SPI, LE>> {
+        let large = self.programmed_freq.is_none_or(|programmed| {
+            let step = match f > programmed {
+                true => f - programmed,
+                false => programmed - f,
+            };
+            step > guard.max_live_step
+        });
+        let muted = self.mute_asserted;
+        if large && self.mute.is_some() {
+            self.mute(true)?;
+        }
+        self.batched(|vco| {
+            if large {
+                let mut st1: regs::ST1 = vco.read_reg()?;
+                st1.rf1_out_pd = true;
+                vco.write_reg(&st1)?;
+                let mut st2: regs::ST2 = vco.read_reg()?;
+                st2.rf2_out_pd = true;
+                vco.write_reg(&st2)?;
+            }
+            vco.set_output_frequency(f)
+        })?;
+        if self.wait_for_lock(guard.lock_timeout_us)?.is_none() {
+            return Err(Error::LockTimeout);
+        }
+        // Only the buffers that change are written
+        self.batched(|vco| {
+            let mut st2: regs::ST2 = vco.read_reg()?;
+            if st2.rf2_out_pd == guard.rf2 {
+                st2.rf2_out_pd = !guard.rf2;
+                vco.write_reg(&st2)?;
+            }
+            let mut st1: regs::ST1 = vco.read_reg()?;
+            if st1.rf1_out_pd == guard.rf1 {
+                st1.rf1_out_pd = !guard.rf1;
+                vco.write_reg(&st1)?;
+            }
+            Ok(())
+        })?;
+        if large && self.mute.is_some() {
+            self.mute(muted)?;
+        }
+        Ok(())
+    }
+
+    /// Like `wait_for_lock`, reading the lock detector every `interval_us`
+    pub(crate) fn poll_lock(
+        &mut self,
+        interval_us: u32,
+        timeout_us: u32,
+    ) -> Result<Option<u32>, Error<SPI, LE>> {
+        // A zero interval would never reach the timeout
+        let interval_us = interval_us.max(1);
+        let mut elapsed = 0;
+        loop {
+            if self.is_locked()? {
+                debug!("locked after {} us", elapsed);
+                return Ok(Some(elapsed));
+            }
+            if elapsed >= timeout_us {
+                warning!("no lock after {} us", timeout_us);
+                return Ok(None);
+            }
+            self.delay_us(interval_us);
+            elapsed += interval_us;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::float::hz;
+    use crate::mock::{initialized_tester, tester};
+    use crate::registers::Register;
+    use embedded_hal_mock as mock;
+    use mock::pin::{Mock as PinMock, State as PinState, Transaction as PinTransaction};
+
+    #[test]
+    fn edges() {
+        let pin = PinMock::new(&[
+            PinTransaction::get(PinState::High),
+            PinTransaction::get(PinState::High),
+            PinTransaction::get(PinState::Low),
+        ]);
+        let mut ld = LockDetectPin::new(pin, false, false);
+        assert_eq!(ld.on_edge().unwrap(), Some(LockEvent::Locked));
+        assert_eq!(ld.on_edge().unwrap(), None);
+        assert!(ld.is_locked());
+        assert_eq!(ld.on_edge().unwrap(), Some(LockEvent::Unlocked));
+    }
+
+    #[test]
+    fn active_low() {
+        let pin = PinMock::new(&[PinTransaction::get(PinState::Low)]);
+        let mut ld = LockDetectPin::new(pin, true, false);
+        assert_eq!(ld.on_edge().unwrap(), Some(LockEvent::Locked));
+    }
+
+    #[test]
+    fn attach() {
+        let mut vco = tester();
+        vco.spi.write(7, 1 << 25);
+        let pin = PinMock::new(&[PinTransaction::get(PinState::Low)]);
+        let mut ld = vco.attach_lock_interrupt(pin, true).unwrap();
+        assert!(!ld.is_locked());
+        let st4: regs::ST4 = vco.read_reg().unwrap();
+        assert!(st4.ld_activelow);
+        let st7: regs::ST7 = vco.read_reg().unwrap();
+        assert!(!st7.ld_sdo_tristate);
+        assert_eq!(ld.on_edge().unwrap(), Some(LockEvent::Locked));
+    }
+
+    #[test]
+    fn retune_muted() {
+        let mut vco = initialized_tester();
+        assert!(matches!(
+            vco.retune_muted(hz(5_000_000_000), 100),
+            Err(Error::LockTimeout)
+        ));
+        assert!(vco.read_reg::<regs::ST4>().unwrap().mute_lock_en);
+
+        vco.spi.write(4, 0);
+        let st10 = regs::ST10 {
+            lock_det: true,
+            ..Default::default()
+        };
+        vco.spi.write(10, st10.encode());
+        vco.spi.take_writes();
+        vco.retune_muted(hz(6_000_000_000), 100).unwrap();
+        // Muting goes out with the new settings, ahead of N
+        assert_eq!(vco.spi.take_writes(), [4, 6, 2, 1, 0, 4]);
+        assert!(!vco.read_reg::<regs::ST4>().unwrap().mute_lock_en);
+        assert_eq!(
+            vco.get_output_frequency().unwrap(),
+            vco.programmed_frequency().unwrap()
+        );
+    }
+
+    #[test]
+    fn retune_guarded() {
+        let mut vco = initialized_tester();
+        let st10 = regs::ST10 {
+            lock_det: true,
+            ..Default::default()
+        };
+        vco.spi.write(10, st10.encode());
+        let guard = OutputGuard {
+            rf1: true,
+            rf2: false,
+            max_live_step: hz(1_000_000),
+            lock_timeout_us: 100,
+        };
+        vco.spi.take_writes();
+        // Nothing programmed yet, so the buffers go down with the divider and RF1 comes back
+        vco.retune_guarded(hz(5_000_000_000), &guard).unwrap();
+        let writes = vco.spi.take_writes();
+        assert_eq!(writes[writes.len() - 4..], [2, 1, 0, 1]);
+        assert!(vco.get_rf1_output().unwrap());
+        assert!(vco.read_reg::<regs::ST2>().unwrap().rf2_out_pd);
+
+        // A small step leaves the buffers alone
+        vco.retune_guarded(hz(5_000_500_000), &guard).unwrap();
+        assert_eq!(vco.spi.take_writes().last(), Some(&0));
+
+        // Without lock the buffers stay down
+        vco.spi.write(10, 0);
+        assert!(matches!(
+            vco.retune_guarded(hz(6_000_000_000), &guard),
+            Err(Error::LockTimeout)
+        ));
+        assert!(!vco.get_rf1_output().unwrap());
+    }
+}
@@ -0,0 +1,87 @@
+//! Logging through the `log` crate, compiled out without the `log` feature
+//!
+//! Register transactions are logged at the trace level, retunes and lock events at the debug
+//! level and failures at the warn level.
+
+/// Logs at the trace level if the `log` feature is enabled
+macro_rules! trace {
+    ($($arg:tt)+) => {{
+        #[cfg(feature = "log")]
+        log::trace!(target: "stuw81300", $($arg)+);
+        // Keeps the arguments used without the feature
+        #[cfg(not(feature = "log"))]
+        if false {
+            let _ = format_args!($($arg)+);
+        }
+    }};
+}
+
+/// Logs at the debug level if the `log` feature is enabled
+macro_rules! debug {
+    ($($arg:tt)+) => {{
+        #[cfg(feature = "log")]
+        log::debug!(target: "stuw81300", $($arg)+);
+        // Keeps the arguments used without the feature
+        #[cfg(not(feature = "log"))]
+        if false {
+            let _ = format_args!($($arg)+);
+        }
+    }};
+}
+
+/// Logs at the warn level if the `log` feature is enabled
+macro_rules! warning {
+    ($($arg:tt)+) => {{
+        #[cfg(feature = "log")]
+        log::warn!(target: "stuw81300", $($arg)+);
+        // Keeps the arguments used without the feature
+        #[cfg(not(feature = "log"))]
+        if false {
+            let _ = format_args!($($arg)+);
+        }
+    }};
+}
+
+pub(crate) use {debug, trace, warning};
+
+#[cfg(all(test, feature = "log"))]
+mod tests {
+    use crate::float::hz;
+    use crate::mock::initialized_tester;
+    use std::string::String;
+    use std::sync::Mutex;
+    use std::vec::Vec;
+
+    static RECORDS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+    struct Recorder;
+
+    impl log::Log for Recorder {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            if record.target() == "stuw81300" {
+                let line = format!("{} {}", record.level(), record.args());
+                RECORDS.lock().unwrap().push(line);
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[test]
+    fn records() {
+        log::set_logger(&Recorder).unwrap();
+        log::set_max_level(log::LevelFilter::Trace);
+        let mut vco = initialized_tester();
+        vco.set_output_frequency(hz(5_000_000_000)).unwrap();
+        // Other tests log concurrently, so only look for this test's records
+        let records = RECORDS.lock().unwrap();
+        assert!(records.iter().any(|r| r.starts_with("TRACE write ST3 ")));
+        assert!(records
+            .iter()
+            .any(|r| r.starts_with("DEBUG retuned to 5000000000")));
+    }
+}
@@ -0,0 +1,180 @@
+//! The synthesis arithmetic as free functions, for host tools and other drivers
+//!
+//! Everything works on whole Hz in integers, so the results are exact and the same in every
+//! build. The output frequency is `f_pfd * (N + FRAC / MOD)` at the N divider input, which is
+//! the VCO frequency, or half of it above 6 GHz (see `PllPath`).
+use crate::DsmOrder;
+use core::ops::RangeInclusive;
+
+/// Largest MOD the device accepts
+pub const MAX_MOD: u32 = 2_097_151;
+/// Highest VCO calibrator frequency in Hz
+pub const MAX_CALIBRATOR_FREQ: u64 = 250_000;
+/// Largest VCO calibrator division
+const MAX_CAL_DIV: u64 = 511;
+
+/// Integer part N of the divider ratio for `f_target` at the N divider input
+/// Panics if `f_pfd` is 0
+pub fn n_for(f_target: u64, f_pfd: u64) -> u32 {
+    (f_target / f_pfd) as u32
+}
+
+/// FRAC and MOD for the fractional part of the divider ratio for `f_target`
+/// The exact fraction in lowest terms is used if its denominator fits in `max_mod`, otherwise
+/// MOD is `max_mod` and FRAC is rounded to the nearest step, at most MOD - 1. MOD is at least
+/// 2, the smallest the device accepts. Dithering isn't accounted for, with it enabled the
+/// output sits half an LSB higher
+/// `None` if `f_pfd` is 0 or `max_mod` is outside `2..=MAX_MOD`
+pub fn frac_mod_for(f_target: u64, f_pfd: u64, max_mod: u32) -> Option<(u32, u32)> {
+    if f_pfd == 0 || !(2..=MAX_MOD).contains(&max_mod) {
+        return None;
+    }
+    let rem = f_target % f_pfd;
+    let gcd = gcd(rem, f_pfd);
+    let (frac, modu) = (rem / gcd, f_pfd / gcd);
+    if modu <= max_mod as u64 {
+        // An integer ratio reduces to 0 / 1
+        let scale = if modu < 2 { 2 } else { 1 };
+        return Some(((frac * scale) as u32, (modu * scale) as u32));
+    }
+    let frac = (rem as u128 * max_mod as u128 + f_pfd as u128 / 2) / f_pfd as u128;
+    Some((frac.min(max_mod as u128 - 1) as u32, max_mod))
+}
+
+/// Smallest VCO calibrator division that keeps the calibrator at or below 250 kHz
+/// `None` if `f_pfd` is too high for any division, see `Error::CalibratorFrequencyTooHigh`
+pub fn caldiv_for(f_pfd: u64) -> Option<u32> {
+    let div = f_pfd.div_ceil(MAX_CALIBRATOR_FREQ);
+    (div <= MAX_CAL_DIV).then_some(div as u32)
+}
+
+/// Divider ratios N the DSM supports at `order`, fractional ones only below the upper end
+/// Integer ratios of 512 and up bypass the DSM and work with every order
+pub fn n_range_for(order: DsmOrder) -> RangeInclusive<u32> {
+    match order {
+        DsmOrder::ThirdOrder => 27..=507,
+        DsmOrder::SecondOrder => 25..=509,
+        DsmOrder::FirstOrder => 24..=510,
+        DsmOrder::FourthOrder => 31..=503,
+    }
+}
+
+/// The DSM orders a divider ratio with integer part `n_int`, plus a fraction if `fractional`,
+/// is valid for, in the order of `DsmOrder::ALL`
+/// Integer ratios of 512 and up are valid for every order, fractional ones for none
+pub fn dsm_orders_for(n_int: u32, fractional: bool) -> impl Iterator<Item = DsmOrder> {
+    DsmOrder::ALL.into_iter().filter(move |&order| {
+        crate::api::check_divider_ratio(n_int, fractional, order as u32).is_ok()
+    })
+}
+
+/// The PFD frequency for `ref_path_sel` and divider `r` as the ratio `ref_freq * num / den`
+pub(crate) fn pfd_ratio(ref_path_sel: u32, r: u32) -> (u64, u64) {
+    let (num, den) = match ref_path_sel {
+        0 => (1, 1),
+        1 => (2, 1),
+        2 => (1, 2),
+        // 3, the field is only two bits wide
+        _ => (1, 4),
+    };
+    (num, den * r as u64)
+}
+
+/// Splits the divider ratio `f / (ref_freq * num / den)` into the integer part and the
+/// fractional part in units of half a FRAC LSB for `modu`, rounded down
+/// Returns `(n_int, half_lsbs, exact, fractional)` where `exact` is true if no rounding was
+/// needed and `fractional` is true if the ratio isn't an integer
+pub(crate) fn divider(
+    f: u64,
+    ref_freq: u64,
+    num: u64,
+    den: u64,
+    modu: u32,
+) -> (u32, u64, bool, bool) {
+    // N = f * den / (ref_freq * num)
+    let top = f as u128 * den as u128;
+    let bottom = ref_freq as u128 * num as u128;
+    let n_int = top / bottom;
+    let rem = top % bottom;
+    let half_lsbs = 2 * rem * modu as u128;
+    let exact = half_lsbs % bottom == 0;
+    (n_int as u32, (half_lsbs / bottom) as u64, exact, rem != 0)
+}
+
+/// Greatest common divisor, `b` if `a` is 0
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while a != 0 {
+        (a, b) = (b % a, a);
+    }
+    b
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn divider_ratio() {
+        // 5 GHz from a 50 MHz PFD is an integer ratio
+        assert_eq!(n_for(5_000_000_000, 50_000_000), 100);
+        assert_eq!(
+            frac_mod_for(5_000_000_000, 50_000_000, MAX_MOD),
+            Some((0, 2))
+        );
+        // 5.0125 GHz is 100 + 1/4
+        assert_eq!(n_for(5_012_500_000, 50_000_000), 100);
+        assert_eq!(
+            frac_mod_for(5_012_500_000, 50_000_000, MAX_MOD),
+            Some((1, 4))
+        );
+        // 1 Hz steps at 50 MHz don't fit MOD, so FRAC is rounded
+        let (frac, modu) = frac_mod_for(5_000_000_001, 50_000_000, MAX_MOD).unwrap();
+        assert_eq!((frac, modu), (0, MAX_MOD));
+        let (frac, modu) = frac_mod_for(5_000_000_013, 50_000_000, MAX_MOD).unwrap();
+        assert_eq!((frac, modu), (1, MAX_MOD));
+        // Fractions just below a whole step stay below MOD
+        assert_eq!(frac_mod_for(49_999_999, 50_000_000, 4), Some((3, 4)));
+        // MOD limits the device can't use
+        assert_eq!(frac_mod_for(5_000_000_001, 50_000_000, 1), None);
+        assert_eq!(frac_mod_for(5_000_000_001, 50_000_000, MAX_MOD + 1), None);
+        assert_eq!(frac_mod_for(5_000_000_001, 0, MAX_MOD), None);
+    }
+
+    #[test]
+    fn dsm_orders() {
+        assert_eq!(n_range_for(DsmOrder::ThirdOrder), 27..=507);
+        // Only the first order DSM reaches down to 24
+        assert!(dsm_orders_for(24, true).eq([DsmOrder::FirstOrder]));
+        // The upper end only takes an integer ratio
+        assert!(dsm_orders_for(507, false).eq([
+            DsmOrder::ThirdOrder,
+            DsmOrder::SecondOrder,
+            DsmOrder::FirstOrder
+        ]));
+        assert!(dsm_orders_for(507, true).eq([DsmOrder::SecondOrder, DsmOrder::FirstOrder]));
+        assert_eq!(dsm_orders_for(600, false).count(), 4);
+        assert_eq!(dsm_orders_for(600, true).count(), 0);
+    }
+
+    #[test]
+    fn divider_split() {
+        // 5 GHz from a 50 MHz PFD is exactly N = 100
+        assert_eq!(
+            divider(5_000_000_000, 100_000_000, 1, 2, 2097151),
+            (100, 0, true, false)
+        );
+        // 3151 MHz is N = 63.02, 0.02 * 2 * 2097151 = 83886.04 half LSBs
+        assert_eq!(
+            divider(3_151_000_000, 100_000_000, 1, 2, 2097151),
+            (63, 83886, false, true)
+        );
+    }
+
+    #[test]
+    fn calibrator() {
+        assert_eq!(caldiv_for(50_000_000), Some(200));
+        assert_eq!(caldiv_for(100_000_001), Some(401));
+        assert_eq!(caldiv_for(127_750_000), Some(511));
+        assert_eq!(caldiv_for(127_750_001), None);
+    }
+}
@@ -0,0 +1,515 @@
+//! Descriptions of every register and field, for tools that render the register map
+//!
+//! The layouts match `RegisterAddr::fields`, this adds the access mode, a short description
+//! and the meaning of each value for fields that select between settings.
+use crate::registers::RegisterAddr;
+
+/// Whether a register or field can be written
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Access {
+    /// Read and written over SPI
+    ReadWrite,
+    /// Status reported by the device, writes are ignored
+    ReadOnly,
+}
+
+/// The meaning of one value of a field
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct FieldValue {
+    /// Raw field value
+    pub value: u32,
+    /// What the value selects
+    pub meaning: &'static str,
+}
+
+/// Description of a single field
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct FieldInfo {
+    /// Lowercase datasheet name of the field
+    pub name: &'static str,
+    /// Position of the least significant bit
+    pub start: u8,
+    /// Width in bits, flags are a single bit
+    pub size: u8,
+    /// Whether the field can be written
+    pub access: Access,
+    /// What the field controls or reports
+    pub description: &'static str,
+    /// Meanings of the individual values, empty for plain numbers and flags
+    pub values: &'static [FieldValue],
+}
+
+/// Description of a register and its fields
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct RegisterInfo {
+    /// Address of the register
+    pub addr: RegisterAddr,
+    /// Whether the register can be written
+    pub access: Access,
+    /// What the register holds
+    pub description: &'static str,
+    /// The modeled fields, numeric fields first followed by flags like `RegisterAddr::fields`
+    pub fields: &'static [FieldInfo],
+}
+
+const fn rw(
+    name: &'static str,
+    start: u8,
+    size: u8,
+    description: &'static str,
+    values: &'static [FieldValue],
+) -> FieldInfo {
+    FieldInfo {
+        name,
+        start,
+        size,
+        access: Access::ReadWrite,
+        description,
+        values,
+    }
+}
+
+const fn ro(name: &'static str, start: u8, size: u8, description: &'static str) -> FieldInfo {
+    FieldInfo {
+        name,
+        start,
+        size,
+        access: Access::ReadOnly,
+        description,
+        values: &[],
+    }
+}
+
+const fn value(value: u32, meaning: &'static str) -> FieldValue {
+    FieldValue { value, meaning }
+}
+
+const DBR: &str = "Double buffering, the write takes effect on the next ST0 write";
+
+/// Every register, in ascending address order
+pub const REGISTERS: [RegisterInfo; 12] = [
+    RegisterInfo {
+        addr: RegisterAddr::ST0,
+        access: Access::ReadWrite,
+        description: "Master register. N divider, CP current",
+        fields: &[
+            rw(
+                "cp_sel",
+                21,
+                5,
+                "Charge pump current, in multiples of Imin",
+                &[],
+            ),
+            rw(
+                "pfd_del",
+                19,
+                2,
+                "PFD delay and matching charge pump leakage",
+                &[
+                    value(0, "1.2 ns / 0 A"),
+                    value(1, "1.9 ns / 0.25*Icp"),
+                    value(2, "2.5 ns / 0.5*Icp"),
+                    value(3, "3.0 ns / 0.75*Icp"),
+                ],
+            ),
+            rw(
+                "n",
+                0,
+                17,
+                "Integer part of the divider ratio, writing it starts VCO calibration",
+                &[],
+            ),
+        ],
+    },
+    RegisterInfo {
+        addr: RegisterAddr::ST1,
+        access: Access::ReadWrite,
+        description: "FRAC value, RF1 output control",
+        fields: &[
+            rw(
+                "frac",
+                0,
+                21,
+                "Fractional part of the divider ratio, below MOD",
+                &[],
+            ),
+            rw("dbr", 26, 1, DBR, &[]),
+            rw(
+                "rf1_out_pd",
+                24,
+                1,
+                "Powers down the RF1 output buffer",
+                &[],
+            ),
+            rw("man_calb_en", 23, 1, "Manual VCO calibration", &[]),
+            rw(
+                "pll_sel",
+                22,
+                1,
+                "Halves the VCO signal fed back to the PLL, needed above 6 GHz",
+                &[],
+            ),
+            rw(
+                "rf1_sel",
+                21,
+                1,
+                "RF1 signal",
+                &[value(0, "VCO output"), value(1, "VCO output divided by 2")],
+            ),
+        ],
+    },
+    RegisterInfo {
+        addr: RegisterAddr::ST2,
+        access: Access::ReadWrite,
+        description: "MOD value, RF2 output control",
+        fields: &[
+            rw("modu", 0, 21, "Fractional modulus MOD, 2 to 2097151", &[]),
+            rw("dbr", 26, 1, DBR, &[]),
+            rw(
+                "rf2_out_pd",
+                21,
+                1,
+                "Powers down the RF2 output buffer",
+                &[],
+            ),
+        ],
+    },
+    RegisterInfo {
+        addr: RegisterAddr::ST3,
+        access: Access::ReadWrite,
+        description:
+            "R divider, CP leakage, CP down-split pulse, Ref. path selection, Device power down",
+        fields: &[
+            rw("cp_leak", 19, 5, "Charge pump leakage current", &[]),
+            rw(
+                "pfd_del_mode",
+                15,
+                2,
+                "Path the PFD delay is applied to",
+                &[
+                    value(0, "No delay"),
+                    value(1, "VCO divider path"),
+                    value(2, "Reference divider path"),
+                ],
+            ),
+            rw(
+                "ref_path_sel",
+                13,
+                2,
+                "Reference path ahead of the R divider",
+                &[
+                    value(0, "Direct"),
+                    value(1, "Doubled"),
+                    value(2, "Halved"),
+                    value(3, "Quartered"),
+                ],
+            ),
+            rw("r", 0, 13, "Reference divider R, 1 to 8191", &[]),
+            rw("dbr", 26, 1, DBR, &[]),
+            rw("pd", 25, 1, "Powers down the device", &[]),
+            rw(
+                "cp_leak_x2",
+                24,
+                1,
+                "Doubles the charge pump leakage current",
+                &[],
+            ),
+            rw(
+                "cp_leak_dir",
+                18,
+                1,
+                "Direction of the charge pump leakage current",
+                &[],
+            ),
+            rw("dnsplit_en", 17, 1, "Charge pump down-split pulse", &[]),
+        ],
+    },
+    RegisterInfo {
+        addr: RegisterAddr::ST4,
+        access: Access::ReadWrite,
+        description:
+            "Lock det. control, Ref. Buffer, CP supply mode, VCO settings, output power control",
+        fields: &[
+            rw(
+                "vco_amp",
+                15,
+                3,
+                "VCO amplitude, up to 7 or 2 on a 3.3 V supply",
+                &[],
+            ),
+            rw(
+                "ref_buff_mode",
+                8,
+                2,
+                "Reference input buffer",
+                &[
+                    value(1, "Differential"),
+                    value(2, "Crystal"),
+                    value(3, "Single ended"),
+                ],
+            ),
+            rw("ld_prec", 3, 3, "Lock detector phase error window", &[]),
+            rw(
+                "ld_count",
+                0,
+                3,
+                "Lock detector count of PFD cycles in the window",
+                &[],
+            ),
+            rw(
+                "calb_3v3_mode1",
+                24,
+                1,
+                "Calibrator 3.3 V supply mode, bit 1",
+                &[],
+            ),
+            rw(
+                "rf_out_3v3",
+                23,
+                1,
+                "RF output buffers on a 3.3 V supply",
+                &[],
+            ),
+            rw("ext_vco_en", 19, 1, "External VCO", &[]),
+            rw(
+                "calb_3v3_mode0",
+                14,
+                1,
+                "Calibrator 3.3 V supply mode, bit 0",
+                &[],
+            ),
+            rw(
+                "vcalb_mode",
+                12,
+                1,
+                "VCO calibration mode for high frequencies or a 3.3 V supply",
+                &[],
+            ),
+            rw("kvco_comp_dis", 11, 1, "Disables KVCO compensation", &[]),
+            rw("pfd_pol", 10, 1, "Inverts the PFD polarity", &[]),
+            rw(
+                "mute_lock_en",
+                7,
+                1,
+                "Mutes the outputs until the PLL locks",
+                &[],
+            ),
+            rw(
+                "ld_activelow",
+                6,
+                1,
+                "Lock detect output is active low",
+                &[],
+            ),
+        ],
+    },
+    RegisterInfo {
+        addr: RegisterAddr::ST5,
+        access: Access::ReadWrite,
+        description: "Low power mode control bit",
+        fields: &[
+            rw(
+                "rf2_outbuf_lp",
+                4,
+                1,
+                "RF2 output buffer low power mode",
+                &[],
+            ),
+            rw("demux_lp", 2, 1, "Output demultiplexer low power mode", &[]),
+            rw("ref_buff_lp", 0, 1, "Reference buffer low power mode", &[]),
+        ],
+    },
+    RegisterInfo {
+        addr: RegisterAddr::ST6,
+        access: Access::ReadWrite,
+        description: "VCO calibrator, manual vco control, DSM settings",
+        fields: &[
+            rw(
+                "dsm_order",
+                22,
+                2,
+                "Delta-sigma modulator order",
+                &[
+                    value(0, "Third order"),
+                    value(1, "Second order"),
+                    value(2, "First order"),
+                    value(3, "Fourth order"),
+                ],
+            ),
+            rw(
+                "vco_sel",
+                18,
+                2,
+                "VCO core used with manual calibration",
+                &[],
+            ),
+            rw(
+                "vco_word",
+                13,
+                5,
+                "VCO band used with manual calibration",
+                &[],
+            ),
+            rw(
+                "prchg_del",
+                10,
+                2,
+                "VCO calibration precharge delay",
+                &[
+                    value(0, "1 cycle"),
+                    value(1, "2 cycles"),
+                    value(2, "3 cycles"),
+                    value(3, "4 cycles"),
+                ],
+            ),
+            rw(
+                "cal_div",
+                0,
+                9,
+                "VCO calibrator division, keeps the calibrator at or below 250 kHz",
+                &[],
+            ),
+            rw("dithering", 26, 1, "DSM dithering", &[]),
+            rw(
+                "en_autocal",
+                20,
+                1,
+                "Calibrates the VCO on every ST0 write",
+                &[],
+            ),
+            rw(
+                "cal_temp_comp",
+                12,
+                1,
+                "Temperature compensated VCO calibration",
+                &[],
+            ),
+            rw(
+                "cal_acc_en",
+                9,
+                1,
+                "VCO calibration accuracy enhancement",
+                &[],
+            ),
+        ],
+    },
+    RegisterInfo {
+        addr: RegisterAddr::ST7,
+        access: Access::ReadWrite,
+        description: "Fast lock control, LD_SDO settings",
+        fields: &[
+            rw(
+                "cp_sel_fl",
+                13,
+                5,
+                "Charge pump current during fast lock",
+                &[],
+            ),
+            rw("fstlck_cnt", 0, 13, "Fast lock duration in PFD cycles", &[]),
+            rw("ld_sdo_tristate", 25, 1, "Tristates the LD_SDO pin", &[]),
+            rw("ld_sdo_mode", 24, 1, "LD_SDO pin mode", &[]),
+            rw(
+                "spi_data_out_disable",
+                23,
+                1,
+                "Stops SPI read data on LD_SDO",
+                &[],
+            ),
+            rw("cycle_slip_en", 19, 1, "Cycle slip reduction", &[]),
+            rw("fstlck_en", 18, 1, "Fast lock", &[]),
+        ],
+    },
+    RegisterInfo {
+        addr: RegisterAddr::ST8,
+        access: Access::ReadWrite,
+        description: "LDO voltage regulator settings",
+        fields: &[
+            rw(
+                "reg_vco_4v5_vout",
+                0,
+                2,
+                "Output voltage of the VCO 4.5 V regulator",
+                &[],
+            ),
+            rw("pd_rf2_disable", 26, 1, "Disables the RF2 power down", &[]),
+        ],
+    },
+    RegisterInfo {
+        addr: RegisterAddr::ST9,
+        access: Access::ReadWrite,
+        description: "Reserved - Test and initialization bit",
+        fields: &[],
+    },
+    RegisterInfo {
+        addr: RegisterAddr::ST10,
+        access: Access::ReadOnly,
+        description: "VCO, lock det. status, LDO status",
+        fields: &[
+            ro("vco_sel", 5, 2, "VCO core picked by the calibrator"),
+            ro("word", 0, 5, "VCO band picked by the calibrator"),
+            ro("reg_dig_startup", 17, 1, "Digital regulator started up"),
+            ro("reg_ref_startup", 16, 1, "Reference regulator started up"),
+            ro("reg_rf_startup", 15, 1, "RF regulator started up"),
+            ro("reg_vco_startup", 14, 1, "VCO regulator started up"),
+            ro(
+                "reg_vco_4v5_startup",
+                13,
+                1,
+                "VCO 4.5 V regulator started up",
+            ),
+            ro("reg_dig_ocp", 12, 1, "Digital regulator overcurrent"),
+            ro("reg_ref_ocp", 11, 1, "Reference regulator overcurrent"),
+            ro("reg_rf_ocp", 10, 1, "RF regulator overcurrent"),
+            ro("reg_vco_ocp", 9, 1, "VCO regulator overcurrent"),
+            ro("reg_vco_4v5_ocp", 8, 1, "VCO 4.5 V regulator overcurrent"),
+            ro("lock_det", 7, 1, "PLL locked"),
+        ],
+    },
+    RegisterInfo {
+        addr: RegisterAddr::ST11,
+        access: Access::ReadOnly,
+        description: "Device ID",
+        fields: &[],
+    },
+];
+
+/// The description of the register at `addr`
+pub fn register(addr: RegisterAddr) -> &'static RegisterInfo {
+    &REGISTERS[addr as usize]
+}
+
+impl RegisterInfo {
+    /// Looks up a field by name
+    pub fn field(&self, name: &str) -> Option<&'static FieldInfo> {
+        self.fields.iter().find(|field| field.name == name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_layout() {
+        for addr in RegisterAddr::iter() {
+            let info = register(addr);
+            assert_eq!(info.addr, addr);
+            assert_eq!(info.access == Access::ReadOnly, addr.read_only());
+            let layout: Vec<_> = addr
+                .fields()
+                .iter()
+                .map(|f| (f.name, f.start, f.size))
+                .collect();
+            let described: Vec<_> = info
+                .fields
+                .iter()
+                .map(|f| (f.name, f.start, f.size))
+                .collect();
+            assert_eq!(layout, described, "{}", addr.name());
+            assert!(info.fields.iter().all(|f| f.access == info.access));
+        }
+        let path = register(RegisterAddr::ST3).field("ref_path_sel").unwrap();
+        assert_eq!(path.values[3].meaning, "Quartered");
+    }
+}
@@ -1,7 +1,113 @@
-///! Provides a mock SPI instance that behaves like the STuW81300
+//! Provides a mock SPI instance that behaves like the STuW81300
 use embedded_hal::blocking::spi::Transfer;
 use embedded_hal::digital::v2::OutputPin;
 
+/// Reference frequency used by the tests, 100 MHz
+pub const REF_FREQ: crate::Hz = crate::float::hz(100_000_000);
+
+/// Compares synthesized frequencies
+/// The default `f32` build rounds away the sub-LSB synthesis error so they match exactly,
+/// with `f64` and `no-float` they only match to within one FRAC step at a 50 MHz PFD
+macro_rules! assert_freq_eq {
+    ($left:expr, $right:expr) => {{
+        let (left, right): (crate::Hz, crate::Hz) = ($left, $right);
+        #[cfg(not(any(feature = "f64", feature = "no-float")))]
+        assert_eq!(left, right);
+        #[cfg(all(feature = "f64", not(feature = "no-float")))]
+        assert!((left - right).abs() < 25.0, "{} != {}", left, right);
+        #[cfg(feature = "no-float")]
+        assert!(left.abs_diff(right) < 25, "{} != {}", left, right);
+    }};
+}
+pub(crate) use assert_freq_eq;
+
+/// A driver on a mock with all registers zeroed, 5 V supply and a 100 MHz single ended
+/// reference
+pub fn tester() -> crate::STuW81300<MockStuw81300SPI, MockStuw81300LE> {
+    tester_with(crate::SupplyVoltage::HighVoltage, 100_000_000)
+}
+
+/// Like `tester`, with another supply voltage or reference frequency in whole Hz
+pub fn tester_with(
+    supply: crate::SupplyVoltage,
+    ref_freq: u64,
+) -> crate::STuW81300<MockStuw81300SPI, MockStuw81300LE> {
+    crate::STuW81300::new_hz(
+        MockStuw81300SPI::default(),
+        MockStuw81300LE::default(),
+        supply,
+        ref_freq,
+        crate::ReferenceType::SingleEnded,
+    )
+}
+
+/// `tester` after `init`, with R at 2 for a 50 MHz PFD
+pub fn initialized_tester() -> crate::STuW81300<MockStuw81300SPI, MockStuw81300LE> {
+    let mut vco = tester();
+    vco.init().unwrap();
+    vco.set_reference_clock_divider(2).unwrap();
+    vco
+}
+
+/// Non-zero power-on contents of ST0 to ST8 for `MockStuw81300SPI::new`
+/// These are representative settings chosen so read-modify-write setters see realistic
+/// neighbouring fields, not a copy of the datasheet reset table
+fn power_on() -> [u32; 12] {
+    use crate::registers::*;
+    let mut registers = [0; 12];
+    registers[0] = ST0 {
+        cp_sel: 16,
+        pfd_del: 1,
+        n: 100,
+    }
+    .encode();
+    registers[1] = ST1 {
+        frac: 1,
+        ..Default::default()
+    }
+    .encode();
+    registers[2] = ST2 {
+        modu: 2,
+        ..Default::default()
+    }
+    .encode();
+    registers[3] = ST3 {
+        r: 1,
+        cp_leak: 2,
+        pfd_del_mode: 1,
+        dnsplit_en: true,
+        ..Default::default()
+    }
+    .encode();
+    registers[4] = ST4 {
+        vco_amp: 2,
+        ref_buff_mode: 3,
+        ld_prec: 3,
+        ld_count: 3,
+        pfd_pol: true,
+        ..Default::default()
+    }
+    .encode();
+    registers[6] = ST6 {
+        cal_div: 200,
+        en_autocal: true,
+        cal_temp_comp: true,
+        ..Default::default()
+    }
+    .encode();
+    registers[7] = ST7 {
+        ld_sdo_mode: true,
+        ..Default::default()
+    }
+    .encode();
+    registers[8] = ST8 {
+        reg_vco_4v5_vout: 1,
+        ..Default::default()
+    }
+    .encode();
+    registers
+}
+
 enum MockPinState {
     High,
     Low,
@@ -9,6 +115,15 @@ enum MockPinState {
 
 pub struct MockStuw81300SPI {
     registers: [u32; 12],
+    /// Addresses written over the bus, in order
+    writes: Vec<usize>,
+    /// Addresses read over the bus, in order
+    reads: Vec<usize>,
+    /// ST0, ST1 and ST2 as the synthesizer uses them, double buffered writes to ST1 and ST2
+    /// only land here on the next ST0 write
+    active: [u32; 3],
+    /// Registers that ignore writes, bit n set for STn
+    frozen: u16,
 }
 
 pub struct MockStuw81300LE {
@@ -25,6 +140,62 @@ impl MockStuw81300SPI {
     pub fn write(&mut self, addr: usize, data: u32) {
         self.registers[addr] = data
     }
+    /// A mock reporting the ID of `variant`, starting from `power_on` instead of all zeros
+    pub fn new(variant: crate::DeviceVariant) -> Self {
+        let mut registers = power_on();
+        registers[11] = variant.id();
+        MockStuw81300SPI {
+            registers,
+            writes: Vec::new(),
+            reads: Vec::new(),
+            active: [registers[0], registers[1], registers[2]],
+            frozen: 0,
+        }
+    }
+
+    /// Makes register `addr` ignore writes from now on, like a device that doesn't take them
+    pub fn freeze(&mut self, addr: usize) {
+        self.frozen |= 1 << addr;
+    }
+
+    /// Returns and clears the addresses written over the bus so far
+    pub fn take_writes(&mut self) -> Vec<usize> {
+        std::mem::take(&mut self.writes)
+    }
+
+    /// Returns and clears the addresses read over the bus so far
+    pub fn take_reads(&mut self) -> Vec<usize> {
+        std::mem::take(&mut self.reads)
+    }
+
+    /// The RF1 frequency the device would generate from the registers written so far, for a
+    /// reference of `REF_FREQ`, or `None` while R or MOD is 0
+    /// Worked out from the datasheet in `f64`, independently of the driver's frequency math
+    pub fn simulated_output_hz(&self) -> Option<f64> {
+        use crate::registers::{Register, ST0, ST1, ST2, ST3, ST6};
+        let st0 = ST0::decode(self.active[0]);
+        let st1 = ST1::decode(self.active[1]);
+        let st2 = ST2::decode(self.active[2]);
+        let st3 = ST3::decode(self.registers[3]);
+        let st6 = ST6::decode(self.registers[6]);
+        if st3.r == 0 || st2.modu == 0 {
+            return None;
+        }
+        let reference = 100e6
+            * match st3.ref_path_sel {
+                0 => 1.0,
+                1 => 2.0,
+                2 => 0.5,
+                _ => 0.25,
+            };
+        let pfd = reference / st3.r as f64;
+        // Dithering offsets the DSM output by half an LSB of FRAC
+        let frac = st1.frac as f64 + if st6.dithering { 0.5 } else { 0.0 };
+        let n = st0.n as f64 + frac / st2.modu as f64;
+        // PLL_SEL feeds the divider with half the VCO frequency
+        let vco = pfd * n * if st1.pll_sel { 2.0 } else { 1.0 };
+        Some(if st1.rf1_sel { vco / 2.0 } else { vco })
+    }
 }
 
 impl Default for MockStuw81300SPI {
@@ -33,6 +204,10 @@ impl Default for MockStuw81300SPI {
             registers: [
                 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0u32, 0x0008052,
             ],
+            writes: Vec::new(),
+            reads: Vec::new(),
+            active: [0; 3],
+            frozen: 0,
         }
     }
 }
@@ -56,9 +231,21 @@ impl Transfer<u8> for MockStuw81300SPI {
         let addr = ((cmd >> 27) & 0b1111) as usize;
         let mut data = cmd & 0x7FFFFFF;
         if read {
+            self.reads.push(addr);
             data = self.read(addr);
         } else {
-            self.write(addr, data);
+            self.writes.push(addr);
+            // ST10 and ST11 are read-only, the device ignores writes to them
+            if addr < 10 && self.frozen & (1 << addr) == 0 {
+                self.write(addr, data);
+            }
+            // Writing N transfers the buffered ST1 and ST2, unbuffered writes apply at once
+            let dbr = data & (1 << 26) != 0;
+            match addr {
+                0 => self.active = [data, self.registers[1], self.registers[2]],
+                1 | 2 if !dbr => self.active[addr] = data,
+                _ => {}
+            }
             data = 0u32;
         }
         let data_buf = data.to_be_bytes();
@@ -93,4 +280,84 @@ mod tests {
         spi.transfer(&mut read).unwrap();
         assert_eq!(u32::from_be_bytes(read), 3);
     }
+
+    #[test]
+    fn variants() {
+        use crate::registers as regs;
+        use crate::DeviceVariant;
+        for variant in [DeviceVariant::Stuw81300T, DeviceVariant::Stuw81300T1] {
+            let mut vco = crate::STuW81300::new(
+                MockStuw81300SPI::new(variant),
+                MockStuw81300LE::default(),
+                crate::SupplyVoltage::LowVoltage,
+                REF_FREQ,
+                crate::ReferenceType::Crystal,
+            );
+            vco.init().unwrap();
+            assert_eq!(vco.variant(), Some(variant));
+            let st4: regs::ST4 = vco.read_reg().unwrap();
+            // Only the STUW81300T uses the 3.3 V calibrator modes
+            assert_eq!(st4.calb_3v3_mode0, variant == DeviceVariant::Stuw81300T);
+            assert_eq!(st4.ref_buff_mode, 2);
+            // Fields init doesn't own are left as they came up
+            assert_eq!(st4.ld_prec, 3);
+            assert!(st4.pfd_pol);
+        }
+    }
+
+    #[test]
+    fn read_modify_write() {
+        use crate::registers::{self as regs, Register};
+        let mut vco = crate::STuW81300::new(
+            MockStuw81300SPI::new(crate::DeviceVariant::Stuw81300T),
+            MockStuw81300LE::default(),
+            crate::SupplyVoltage::HighVoltage,
+            REF_FREQ,
+            crate::ReferenceType::SingleEnded,
+        );
+        vco.init().unwrap();
+        vco.set_reference_clock_divider(2).unwrap();
+        let st3: regs::ST3 = vco.read_reg().unwrap();
+        assert_eq!(st3.cp_leak, 2);
+        assert!(st3.dnsplit_en);
+        vco.set_output_frequency(crate::float::hz(5_000_000_000))
+            .unwrap();
+        let simulated = vco.spi.simulated_output_hz().unwrap();
+        assert!((simulated - 5e9).abs() < 1e3);
+        let before: regs::ST1 = vco.read_reg().unwrap();
+        vco.set_rf1_output(false).unwrap();
+        assert_eq!(
+            regs::ST1::decode(vco.spi.read(1)),
+            regs::ST1 {
+                rf1_out_pd: true,
+                ..before
+            }
+        );
+        assert!(vco.read_reg::<regs::ST6>().unwrap().cal_temp_comp);
+    }
+
+    #[test]
+    fn simulated_output() {
+        let mut vco = tester();
+        assert_eq!(vco.spi.simulated_output_hz(), None);
+        vco.init().unwrap();
+        vco.set_reference_clock_divider(2).unwrap();
+        for f in [
+            3_100_000_000u64,
+            5_000_000_000,
+            6_543_210_000,
+            7_900_000_000,
+        ] {
+            vco.set_output_frequency(crate::float::hz(f)).unwrap();
+            let simulated = vco.spi.simulated_output_hz().unwrap();
+            // Within the f32 resolution of the requested frequency
+            assert!((simulated - f as f64).abs() < 1e3, "{} != {}", simulated, f);
+        }
+        // Double buffered FRAC and MOD wait for N
+        vco.stage_output_frequency(crate::float::hz(5_000_000_000))
+            .unwrap();
+        assert!(vco.spi.simulated_output_hz().unwrap() > 7e9);
+        vco.latch().unwrap();
+        assert!((vco.spi.simulated_output_hz().unwrap() - 5e9).abs() < 1e3);
+    }
 }
@@ -0,0 +1,255 @@
+//! Datasheet ordering rules for programming several registers at once
+//!
+//! Bulk operations (`apply_config`, `set_output_frequency`, ...) hold their register writes
+//! back in a `WriteBatch` while they run, and send them afterwards in `WRITE_ORDER`:
+//! * ST9 first, the initialization register has to precede everything else
+//! * the configuration registers ST3 to ST8 next
+//! * MOD (ST2) before FRAC (ST1), so FRAC never applies to a stale MOD
+//! * ST0 last, writing N transfers double buffered ST1/ST2 and starts the VCO calibration,
+//!   which has to see the final settings
+//!
+//! Reads during the operation see the held back values, so read-modify-write setters compose.
+//!
+//! `defer_writes` extends the same idea across calls: writes only update the shadow registers
+//! until `flush` sends every register that changed, again in `WRITE_ORDER`.
+use crate::error::Error;
+use crate::registers::RegisterAddr;
+use crate::STuW81300;
+use embedded_hal as hal;
+use hal::blocking::spi::Transfer;
+use hal::digital::v2::OutputPin;
+
+/// The order writable registers are flushed in
+pub(crate) const WRITE_ORDER: [RegisterAddr; 10] = [
+    RegisterAddr::ST9,
+    RegisterAddr::ST3,
+    RegisterAddr::ST4,
+    RegisterAddr::ST5,
+    RegisterAddr::ST6,
+    RegisterAddr::ST7,
+    RegisterAddr::ST8,
+    RegisterAddr::ST2,
+    RegisterAddr::ST1,
+    RegisterAddr::ST0,
+];
+
+/// Register writes held back until the end of a bulk operation, at most one per register
+#[derive(Debug, Default, Clone)]
+pub(crate) struct WriteBatch {
+    pending: [Option<u32>; 12],
+}
+
+impl WriteBatch {
+    /// Records a write, replacing any earlier one to the same register
+    pub(crate) fn stage(&mut self, addr: RegisterAddr, data: u32) {
+        self.pending[addr as usize] = Some(data);
+    }
+
+    /// Replaces what is held back for `addr`, `None` holds nothing back
+    pub(crate) fn reset(&mut self, addr: RegisterAddr, data: Option<u32>) {
+        self.pending[addr as usize] = data;
+    }
+
+    /// The held back value of `addr`, if any
+    pub(crate) fn get(&self, addr: RegisterAddr) -> Option<u32> {
+        self.pending[addr as usize]
+    }
+
+    /// The held back writes in `WRITE_ORDER`
+    pub(crate) fn in_order(&self) -> impl Iterator<Item = (RegisterAddr, u32)> + '_ {
+        WRITE_ORDER
+            .into_iter()
+            .filter_map(|addr| Some((addr, self.get(addr)?)))
+    }
+}
+
+impl<SPI, LE, PD, MUTE, D, LD> STuW81300<SPI, LE, PD, MUTE, D, LD>
+where
+    SPI: Transfer<u8>,
+    LE: OutputPin,
+{
+    /// Runs `f` with register writes held back, then sends them in `WRITE_ORDER`
+    /// Nothing is written if `f` fails, and nested calls join the outer batch
+    pub(crate) fn batched<R>(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<R, Error<SPI, LE>>,
+    ) -> Result<R, Error<SPI, LE>> {
+        if self.batch.is_some() {
+            return f(self);
+        }
+        self.batched_words(f).map(|(value, _)| value)
+    }
+
+    /// Like `batched`, but also returns the writes that were sent
+    /// Must not be nested inside another batch
+    pub(crate) fn batched_words<R>(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<R, Error<SPI, LE>>,
+    ) -> Result<(R, WriteBatch), Error<SPI, LE>> {
+        self.batch = Some(WriteBatch::default());
+        let result = f(self).and_then(|value| {
+            // Checked while the batch is still pending, so rules spanning several registers
+            // see the final values and nothing is written if any of them fails
+            if self.strict {
+                self.check_batch()?;
+            }
+            Ok(value)
+        });
+        let batch = self.batch.take().unwrap_or_default();
+        let value = result?;
+        for (addr, data) in batch.in_order() {
+            self.write_unchecked(addr, data)?;
+        }
+        Ok((value, batch))
+    }
+
+    /// Enables or disables deferred writes, off by default
+    /// While enabled, setters only update the shadow registers (see `cached_reg`) and mark
+    /// them dirty, reads come from the shadow where it holds a value, and nothing is sent
+    /// until `flush`. In strict mode the checks also wait for `flush`
+    /// Disabling it doesn't send the pending writes, call `flush` as well
+    pub fn defer_writes(&mut self, defer: bool) {
+        self.deferred = defer;
+    }
+
+    /// Whether writes are deferred
+    pub fn is_deferred(&self) -> bool {
+        self.deferred
+    }
+
+    /// Registers with deferred writes that haven't been flushed, bit n set for STn
+    pub fn dirty_registers(&self) -> u16 {
+        self.dirty
+    }
+
+    /// Sends every dirty register in `WRITE_ORDER`, one write per register
+    /// In strict mode all of them are checked first and nothing is sent if any check fails
+    /// A bus error leaves the unsent registers dirty
+    pub fn flush(&mut self) -> Result<(), Error<SPI, LE>> {
+        let mut pending = WriteBatch::default();
+        for addr in WRITE_ORDER {
+            if let (true, Some(data)) = (self.is_dirty(addr), self.shadow[addr as usize]) {
+                pending.stage(addr, data);
+            }
+        }
+        if self.strict {
+            for (addr, data) in pending.in_order() {
+                self.check_write(addr, data)?;
+            }
+        }
+        let deferred = core::mem::replace(&mut self.deferred, false);
+        let result = pending.in_order().try_for_each(|(addr, data)| {
+            self.write_unchecked(addr, data)?;
+            self.dirty &= !(1 << addr as u16);
+            Ok(())
+        });
+        self.deferred = deferred;
+        result
+    }
+
+    pub(crate) fn is_dirty(&self, addr: RegisterAddr) -> bool {
+        self.dirty & (1 << addr as u16) != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::tester;
+    use embedded_hal_mock as mock;
+    use mock::pin::{Mock as PinMock, State as PinState, Transaction as PinTransaction};
+    use mock::spi::{Mock as SpiMock, Transaction as SpiTransaction};
+
+    #[test]
+    fn batch_order() {
+        let mut batch = WriteBatch::default();
+        batch.stage(RegisterAddr::ST0, 1);
+        batch.stage(RegisterAddr::ST1, 2);
+        batch.stage(RegisterAddr::ST6, 3);
+        batch.stage(RegisterAddr::ST2, 4);
+        batch.stage(RegisterAddr::ST1, 5);
+        let order: Vec<_> = batch.in_order().collect();
+        assert_eq!(
+            order,
+            [
+                (RegisterAddr::ST6, 3),
+                (RegisterAddr::ST2, 4),
+                (RegisterAddr::ST1, 5),
+                (RegisterAddr::ST0, 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn flushed_in_order() {
+        let spi = SpiMock::new(&[
+            SpiTransaction::transfer(vec![0x48, 0, 0, 0], vec![0; 4]),
+            SpiTransaction::transfer(vec![0x10, 0, 0, 3], vec![0; 4]),
+            SpiTransaction::transfer(vec![0x08, 0, 0, 2], vec![0; 4]),
+            SpiTransaction::transfer(vec![0x00, 0, 0, 1], vec![0; 4]),
+        ]);
+        let pin_expectations: Vec<_> = (0..4)
+            .flat_map(|_| {
+                [
+                    PinTransaction::set(PinState::Low),
+                    PinTransaction::set(PinState::High),
+                ]
+            })
+            .collect();
+        let le = PinMock::new(&pin_expectations);
+        let mut vco = STuW81300::new(
+            spi,
+            le,
+            crate::SupplyVoltage::HighVoltage,
+            crate::mock::REF_FREQ,
+            crate::ReferenceType::SingleEnded,
+        );
+        vco.batched(|vco| {
+            vco.write(RegisterAddr::ST0, 1)?;
+            vco.write(RegisterAddr::ST1, 2)?;
+            vco.write(RegisterAddr::ST2, 3)?;
+            vco.write(RegisterAddr::ST9, 0)?;
+            // Reads see the held back value without touching the bus
+            assert_eq!(vco.read(RegisterAddr::ST1)?, 2);
+            Ok(())
+        })
+        .unwrap();
+        vco.spi.done();
+        vco.le.done();
+    }
+
+    #[test]
+    fn failed_batch_writes_nothing() {
+        let mut vco = tester();
+        let result = vco.batched(|vco| {
+            vco.write(RegisterAddr::ST0, 1)?;
+            vco.write(RegisterAddr::ST11, 0)
+        });
+        assert!(matches!(result, Err(Error::ReadOnly)));
+        assert_eq!(vco.read(RegisterAddr::ST0).unwrap(), 0);
+    }
+
+    #[test]
+    fn deferred_flush() {
+        let mut vco = tester();
+        vco.init().unwrap();
+        vco.spi.take_writes();
+        vco.defer_writes(true);
+        vco.set_charge_pump(12).unwrap();
+        vco.set_reference_clock_divider(2).unwrap();
+        vco.set_output_frequency(crate::float::hz(5_000_000_000))
+            .unwrap();
+        vco.set_charge_pump(7).unwrap();
+        assert!(vco.spi.take_writes().is_empty());
+        assert_eq!(vco.get_charge_pump().unwrap(), 7);
+        assert_eq!(vco.spi.read(0), 0);
+        vco.flush().unwrap();
+        let writes = vco.spi.take_writes();
+        // Each dirty register is sent once, N last
+        assert_eq!(writes.last(), Some(&0));
+        assert_eq!(writes.iter().filter(|&&addr| addr == 0).count(), 1);
+        assert_eq!(vco.dirty_registers(), 0);
+        vco.defer_writes(false);
+        assert_eq!(vco.get_charge_pump().unwrap(), 7);
+    }
+}
@@ -0,0 +1,442 @@
+//! Reference settings planned together with the output frequency
+//!
+//! `set_output_frequency` keeps the reference path and R as configured and only plans the
+//! divider. `set_output_frequency_with` can choose the reference settings for each frequency
+//! as well, either for the highest PFD frequency, which lowers the in-band phase noise, or to
+//! keep the integer boundary spur away from the carrier.
+//!
+//! Each strategy is a `FrequencyPlanner`, which turns the target frequency and the current
+//! register image into a new image without touching the bus. The built-in ones run the
+//! driver's own planning on a scratch copy of the registers, other planners can choose FRAC,
+//! MOD and anything else freely. Only the registers the plan changes are written, N last.
+use crate::api::{check_divider_ratio, ReferenceClockPath};
+use crate::boot::RegisterPlan;
+use crate::error::Error;
+use crate::float::hz;
+use crate::interface::Interface;
+use crate::math;
+use crate::order::WRITE_ORDER;
+use crate::pll_path::PllPath;
+use crate::registers::{self as regs, RegisterAddr};
+use crate::rounding::Rounding;
+use crate::{Hz, NoPin, ReferenceType, STuW81300, SupplyVoltage};
+use core::convert::Infallible;
+use embedded_hal as hal;
+use hal::blocking::spi::Transfer;
+use hal::digital::v2::{InputPin, OutputPin};
+
+/// Highest PFD frequency the planner picks, in Hz
+const MAX_PFD_FREQ: u64 = 100_000_000;
+/// Largest R `PlanStrategy::SpurAvoid` tries on each reference path
+const MAX_SPUR_AVOID_R: u32 = 8;
+/// Every reference path, in order of preference when two give the same PFD frequency
+const PATHS: [ReferenceClockPath; 4] = [
+    ReferenceClockPath::Direct,
+    ReferenceClockPath::Doubled,
+    ReferenceClockPath::Halved,
+    ReferenceClockPath::Quartered,
+];
+
+/// How `set_output_frequency_with` plans the reference settings and the divider
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PlanStrategy {
+    /// The reference path and smallest R that give the highest PFD frequency up to 100 MHz,
+    /// for the lowest in-band phase noise
+    MaxPfd,
+    /// The current reference settings with the maximum MOD, like `set_output_frequency`
+    MaxMod,
+    /// The current reference settings with an exact FRAC / MOD, like `Rounding::Exact`
+    ExactGrid,
+    /// The reference settings, up to R = 8 on each path, that keep the integer boundary spur
+    /// furthest from the carrier, or avoid it with an integer ratio
+    SpurAvoid,
+    /// Like `MaxMod`, but divider ratios of 512 and up switch to integer mode, with FRAC 0 and
+    /// dithering off, instead of failing with `ValidationError::IntegerModeRequired`
+    /// The ratio is rounded to a whole N as set with `set_rounding`
+    AllowInteger,
+}
+
+/// What a `FrequencyPlanner` plans from
+#[derive(Debug, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PlanConstraints {
+    /// Reference frequency in Hz
+    pub ref_freq: u64,
+    /// Connection type of the reference, which limits the reference paths
+    pub ref_type: ReferenceType,
+    /// Supply voltage, which limits the VCO amplitude and sets the calibrator mode
+    pub supply_voltage: SupplyVoltage,
+    /// See `set_rounding`
+    pub rounding: Rounding,
+    /// The registers as they are now, and the variant whose RF1 range applies
+    pub current: RegisterPlan,
+}
+
+/// Works out the register settings for an output frequency, see `set_output_frequency_with`
+/// `PlanStrategy` holds the built-in planners, implement this to try other FRAC and MOD
+/// selections without changing the driver
+pub trait FrequencyPlanner {
+    /// The complete image of ST0 to ST8 for output frequency `target`, usually
+    /// `constraints.current` with some fields changed
+    /// Reject targets with `Error::FrequencyOutOfRange` or `Error::Validation`, the image
+    /// itself is written as is
+    fn plan<SPI: Transfer<u8>, LE: OutputPin>(
+        &self,
+        target: Hz,
+        constraints: &PlanConstraints,
+    ) -> Result<RegisterPlan, Error<SPI, LE>>;
+}
+
+impl<P: FrequencyPlanner> FrequencyPlanner for &P {
+    fn plan<SPI: Transfer<u8>, LE: OutputPin>(
+        &self,
+        target: Hz,
+        constraints: &PlanConstraints,
+    ) -> Result<RegisterPlan, Error<SPI, LE>> {
+        (*self).plan(target, constraints)
+    }
+}
+
+/// Bus of the scratch driver the built-in planners run on, every register lives in its shadow
+struct Scratch;
+
+impl Transfer<u8> for Scratch {
+    type Error = Infallible;
+
+    fn transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], Self::Error> {
+        Ok(words)
+    }
+}
+
+impl FrequencyPlanner for PlanStrategy {
+    fn plan<SPI: Transfer<u8>, LE: OutputPin>(
+        &self,
+        target: Hz,
+        constraints: &PlanConstraints,
+    ) -> Result<RegisterPlan, Error<SPI, LE>> {
+        let mut scratch = STuW81300::new_hz(
+            Scratch,
+            NoPin,
+            constraints.supply_voltage,
+            constraints.ref_freq,
+            constraints.ref_type,
+        );
+        let RegisterPlan {
+            variant,
+            mut registers,
+        } = constraints.current;
+        scratch.set_interface(Interface::WriteOnly { variant });
+        scratch.defer_writes(true);
+        for (addr, &data) in RegisterAddr::iter().zip(registers.iter()) {
+            scratch.shadow[addr as usize] = Some(data);
+        }
+        scratch.rounding = constraints.rounding;
+        scratch
+            .plan_with_strategy(target, *self)
+            .map_err(from_scratch)?;
+        for (addr, register) in RegisterAddr::iter().zip(registers.iter_mut()) {
+            *register = scratch.shadow[addr as usize].unwrap_or(*register);
+        }
+        Ok(RegisterPlan { variant, registers })
+    }
+}
+
+/// Carries an error of the scratch driver over, planning never touches its bus
+fn from_scratch<SPI: Transfer<u8>, LE: OutputPin>(error: Error<Scratch, NoPin>) -> Error<SPI, LE> {
+    match error {
+        Error::Transfer(never) | Error::LatchEnable(never) => match never {},
+        Error::FullRetuneRequired => Error::FullRetuneRequired,
+        Error::CalibratorFrequencyTooHigh => Error::CalibratorFrequencyTooHigh,
+        Error::ReadOnly => Error::ReadOnly,
+        Error::UnknownDevice(id) => Error::UnknownDevice(id),
+        Error::InvalidFieldValue => Error::InvalidFieldValue,
+        Error::PowerDownPin => Error::PowerDownPin,
+        Error::MutePin => Error::MutePin,
+        Error::LockDetectPin => Error::LockDetectPin,
+        Error::Unreadable(addr) => Error::Unreadable(addr),
+        Error::FrequencyOutOfRange { min, max } => Error::FrequencyOutOfRange { min, max },
+        Error::LockTimeout => Error::LockTimeout,
+        Error::WritesDeferred => Error::WritesDeferred,
+        Error::Validation(error) => Error::Validation(error),
+    }
+}
+
+impl<SPI, LE, PD, MUTE, D, LD> STuW81300<SPI, LE, PD, MUTE, D, LD>
+where
+    SPI: Transfer<u8>,
+    LE: OutputPin,
+    PD: OutputPin,
+    MUTE: OutputPin,
+    LD: InputPin,
+{
+    /// Sets the output frequency like `set_output_frequency`, planned by `planner`
+    /// With a `PlanStrategy`, `MaxPfd` and `SpurAvoid` also set the reference path and R,
+    /// written together with the divider. All but `ExactGrid` round as set with `set_rounding`
+    /// Only the registers the plan changes are written, in datasheet order, followed by N
+    pub fn set_output_frequency_with<P: FrequencyPlanner>(
+        &mut self,
+        f: Hz,
+        planner: P,
+    ) -> Result<(), Error<SPI, LE>> {
+        let current = self.register_plan()?;
+        let constraints = PlanConstraints {
+            ref_freq: self.ref_freq,
+            ref_type: self.ref_type,
+            supply_voltage: self.supply_voltage,
+            rounding: self.rounding,
+            current,
+        };
+        let plan = planner.plan(f, &constraints)?;
+        self.batched(|vco| {
+            for addr in WRITE_ORDER {
+                let (Some(&data), Some(&old)) = (
+                    plan.registers.get(addr as usize),
+                    current.registers.get(addr as usize),
+                ) else {
+                    continue;
+                };
+                // N always, it starts the VCO calibration
+                if data != old || addr == RegisterAddr::ST0 {
+                    vco.write(addr, data)?;
+                }
+            }
+            Ok(())
+        })?;
+        self.requested_freq = Some(f);
+        self.programmed_freq = match self.output_frequency_cached() {
+            Some(programmed) => Some(programmed),
+            None => Some(self.get_output_frequency()?),
+        };
+        Ok(())
+    }
+
+    /// Plans and writes output frequency `f` by `strategy`, on the scratch driver
+    fn plan_with_strategy(&mut self, f: Hz, strategy: PlanStrategy) -> Result<(), Error<SPI, LE>> {
+        let reference = match strategy {
+            PlanStrategy::MaxPfd => Some(self.max_pfd_reference()),
+            PlanStrategy::SpurAvoid => Some(self.spur_avoiding_reference(f)?),
+            PlanStrategy::MaxMod | PlanStrategy::ExactGrid | PlanStrategy::AllowInteger => None,
+        };
+        if strategy == PlanStrategy::ExactGrid {
+            self.rounding = Rounding::Exact;
+        }
+        self.batched(|vco| {
+            if let Some((path, r)) = reference {
+                // Both at once, the calibrator follows in `set_output_frequency`
+                let mut st3: regs::ST3 = vco.read_reg()?;
+                st3.ref_path_sel = path as u32;
+                st3.r = r;
+                vco.write_reg(&st3)?;
+                vco.forget_tuning();
+            }
+            vco.set_output_frequency_planned(f, strategy == PlanStrategy::AllowInteger)
+        })
+    }
+
+    /// The reference path and R for `PlanStrategy::MaxPfd`
+    /// Each supported path with the smallest R that keeps it at or below `MAX_PFD_FREQ`
+    fn max_pfd_reference(&self) -> (ReferenceClockPath, u32) {
+        let mut best = (ReferenceClockPath::Direct, 1, 0);
+        for path in PATHS {
+            if !self.reference_path_supported(path) {
+                continue;
+            }
+            let (num, den) = math::pfd_ratio(path as u32, 1);
+            let r = (self.ref_freq * num).div_ceil(den * MAX_PFD_FREQ).max(1);
+            let pfd = self.ref_freq * num / (den * r);
+            if pfd > best.2 {
+                best = (path, r as u32, pfd);
+            }
+        }
+        (best.0, best.1)
+    }
+
+    /// The reference path and R for `PlanStrategy::SpurAvoid`
+    /// Scores every candidate by the distance of the PFD harmonic nearest to the VCO, where the
+    /// integer boundary spur lands, and prefers the higher PFD frequency on a tie. Falls back
+    /// to `max_pfd_reference` if no candidate gives a valid divider ratio
+    fn spur_avoiding_reference(
+        &mut self,
+        f: Hz,
+    ) -> Result<(ReferenceClockPath, u32), Error<SPI, LE>> {
+        #[cfg(not(feature = "no-float"))]
+        let f = f as u64;
+        let st1: regs::ST1 = self.read_reg()?;
+        let st6: regs::ST6 = self.read_reg()?;
+        let vco = if st1.rf1_sel { 2 * f } else { f };
+        let pll_path = PllPath::for_vco(hz(vco));
+        // Score, PFD frequency, path and R of the best candidate so far
+        let mut best: Option<(u64, u64, ReferenceClockPath, u32)> = None;
+        for path in PATHS {
+            if !self.reference_path_supported(path) {
+                continue;
+            }
+            for r in 1..=MAX_SPUR_AVOID_R {
+                let (num, den) = math::pfd_ratio(path as u32, r);
+                let pfd = self.ref_freq * num / den;
+                if pfd > MAX_PFD_FREQ {
+                    continue;
+                }
+                // N = vco * den / (ref_freq * num), at the N divider input
+                let (top, bottom) = (vco * den, self.ref_freq * num * pll_path.divisor() as u64);
+                let n_int = math::n_for(top, bottom);
+                let rem = top % bottom;
+                let fractional = rem != 0;
+                if n_int > 512
+                    || (n_int == 512 && fractional)
+                    || check_divider_ratio(n_int, fractional, st6.dsm_order).is_err()
+                {
+                    continue;
+                }
+                // Distance to the nearest harmonic in Hz at the N divider input
+                let score = match fractional {
+                    true => {
+                        (rem.min(bottom - rem) as u128 * self.ref_freq as u128 * num as u128
+                            / (bottom as u128 * den as u128)) as u64
+                    }
+                    false => u64::MAX,
+                };
+                if best.is_none_or(|best| (score, pfd) > (best.0, best.1)) {
+                    best = Some((score, pfd, path, r));
+                }
+            }
+        }
+        Ok(match best {
+            Some((_, _, path, r)) => (path, r),
+            None => self.max_pfd_reference(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{tester_with, MockStuw81300LE, MockStuw81300SPI};
+
+    fn tester_at(ref_freq: u64) -> STuW81300<MockStuw81300SPI, MockStuw81300LE> {
+        let mut vco = tester_with(crate::SupplyVoltage::HighVoltage, ref_freq);
+        vco.init().unwrap();
+        vco.set_reference_clock_divider(4).unwrap();
+        vco
+    }
+
+    #[test]
+    fn max_pfd() {
+        // A 20 MHz reference can be doubled
+        let mut vco = tester_at(20_000_000);
+        vco.set_output_frequency_with(hz(5_000_000_000), PlanStrategy::MaxPfd)
+            .unwrap();
+        let st3: regs::ST3 = vco.read_reg().unwrap();
+        assert_eq!(st3.ref_path_sel, ReferenceClockPath::Doubled as u32);
+        assert_eq!(vco.get_pfd_frequency().unwrap(), hz(40_000_000));
+        // A 250 MHz reference has to be halved, 125 MHz is then too fast for R = 1
+        let mut vco = tester_at(250_000_000);
+        vco.set_output_frequency_with(hz(5_000_000_000), PlanStrategy::MaxPfd)
+            .unwrap();
+        assert_eq!(vco.get_pfd_frequency().unwrap(), hz(62_500_000));
+        let st0: regs::ST0 = vco.read_reg().unwrap();
+        assert_eq!(st0.n, 80);
+    }
+
+    #[test]
+    fn allow_integer() {
+        // 10 MHz PFD, 5.123 GHz is N = 512.3
+        let mut vco = tester_at(100_000_000);
+        vco.set_reference_clock_divider(10).unwrap();
+        assert!(matches!(
+            vco.set_output_frequency(hz(5_123_000_000)),
+            Err(Error::Validation(
+                crate::error::ValidationError::IntegerModeRequired { n: 512 }
+            ))
+        ));
+        vco.set_output_frequency_with(hz(5_123_000_000), PlanStrategy::AllowInteger)
+            .unwrap();
+        let st0: regs::ST0 = vco.read_reg().unwrap();
+        let st1: regs::ST1 = vco.read_reg().unwrap();
+        let st6: regs::ST6 = vco.read_reg().unwrap();
+        assert_eq!((st0.n, st1.frac, st6.dithering), (512, 0, false));
+        assert_eq!(vco.programmed_frequency(), Some(hz(5_120_000_000)));
+        // 5.126 GHz is nearer to N = 513
+        vco.set_output_frequency_with(hz(5_126_000_000), PlanStrategy::AllowInteger)
+            .unwrap();
+        assert_eq!(vco.spi.simulated_output_hz(), Some(5_130_000_000.0));
+        // Below 512 the DSM is still used
+        vco.set_output_frequency_with(hz(5_000_000_000), PlanStrategy::AllowInteger)
+            .unwrap();
+        let st6: regs::ST6 = vco.read_reg().unwrap();
+        assert!(st6.dithering);
+    }
+
+    /// Divider ratios on a grid of 1 / `self.0` with the current reference settings, rounded
+    /// down, direct reference and PLL paths only
+    struct CoarseGrid(u32);
+
+    impl FrequencyPlanner for CoarseGrid {
+        fn plan<SPI: Transfer<u8>, LE: OutputPin>(
+            &self,
+            target: Hz,
+            constraints: &PlanConstraints,
+        ) -> Result<RegisterPlan, Error<SPI, LE>> {
+            #[cfg(not(feature = "no-float"))]
+            let target = target as u64;
+            let mut plan = constraints.current;
+            let st3 = regs::ST3::from(plan.registers[3]);
+            let steps = target * self.0 as u64 * st3.r as u64 / constraints.ref_freq;
+            let mut st0 = regs::ST0::from(plan.registers[0]);
+            let mut st1 = regs::ST1::from(plan.registers[1]);
+            let mut st2 = regs::ST2::from(plan.registers[2]);
+            st0.n = (steps / self.0 as u64) as u32;
+            st1.frac = (steps % self.0 as u64) as u32;
+            st2.modu = self.0;
+            plan.registers[0] = st0.into();
+            plan.registers[1] = st1.into();
+            plan.registers[2] = st2.into();
+            Ok(plan)
+        }
+    }
+
+    #[test]
+    fn custom_planner() {
+        // 25 MHz PFD
+        let mut vco = tester_at(100_000_000);
+        vco.spi.take_writes();
+        vco.set_output_frequency_with(hz(5_012_800_000), CoarseGrid(1000))
+            .unwrap();
+        assert_eq!(vco.spi.take_writes(), [2, 1, 0]);
+        let st0: regs::ST0 = vco.read_reg().unwrap();
+        let st1: regs::ST1 = vco.read_reg().unwrap();
+        let st2: regs::ST2 = vco.read_reg().unwrap();
+        assert_eq!((st0.n, st1.frac, st2.modu), (200, 512, 1000));
+        assert_eq!(vco.requested_frequency(), Some(hz(5_012_800_000)));
+
+        // The built-in planners leave the driver's own settings alone
+        vco.set_output_frequency_with(hz(5_012_800_000), PlanStrategy::MaxPfd)
+            .unwrap();
+        assert_eq!(vco.get_pfd_frequency().unwrap(), hz(100_000_000));
+        assert_eq!(vco.rounding(), Rounding::Nearest);
+    }
+
+    #[test]
+    fn other_strategies() {
+        let mut vco = tester_at(100_000_000);
+        // 5.04 GHz is N = 252 at a 20 MHz PFD, without any fractional spurs
+        vco.set_output_frequency_with(hz(5_040_000_000), PlanStrategy::SpurAvoid)
+            .unwrap();
+        assert_eq!(vco.get_pfd_frequency().unwrap(), hz(20_000_000));
+        let st0: regs::ST0 = vco.read_reg().unwrap();
+        assert_eq!(st0.n, 252);
+
+        // The reference settings stay as they are
+        vco.set_output_frequency_with(hz(5_040_000_512), PlanStrategy::ExactGrid)
+            .unwrap();
+        assert_eq!(vco.get_pfd_frequency().unwrap(), hz(20_000_000));
+        let st2: regs::ST2 = vco.read_reg().unwrap();
+        assert_eq!(st2.modu, 78125);
+        assert_eq!(vco.rounding(), Rounding::Nearest);
+        vco.set_output_frequency_with(hz(5_040_000_512), PlanStrategy::MaxMod)
+            .unwrap();
+        let st2: regs::ST2 = vco.read_reg().unwrap();
+        assert_eq!(st2.modu, math::MAX_MOD);
+    }
+}
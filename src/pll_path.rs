@@ -0,0 +1,255 @@
+//! The PLL path, which feeds the VCO to the N divider either directly or halved
+//!
+//! The N divider only takes up to 6 GHz, so a faster VCO has to be halved on its way there
+//! (PLL_SEL in ST1). The divider ratio then sets half the VCO frequency, and every conversion
+//! between the ratio and the VCO or output frequency goes through `PllPath::divisor`, so the
+//! planner and the getters can't disagree about the factor.
+//!
+//! The tunable range is worked out for both paths as well, the halved one reaches VCO
+//! frequencies twice as high for the same N.
+use crate::api::DsmOrder;
+use crate::error::{Error, ValidationError};
+use crate::float::hz;
+use crate::math;
+use crate::registers as regs;
+use crate::{Hz, STuW81300};
+use embedded_hal as hal;
+use hal::blocking::spi::Transfer;
+use hal::digital::v2::{InputPin, OutputPin};
+
+/// Highest VCO frequency the N divider takes directly, in Hz
+const MAX_DIRECT_VCO_FREQ: u64 = 6_000_000_000;
+
+/// Signal path from the VCO to the N divider, PLL_SEL
+#[repr(u8)]
+#[derive(Debug, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PllPath {
+    /// The VCO drives the N divider, up to 6 GHz
+    Direct,
+    /// The VCO is halved for the N divider, needed above 6 GHz
+    Halved,
+}
+
+impl PllPath {
+    /// The path `set_output_frequency` picks for a VCO at `vco_freq`, `Halved` above 6 GHz
+    pub fn for_vco(vco_freq: Hz) -> PllPath {
+        match vco_freq > hz(MAX_DIRECT_VCO_FREQ) {
+            true => PllPath::Halved,
+            false => PllPath::Direct,
+        }
+    }
+
+    /// The VCO frequency over the frequency at the N divider input, 1 or 2
+    pub fn divisor(self) -> u32 {
+        match self {
+            PllPath::Direct => 1,
+            PllPath::Halved => 2,
+        }
+    }
+
+    /// Returns true if the N divider can follow a VCO at `vco_freq` on this path
+    pub fn supports(self, vco_freq: Hz) -> bool {
+        self == PllPath::Halved || vco_freq <= hz(MAX_DIRECT_VCO_FREQ)
+    }
+
+    /// The (min, max) VCO frequency in Hz `set_output_frequency` uses this path for
+    fn vco_range(self) -> (u64, u64) {
+        match self {
+            PllPath::Direct => (0, MAX_DIRECT_VCO_FREQ),
+            PllPath::Halved => (MAX_DIRECT_VCO_FREQ + 1, u64::MAX),
+        }
+    }
+
+    /// The path PLL_SEL selects in `st1`
+    pub(crate) fn of(st1: &regs::ST1) -> PllPath {
+        match st1.pll_sel {
+            false => PllPath::Direct,
+            true => PllPath::Halved,
+        }
+    }
+
+    /// Selects this path with PLL_SEL in `st1`
+    pub(crate) fn select(self, st1: &mut regs::ST1) {
+        st1.pll_sel = self == PllPath::Halved;
+    }
+}
+
+impl<SPI, LE, PD, MUTE, D, LD> STuW81300<SPI, LE, PD, MUTE, D, LD>
+where
+    SPI: Transfer<u8>,
+    LE: OutputPin,
+    PD: OutputPin,
+    MUTE: OutputPin,
+    LD: InputPin,
+{
+    /// Sets the signal path to the PLL, which `set_output_frequency` otherwise picks itself
+    /// N, FRAC and MOD stay as they are, so the VCO and output frequency move by a factor of 2
+    /// Returns `ValidationError::PllPathUnsupported` if that would leave the N divider on the
+    /// direct path with a VCO above 6 GHz
+    pub fn set_pll_path(&mut self, path: PllPath) -> Result<(), Error<SPI, LE>> {
+        let mut st1: regs::ST1 = self.read_reg()?;
+        path.select(&mut st1);
+        if let Some(vco_freq) = self.vco_frequency_with(&st1)? {
+            if !path.supports(vco_freq) {
+                return Err(ValidationError::PllPathUnsupported { path, vco_freq }.into());
+            }
+        }
+        self.write_reg(&st1)?;
+        self.forget_tuning();
+        Ok(())
+    }
+
+    /// Gets the signal path to the PLL
+    pub fn get_pll_path(&mut self) -> Result<PllPath, Error<SPI, LE>> {
+        let st1: regs::ST1 = self.read_reg()?;
+        Ok(PllPath::of(&st1))
+    }
+
+    /// The (min, max) output frequency in whole Hz `set_output_frequency` can reach with the
+    /// current reference settings, DSM order and RF1 path
+    /// N is limited to the fractional range of the DSM order on each PLL path. At low PFD
+    /// frequencies the two paths may leave a gap below 6 GHz at the VCO, which the range spans
+    /// Returns `None` while R is 0 or if no frequency in the RF1 range of the variant can be
+    /// reached
+    pub fn tunable_range(&mut self) -> Result<Option<(u64, u64)>, Error<SPI, LE>> {
+        let st1: regs::ST1 = self.read_reg()?;
+        let st3: regs::ST3 = self.read_reg()?;
+        let st6: regs::ST6 = self.read_reg()?;
+        if st3.r == 0 {
+            return Ok(None);
+        }
+        let (num, den) = math::pfd_ratio(st3.ref_path_sel, st3.r);
+        let n = math::n_range_for(DsmOrder::ALL[(st6.dsm_order & 0b11) as usize]);
+        let (min, max) = self.output_frequency_range();
+        #[cfg(not(feature = "no-float"))]
+        let (min, max) = (min as u64, max as u64);
+        let mut range: Option<(u64, u64)> = None;
+        for path in [PllPath::Direct, PllPath::Halved] {
+            let (low, high) = path.vco_range();
+            // The VCO runs at N * PFD * divisor
+            let step = self.ref_freq * num * path.divisor() as u64;
+            let low = low.max(min).max((*n.start() as u64 * step).div_ceil(den));
+            let high = high.min(max).min(*n.end() as u64 * step / den);
+            if low <= high {
+                range = Some(range.map_or((low, high), |(a, b)| (a.min(low), b.max(high))));
+            }
+        }
+        // The VCO runs at twice the output frequency on the divided RF1 path
+        let div = if st1.rf1_sel { 2 } else { 1 };
+        Ok(range.map(|(low, high)| (low.div_ceil(div), high / div)))
+    }
+
+    /// The VCO frequency the divider settings give with `st1`, `None` while R or MOD is 0
+    fn vco_frequency_with(&mut self, st1: &regs::ST1) -> Result<Option<Hz>, Error<SPI, LE>> {
+        let st0: regs::ST0 = self.read_reg()?;
+        let st2: regs::ST2 = self.read_reg()?;
+        let st3: regs::ST3 = self.read_reg()?;
+        let st6: regs::ST6 = self.read_reg()?;
+        if st3.r == 0 || st2.modu == 0 {
+            return Ok(None);
+        }
+        let direct = regs::ST1 {
+            rf1_sel: false,
+            ..*st1
+        };
+        Ok(Some(
+            self.output_frequency_of(&st0, &direct, &st2, &st3, &st6),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{assert_freq_eq, initialized_tester, tester};
+    use crate::Rf1Path;
+
+    #[test]
+    fn paths() {
+        assert_eq!(PllPath::for_vco(hz(6_000_000_000)), PllPath::Direct);
+        assert_eq!(PllPath::for_vco(hz(6_000_001_024)), PllPath::Halved);
+        assert!(!PllPath::Direct.supports(hz(6_000_001_024)));
+        assert!(PllPath::Halved.supports(hz(3_000_000_000)));
+        for path in [PllPath::Direct, PllPath::Halved] {
+            let mut st1 = regs::ST1::default();
+            path.select(&mut st1);
+            assert_eq!(PllPath::of(&st1), path);
+            assert_eq!(st1.pll_sel, path.divisor() == 2);
+        }
+    }
+
+    #[test]
+    fn set_get() {
+        let mut vco = initialized_tester();
+        for path in [PllPath::Halved, PllPath::Direct] {
+            vco.set_pll_path(path).unwrap();
+            assert_eq!(vco.get_pll_path().unwrap(), path);
+            assert_eq!(vco.divider_state().unwrap().pll_path, path);
+        }
+    }
+
+    #[test]
+    fn planned_paths() {
+        let mut vco = initialized_tester();
+        vco.set_output_frequency(hz(5_000_000_000)).unwrap();
+        assert_eq!(vco.get_pll_path().unwrap(), PllPath::Direct);
+        // Halving the VCO for the N divider doubles the output for the same N
+        vco.set_pll_path(PllPath::Halved).unwrap();
+        assert_freq_eq!(vco.get_output_frequency().unwrap(), hz(10_000_000_000));
+
+        vco.set_output_frequency(hz(7_000_000_000)).unwrap();
+        assert_eq!(vco.get_pll_path().unwrap(), PllPath::Halved);
+        assert_eq!(vco.read_reg::<regs::ST0>().unwrap().n, 70);
+        // Within the half LSB dithering adds, doubled by the halved path
+        let simulated = vco.spi.simulated_output_hz().unwrap();
+        assert!((simulated - 7_000_000_000.0).abs() < 50.0);
+        assert_freq_eq!(vco.get_output_frequency().unwrap(), hz(7_000_000_000));
+        // 3.5 GHz on the divided RF1 path is the same VCO frequency
+        vco.set_rf1_path(Rf1Path::Divided).unwrap();
+        vco.set_output_frequency(hz(3_500_000_000)).unwrap();
+        assert_eq!(vco.get_pll_path().unwrap(), PllPath::Halved);
+        // Back on the direct path N = 70 puts the VCO at 3.5 GHz, N = 130 would be 6.5 GHz
+        vco.set_n(130).unwrap();
+        assert!(matches!(
+            vco.set_pll_path(PllPath::Direct),
+            Err(Error::Validation(ValidationError::PllPathUnsupported {
+                path: PllPath::Direct,
+                ..
+            }))
+        ));
+        assert_eq!(vco.get_pll_path().unwrap(), PllPath::Halved);
+    }
+
+    #[test]
+    fn tunable_range() {
+        let mut vco = initialized_tester();
+        // A 50 MHz PFD covers the whole VCO range
+        assert_eq!(
+            vco.tunable_range().unwrap(),
+            Some((3_000_000_000, 8_000_000_000))
+        );
+        // At 6.25 MHz N = 507 tops out at 3.16875 GHz directly and 6.3375 GHz halved
+        vco.set_reference_clock_divider(16).unwrap();
+        assert_eq!(
+            vco.tunable_range().unwrap(),
+            Some((3_000_000_000, 6_337_500_000))
+        );
+        vco.set_dsm_order(DsmOrder::FourthOrder).unwrap();
+        vco.set_rf1_path(Rf1Path::Divided).unwrap();
+        assert_eq!(
+            vco.tunable_range().unwrap(),
+            Some((1_500_000_000, 3_143_750_000))
+        );
+        vco.set_reference_clock_divider(100).unwrap();
+        assert_eq!(vco.tunable_range().unwrap(), None);
+    }
+
+    #[test]
+    fn tunable_range_without_r() {
+        let mut vco = tester();
+        vco.init().unwrap();
+        assert_eq!(vco.read_reg::<regs::ST3>().unwrap().r, 0);
+        assert_eq!(vco.tunable_range().unwrap(), None);
+    }
+}
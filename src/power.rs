@@ -0,0 +1,206 @@
+//! Device power down control, through the optional hardware pin or the ST3 PD bit
+use crate::error::Error;
+use crate::registers as regs;
+use crate::STuW81300;
+use embedded_hal as hal;
+use hal::blocking::delay::DelayUs;
+use hal::blocking::spi::Transfer;
+use hal::digital::v2::{InputPin, OutputPin};
+
+/// Time for the internal regulators to settle after leaving power down, in microseconds
+pub(crate) const POWER_UP_SETTLE_US: u32 = 200;
+
+/// The overall power state of the device, from most to least powered
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PowerState {
+    /// Running with full power blocks and RF1 on
+    Active,
+    /// Running, but RF1 is muted by the mute pin or its output buffer is powered down
+    OutputsMuted,
+    /// Running with at least one of the ST5 low power modes enabled
+    LowPower,
+    /// Powered down by the hardware pin or the ST3 PD bit
+    PoweredDown,
+}
+
+impl<SPI, LE, PD, MUTE, D, LD> STuW81300<SPI, LE, PD, MUTE, D, LD>
+where
+    SPI: Transfer<u8>,
+    LE: OutputPin,
+{
+    /// Returns true if the device is powered down
+    /// With a power down pin attached this is the level last driven on it, otherwise the
+    /// ST3 PD bit is read
+    pub fn is_powered_down(&mut self) -> Result<bool, Error<SPI, LE>> {
+        if self.pd.is_some() {
+            return Ok(self.pd_asserted);
+        }
+        let st3: regs::ST3 = self.read_reg()?;
+        Ok(st3.pd)
+    }
+
+    /// Gets the power state of the device
+    /// When several apply the least powered state is reported, e.g. a powered down device
+    /// with muted outputs is `PoweredDown`
+    pub fn power_state(&mut self) -> Result<PowerState, Error<SPI, LE>> {
+        if self.is_powered_down()? {
+            return Ok(PowerState::PoweredDown);
+        }
+        let st5: regs::ST5 = self.read_reg()?;
+        if st5.rf2_outbuf_lp || st5.demux_lp || st5.ref_buff_lp {
+            return Ok(PowerState::LowPower);
+        }
+        let muted = if self.mute.is_some() {
+            self.mute_asserted
+        } else {
+            self.read_reg::<regs::ST1>()?.rf1_out_pd
+        };
+        Ok(if muted {
+            PowerState::OutputsMuted
+        } else {
+            PowerState::Active
+        })
+    }
+}
+
+impl<SPI, LE, PD, MUTE, D, LD> STuW81300<SPI, LE, PD, MUTE, D, LD>
+where
+    SPI: Transfer<u8>,
+    LE: OutputPin,
+    PD: OutputPin,
+    MUTE: OutputPin,
+    LD: InputPin,
+    D: DelayUs<u32>,
+{
+    /// Powers down the device
+    /// Drives the hardware power down pin high if one was attached with `with_pd_pin`,
+    /// otherwise sets the PD bit in ST3
+    pub fn power_down(&mut self) -> Result<(), Error<SPI, LE>> {
+        match self.pd.as_mut() {
+            Some(pd) => {
+                pd.set_high().map_err(|_| Error::PowerDownPin)?;
+                self.pd_asserted = true;
+                Ok(())
+            }
+            None => {
+                let mut st3: regs::ST3 = self.read_reg()?;
+                st3.pd = true;
+                self.write_reg(&st3)
+            }
+        }
+    }
+
+    /// Brings the device out of power down and waits for the regulators to settle
+    /// Only waits with a delay attached with `with_delay`. Without one this returns as soon as
+    /// the device is released, and the caller must wait 200 us before the next access
+    pub fn power_up(&mut self) -> Result<(), Error<SPI, LE>> {
+        self.release_power_down()?;
+        self.delay_us(POWER_UP_SETTLE_US);
+        Ok(())
+    }
+
+    /// Waits `us` microseconds on the attached delay, if any
+    pub(crate) fn delay_us(&mut self, us: u32) {
+        if let Some(delay) = self.delay.as_mut() {
+            delay.delay_us(us);
+        }
+    }
+}
+
+impl<SPI, LE, PD, MUTE, D, LD> STuW81300<SPI, LE, PD, MUTE, D, LD>
+where
+    SPI: Transfer<u8>,
+    LE: OutputPin,
+    PD: OutputPin,
+    MUTE: OutputPin,
+    LD: InputPin,
+{
+    /// Releases the hardware power down pin or clears the PD bit, without waiting
+    pub(crate) fn release_power_down(&mut self) -> Result<(), Error<SPI, LE>> {
+        match self.pd.as_mut() {
+            Some(pd) => {
+                pd.set_low().map_err(|_| Error::PowerDownPin)?;
+                self.pd_asserted = false;
+                Ok(())
+            }
+            None => {
+                let mut st3: regs::ST3 = self.read_reg()?;
+                st3.pd = false;
+                self.write_reg(&st3)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::tester;
+    use embedded_hal_mock as mock;
+    use mock::delay::MockNoop;
+    use mock::pin::{Mock as PinMock, State as PinState, Transaction as PinTransaction};
+
+    #[test]
+    fn register_power_down() {
+        let mut vco = tester();
+        vco.power_down().unwrap();
+        assert!(vco.read_reg::<regs::ST3>().unwrap().pd);
+        assert_eq!(vco.power_state().unwrap(), PowerState::PoweredDown);
+        vco.power_up().unwrap();
+        assert!(!vco.read_reg::<regs::ST3>().unwrap().pd);
+        assert!(!vco.is_powered_down().unwrap());
+    }
+
+    #[test]
+    fn power_state() {
+        let mut vco = tester();
+        assert_eq!(vco.power_state().unwrap(), PowerState::Active);
+        vco.mute(true).unwrap();
+        assert_eq!(vco.power_state().unwrap(), PowerState::OutputsMuted);
+        vco.write_reg(&regs::ST5 {
+            demux_lp: true,
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(vco.power_state().unwrap(), PowerState::LowPower);
+    }
+
+    #[test]
+    fn pin_power_down() {
+        let pd = PinMock::new(&[
+            PinTransaction::set(PinState::High),
+            PinTransaction::set(PinState::Low),
+            PinTransaction::set(PinState::Low),
+        ]);
+        let mut vco = tester().with_pd_pin(pd).with_delay(MockNoop::new());
+        vco.power_down().unwrap();
+        assert!(vco.is_powered_down().unwrap());
+        vco.power_up().unwrap();
+        // The register bit is left alone when the pin is used
+        assert!(!vco.read_reg::<regs::ST3>().unwrap().pd);
+        vco.init().unwrap();
+    }
+
+    /// Adds up the time waited
+    struct Waited(u32);
+
+    impl DelayUs<u32> for Waited {
+        fn delay_us(&mut self, us: u32) {
+            self.0 += us;
+        }
+    }
+
+    #[test]
+    fn init_waits_for_regulators() {
+        let pd = PinMock::new(&[
+            PinTransaction::set(PinState::High),
+            PinTransaction::set(PinState::Low),
+        ]);
+        let mut vco = tester().with_pd_pin(pd).with_delay(Waited(0));
+        vco.power_down().unwrap();
+        vco.init().unwrap();
+        assert!(!vco.is_powered_down().unwrap());
+        assert_eq!(vco.delay.as_ref().unwrap().0, POWER_UP_SETTLE_US);
+    }
+}
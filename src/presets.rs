@@ -0,0 +1,95 @@
+//! Known-good starting points for `Config`
+//!
+//! Every preset is a plain `Config`, so individual settings can be changed with struct
+//! update syntax before applying it:
+//!
+//! ```ignore
+//! vco.apply_config(&Config {
+//!     reference_divider: 4,
+//!     ..presets::low_phase_noise_5v()
+//! })?;
+//! ```
+//!
+//! The reference path and divider are board specific and left at their defaults (direct,
+//! divide by 1), which suits a 25 to 200 MHz reference.
+use crate::api::{DsmOrder, PfdDelay, PfdDelayMode, PrechargeDelay};
+use crate::config::{Calibration, Config};
+use crate::fast_lock::FastLock;
+use crate::lock::LockDetector;
+
+/// Lowest phase noise with a 5 V supply (`SupplyVoltage::HighVoltage`)
+/// Maximum charge pump current and VCO amplitude, third order DSM with dithering and the
+/// most robust calibration
+pub fn low_phase_noise_5v() -> Config {
+    Config {
+        dsm_order: DsmOrder::ThirdOrder,
+        dithering: true,
+        pfd_delay: PfdDelay::Default,
+        pfd_delay_mode: PfdDelayMode::VcoDivDelay,
+        charge_pump: 31,
+        vco_amplitude: 7,
+        ..Default::default()
+    }
+}
+
+/// Reduced power consumption with a 3.3 V supply (`SupplyVoltage::LowVoltage`)
+/// Smallest VCO amplitude and a reduced charge pump current, at the cost of phase noise
+pub fn low_power_3v3() -> Config {
+    Config {
+        charge_pump: 15,
+        vco_amplitude: 0,
+        ..Default::default()
+    }
+}
+
+/// Fast frequency hopping, valid for either supply
+/// Fast lock at the full charge pump current with cycle slip reduction for large jumps, a
+/// short calibration precharge and a quicker lock detector
+pub fn fast_hopping() -> Config {
+    Config {
+        calibration: Calibration {
+            precharge_delay: PrechargeDelay::One,
+            ..Default::default()
+        },
+        lock_detector: LockDetector {
+            precision: 3,
+            count: 1,
+        },
+        fast_lock: Some(FastLock {
+            charge_pump: 31,
+            count: 1000,
+            cycle_slip_reduction: true,
+        }),
+        charge_pump: 15,
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::float::hz;
+    use crate::mock::{assert_freq_eq, tester_with};
+    use crate::SupplyVoltage;
+
+    #[test]
+    fn presets_apply() {
+        for (supply, config) in [
+            (SupplyVoltage::HighVoltage, low_phase_noise_5v()),
+            (SupplyVoltage::LowVoltage, low_power_3v3()),
+            (SupplyVoltage::LowVoltage, fast_hopping()),
+            (SupplyVoltage::HighVoltage, fast_hopping()),
+        ] {
+            let mut vco = tester_with(supply, 100_000_000);
+            vco.init().unwrap();
+            vco.apply_config(&Config {
+                reference_divider: 2,
+                ..config
+            })
+            .unwrap();
+            assert_eq!(vco.get_fast_lock().unwrap(), config.fast_lock);
+            vco.set_output_frequency(hz(5_000_000_000)).unwrap();
+            assert_freq_eq!(vco.get_output_frequency().unwrap(), hz(5_000_000_000));
+        }
+    }
+}
@@ -0,0 +1,210 @@
+//! Programming and verifying a device in one step, e.g. on a factory test fixture
+use crate::config::Config;
+use crate::error::Error;
+use crate::registers::RegisterAddr;
+use crate::self_test::SelfTestReport;
+use crate::{Hz, STuW81300};
+use embedded_hal as hal;
+use hal::blocking::delay::DelayUs;
+use hal::blocking::spi::Transfer;
+use hal::digital::v2::{InputPin, OutputPin};
+
+/// Everything `program_and_verify` needs to bring up and check a device
+#[derive(Debug, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ProductionProfile {
+    /// Settings to program
+    pub config: Config,
+    /// Output frequency to program after `config`, in Hz
+    pub output_frequency: Hz,
+    /// ID the device must report, see `DeviceVariant::id`
+    pub expected_device_id: u32,
+    /// Run `self_test` before programming
+    pub self_test: bool,
+    /// Read every programmed register back and compare it
+    pub read_back: bool,
+    /// How long to wait for lock after programming in microseconds, `None` skips the check
+    pub lock_timeout_us: Option<u32>,
+}
+
+/// Outcome of `program_and_verify`
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ProductionReport {
+    /// The ID read from ST11
+    pub device_id: u32,
+    /// The ID matched the profile
+    pub device_id_ok: bool,
+    /// Result of `self_test`, `None` if it was disabled
+    pub self_test: Option<SelfTestReport>,
+    /// The profile was programmed, programming is skipped if an earlier check failed
+    pub programmed: bool,
+    /// Registers that read back differently, bit n set for STn
+    /// `None` if read back was disabled or nothing was programmed
+    pub read_back_mismatches: Option<u16>,
+    /// Whether the PLL locked within the timeout, `None` if the check was skipped
+    pub locked: Option<bool>,
+    /// Time until lock in microseconds, to within the poll interval
+    pub lock_time_us: Option<u32>,
+}
+
+impl ProductionReport {
+    /// Returns true if every enabled check passed
+    pub fn passed(&self) -> bool {
+        self.device_id_ok
+            && self.self_test.is_none_or(|report| report.passed())
+            && self.programmed
+            && self.read_back_mismatches.is_none_or(|mask| mask == 0)
+            && self.locked != Some(false)
+    }
+}
+
+impl<SPI, LE, PD, MUTE, D, LD> STuW81300<SPI, LE, PD, MUTE, D, LD>
+where
+    SPI: Transfer<u8>,
+    LE: OutputPin,
+    PD: OutputPin,
+    MUTE: OutputPin,
+    LD: InputPin,
+    D: DelayUs<u32>,
+{
+    /// Checks the device ID, optionally runs `self_test`, then initializes the device, applies
+    /// the profile's configuration and output frequency and verifies the result
+    /// Failed checks are reported in the result, only bus errors return `Err`. The registers
+    /// are read back from the device, not the shadow, so this needs SDO
+    /// Returns `Error::WritesDeferred` without touching the bus while writes are deferred,
+    /// since nothing would be programmed
+    /// Waiting for lock needs a delay attached with `with_delay`, without one the lock state
+    /// is polled back to back and the timeout becomes a number of polls
+    pub fn program_and_verify(
+        &mut self,
+        profile: &ProductionProfile,
+    ) -> Result<ProductionReport, Error<SPI, LE>> {
+        if self.deferred {
+            return Err(Error::WritesDeferred);
+        }
+        let device_id = self.device_id()?;
+        let mut report = ProductionReport {
+            device_id,
+            device_id_ok: device_id == profile.expected_device_id,
+            self_test: None,
+            programmed: false,
+            read_back_mismatches: None,
+            locked: None,
+            lock_time_us: None,
+        };
+        if !report.device_id_ok {
+            return Ok(report);
+        }
+        if profile.self_test {
+            let self_test = self.self_test()?;
+            report.self_test = Some(self_test);
+            if !self_test.passed() {
+                return Ok(report);
+            }
+        }
+
+        self.init()?;
+        let (_, written) = self.batched_words(|vco| {
+            vco.apply_config(&profile.config)?;
+            vco.set_output_frequency(profile.output_frequency)
+        })?;
+        report.programmed = true;
+
+        if profile.read_back {
+            let mut mismatches = 0;
+            for (addr, data) in written.in_order() {
+                // ST9 is a reserved test register, it isn't guaranteed to read back
+                if addr != RegisterAddr::ST9 && self.read_device(addr)? != data {
+                    mismatches |= 1 << addr as u16;
+                }
+            }
+            report.read_back_mismatches = Some(mismatches);
+        }
+
+        if let Some(timeout) = profile.lock_timeout_us {
+            let lock_time = self.wait_for_lock(timeout)?;
+            report.locked = Some(lock_time.is_some());
+            report.lock_time_us = lock_time;
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::float::hz;
+    use crate::mock::tester;
+    use crate::registers::{self as regs, Register};
+
+    fn profile() -> ProductionProfile {
+        ProductionProfile {
+            config: Config {
+                reference_divider: 2,
+                ..Default::default()
+            },
+            output_frequency: hz(5_000_000_000),
+            expected_device_id: 0x8052,
+            self_test: true,
+            read_back: true,
+            lock_timeout_us: Some(100),
+        }
+    }
+
+    #[test]
+    fn good_device() {
+        let mut vco = tester();
+        let st10 = regs::ST10 {
+            lock_det: true,
+            ..Default::default()
+        };
+        vco.spi.write(10, st10.encode());
+        let report = vco.program_and_verify(&profile()).unwrap();
+        assert!(report.passed());
+        assert_eq!(report.read_back_mismatches, Some(0));
+        assert_eq!(report.lock_time_us, Some(0));
+        assert_eq!(vco.get_charge_pump().unwrap(), 31);
+    }
+
+    #[test]
+    fn no_lock() {
+        let mut vco = tester();
+        let report = vco.program_and_verify(&profile()).unwrap();
+        assert!(report.programmed);
+        assert_eq!(report.locked, Some(false));
+        assert!(!report.passed());
+    }
+
+    #[test]
+    fn reads_back_from_the_device() {
+        let mut vco = tester();
+        vco.set_cached_reads(true);
+        vco.spi.freeze(4);
+        let report = vco.program_and_verify(&profile()).unwrap();
+        assert_eq!(report.read_back_mismatches, Some(1 << 4));
+
+        let mut vco = tester();
+        vco.defer_writes(true);
+        assert!(matches!(
+            vco.program_and_verify(&profile()),
+            Err(Error::WritesDeferred)
+        ));
+        assert!(vco.spi.take_writes().is_empty());
+    }
+
+    #[test]
+    fn wrong_device() {
+        let mut vco = tester();
+        let report = vco
+            .program_and_verify(&ProductionProfile {
+                expected_device_id: 0x804B,
+                ..profile()
+            })
+            .unwrap();
+        assert!(!report.device_id_ok);
+        assert!(!report.programmed);
+        // Nothing was written
+        assert_eq!(vco.spi.read(0), 0);
+    }
+}
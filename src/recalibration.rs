@@ -0,0 +1,289 @@
+//! Recalibrating the VCO after the device temperature has drifted
+//!
+//! The VCO band is picked by the calibrator when N is written. With `cal_temp_comp` set the
+//! chosen band holds lock over the full temperature range, without it a large temperature
+//! change since the last calibration can push the VCO out of its band. A periodic task can
+//! feed a temperature sensor into a `TemperatureTracker` and call `recalibrate` whenever it
+//! reports a large enough change.
+use crate::error::{Error, ValidationError};
+use crate::registers::{self as regs, RegisterAddr};
+use crate::STuW81300;
+use embedded_hal as hal;
+use hal::blocking::delay::DelayUs;
+use hal::blocking::spi::Transfer;
+use hal::digital::v2::{InputPin, OutputPin};
+
+/// Default temperature change that calls for a recalibration, in millidegrees Celsius
+pub const RECALIBRATION_THRESHOLD_MC: u32 = 20_000;
+
+/// Tracks the temperature at the last calibration and flags when it has drifted too far
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct TemperatureTracker {
+    threshold_mc: u32,
+    calibrated_at_mc: Option<i32>,
+}
+
+impl Default for TemperatureTracker {
+    /// Flags a recalibration every `RECALIBRATION_THRESHOLD_MC`
+    fn default() -> Self {
+        TemperatureTracker::new(RECALIBRATION_THRESHOLD_MC)
+    }
+}
+
+impl TemperatureTracker {
+    /// Flags a recalibration once the temperature moves `threshold_mc` millidegrees away from
+    /// the temperature at the last calibration
+    pub fn new(threshold_mc: u32) -> Self {
+        TemperatureTracker {
+            threshold_mc,
+            calibrated_at_mc: None,
+        }
+    }
+
+    /// Records a calibration done at `temp_mc`
+    pub fn calibrated(&mut self, temp_mc: i32) {
+        self.calibrated_at_mc = Some(temp_mc);
+    }
+
+    /// Returns true if the device should be recalibrated at `temp_mc`
+    /// The first reading seeds the tracker, as `init` or a frequency change just calibrated
+    pub fn needs_recalibration(&mut self, temp_mc: i32) -> bool {
+        match self.calibrated_at_mc {
+            Some(at) => temp_mc.abs_diff(at) >= self.threshold_mc,
+            None => {
+                self.calibrated_at_mc = Some(temp_mc);
+                false
+            }
+        }
+    }
+}
+
+/// Largest VCO core, the core field is 2 bits wide
+const MAX_CORE: u32 = 3;
+/// Largest VCO word, the word field is 5 bits wide
+const MAX_WORD: u32 = 31;
+
+/// A VCO core and band picked by the calibrator, see `capture_vco_word`
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct VcoWord {
+    /// VCO core, ST10 VCO_SEL
+    pub core: u32,
+    /// Band within the core, ST10 WORD
+    pub word: u32,
+}
+
+impl<SPI, LE, PD, MUTE, D, LD> STuW81300<SPI, LE, PD, MUTE, D, LD>
+where
+    SPI: Transfer<u8>,
+    LE: OutputPin,
+{
+    /// Reads back the VCO core and word the last calibration picked
+    /// Store it per frequency to skip calibration later with `preload_vco_word`, which only
+    /// holds lock at about the temperature it was captured at
+    pub fn capture_vco_word(&mut self) -> Result<VcoWord, Error<SPI, LE>> {
+        let st10: regs::ST10 = self.read_reg()?;
+        Ok(VcoWord {
+            core: st10.vco_sel,
+            word: st10.word,
+        })
+    }
+
+    /// Selects the VCO `core` and `word` directly and enables manual calibration
+    /// Writing N then retunes without running the calibrator, so a hop between known
+    /// frequencies only takes the SPI writes and the PLL settling. Preload the word captured
+    /// at the new frequency before retuning, see `capture_vco_word`
+    pub fn preload_vco_word(&mut self, core: u32, word: u32) -> Result<(), Error<SPI, LE>> {
+        if core > MAX_CORE {
+            return Err(ValidationError::VcoCoreOutOfRange {
+                given: core,
+                max: MAX_CORE,
+            }
+            .into());
+        }
+        if word > MAX_WORD {
+            return Err(ValidationError::VcoWordOutOfRange {
+                given: word,
+                max: MAX_WORD,
+            }
+            .into());
+        }
+        self.batched(|vco| {
+            let mut st6: regs::ST6 = vco.read_reg()?;
+            st6.vco_sel = core;
+            st6.vco_word = word;
+            vco.write_reg(&st6)?;
+            vco.set_manual_calibration(true)
+        })
+    }
+
+    /// Enables or disables manual calibration
+    /// While enabled the VCO runs on the core and word in ST6 instead of calibrating on every
+    /// N write, see `preload_vco_word`
+    pub fn set_manual_calibration(&mut self, enabled: bool) -> Result<(), Error<SPI, LE>> {
+        let mut st1: regs::ST1 = self.read_reg()?;
+        st1.man_calb_en = enabled;
+        self.write_reg(&st1)
+    }
+
+    /// Gets if manual calibration is enabled
+    pub fn get_manual_calibration(&mut self) -> Result<bool, Error<SPI, LE>> {
+        let st1: regs::ST1 = self.read_reg()?;
+        Ok(st1.man_calb_en)
+    }
+}
+
+impl<SPI, LE, PD, MUTE, D, LD> STuW81300<SPI, LE, PD, MUTE, D, LD>
+where
+    SPI: Transfer<u8>,
+    LE: OutputPin,
+    PD: OutputPin,
+    MUTE: OutputPin,
+    LD: InputPin,
+    D: DelayUs<u32>,
+{
+    /// Reruns VCO calibration at the current frequency and returns the new VCO word
+    /// ST0 is rewritten unchanged, with autocalibration enabled and manual calibration
+    /// disabled for the write. Manual calibration is enabled again once locked, with the new
+    /// word preloaded in ST6
+    /// If a mute pin is attached the output is muted until the PLL locks again, otherwise
+    /// `mute_lock_en` in ST4 (if set) has the device do the same on its own
+    /// On `LockTimeout` the output is left muted and manual calibration disabled
+    pub fn recalibrate(&mut self, lock_timeout_us: u32) -> Result<VcoWord, Error<SPI, LE>> {
+        let muted = self.mute.is_some();
+        if muted {
+            self.mute(true)?;
+        }
+        let st6: regs::ST6 = self.read_reg()?;
+        if !st6.en_autocal {
+            let mut cal = st6;
+            cal.en_autocal = true;
+            self.write_reg(&cal)?;
+        }
+        let manual = self.get_manual_calibration()?;
+        if manual {
+            self.set_manual_calibration(false)?;
+        }
+        let st0 = self.read(RegisterAddr::ST0)?;
+        self.write(RegisterAddr::ST0, st0)?;
+        if !st6.en_autocal {
+            self.write_reg(&st6)?;
+        }
+        if self.wait_for_lock(lock_timeout_us)?.is_none() {
+            return Err(Error::LockTimeout);
+        }
+        let word = self.capture_vco_word()?;
+        if manual {
+            self.preload_vco_word(word.core, word.word)?;
+        }
+        if muted {
+            self.mute(false)?;
+        }
+        Ok(word)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::tester;
+    use crate::registers::Register;
+    use embedded_hal_mock as mock;
+    use mock::pin::{Mock as PinMock, State as PinState, Transaction as PinTransaction};
+
+    #[test]
+    fn tracker() {
+        let mut tracker = TemperatureTracker::new(10_000);
+        assert!(!tracker.needs_recalibration(25_000));
+        assert!(!tracker.needs_recalibration(34_999));
+        assert!(tracker.needs_recalibration(15_000));
+        tracker.calibrated(15_000);
+        assert!(!tracker.needs_recalibration(24_000));
+        assert!(tracker.needs_recalibration(5_000));
+    }
+
+    #[test]
+    fn recalibrate() {
+        let st10 = regs::ST10 {
+            lock_det: true,
+            word: 17,
+            ..Default::default()
+        };
+        let pin = PinMock::new(&[
+            PinTransaction::set(PinState::High),
+            PinTransaction::set(PinState::Low),
+        ]);
+        let mut vco = tester().with_mute_pin(pin);
+        vco.spi.write(10, st10.encode());
+        vco.spi.write(0, 1234);
+        assert_eq!(vco.recalibrate(100).unwrap(), VcoWord { core: 0, word: 17 });
+        assert_eq!(vco.spi.read(0), 1234);
+        // Autocalibration is restored to off
+        assert!(!vco.read_reg::<regs::ST6>().unwrap().en_autocal);
+        vco.mute.unwrap().done();
+    }
+
+    #[test]
+    fn vco_word_preload() {
+        let mut vco = tester();
+        vco.spi.write(
+            10,
+            regs::ST10 {
+                vco_sel: 2,
+                word: 21,
+                ..Default::default()
+            }
+            .encode(),
+        );
+        let captured = vco.capture_vco_word().unwrap();
+        assert_eq!(captured, VcoWord { core: 2, word: 21 });
+
+        vco.preload_vco_word(captured.core, captured.word).unwrap();
+        assert_eq!(vco.spi.take_writes(), [6, 1]);
+        let st6: regs::ST6 = vco.read_reg().unwrap();
+        assert_eq!((st6.vco_sel, st6.vco_word), (2, 21));
+        assert!(vco.get_manual_calibration().unwrap());
+
+        assert!(matches!(
+            vco.preload_vco_word(0, 32),
+            Err(Error::Validation(ValidationError::VcoWordOutOfRange {
+                given: 32,
+                max: 31
+            }))
+        ));
+        assert!(vco.spi.take_writes().is_empty());
+        vco.set_manual_calibration(false).unwrap();
+        assert!(!vco.get_manual_calibration().unwrap());
+    }
+
+    #[test]
+    fn recalibrate_manual() {
+        let mut vco = tester();
+        vco.preload_vco_word(1, 9).unwrap();
+        let st10 = regs::ST10 {
+            lock_det: true,
+            vco_sel: 2,
+            word: 21,
+            ..Default::default()
+        };
+        vco.spi.write(10, st10.encode());
+        vco.spi.take_writes();
+        // Manual calibration is off for the ST0 write, then back on with the new word
+        let word = vco.recalibrate(100).unwrap();
+        assert_eq!(word, VcoWord { core: 2, word: 21 });
+        assert_eq!(vco.spi.take_writes(), [6, 1, 0, 6, 6, 1]);
+        let st6: regs::ST6 = vco.read_reg().unwrap();
+        assert_eq!((st6.vco_sel, st6.vco_word), (2, 21));
+        assert!(!st6.en_autocal);
+        assert!(vco.get_manual_calibration().unwrap());
+    }
+
+    #[test]
+    fn stays_muted_without_lock() {
+        let pin = PinMock::new(&[PinTransaction::set(PinState::High)]);
+        let mut vco = tester().with_mute_pin(pin);
+        assert!(matches!(vco.recalibrate(100), Err(Error::LockTimeout)));
+        vco.mute.unwrap().done();
+    }
+}
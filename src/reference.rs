@@ -0,0 +1,112 @@
+//! Reference input buffer settings
+//!
+//! The buffer mode in ST4 has to match what drives pins 20 and 21, `init` programs it from the
+//! `ReferenceType` given to the constructor. The low power mode in ST5 trades the buffer's
+//! drive, and with it some in-band phase noise, for current.
+use crate::error::Error;
+use crate::registers as regs;
+use crate::{ReferenceType, STuW81300};
+use embedded_hal as hal;
+use hal::blocking::spi::Transfer;
+use hal::digital::v2::{InputPin, OutputPin};
+
+/// Reference input buffer settings, written to ST4 and ST5
+#[derive(Debug, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ReferenceBufferConfig {
+    /// Buffer mode for the reference on pins 20 and 21, REF_BUFF_MODE
+    pub mode: ReferenceType,
+    /// Runs the buffer in its low power mode, REF_BUFF_LP
+    pub low_power: bool,
+}
+
+impl ReferenceBufferConfig {
+    /// The buffer mode for `ref_type` at full drive, for the lowest phase noise
+    /// Low power is worth trying with fast-edged single ended or LVPECL sources when current
+    /// matters, a crystal keeps the full drive
+    pub fn recommended(ref_type: ReferenceType) -> Self {
+        ReferenceBufferConfig {
+            mode: ref_type,
+            low_power: false,
+        }
+    }
+}
+
+impl<SPI, LE, PD, MUTE, D, LD> STuW81300<SPI, LE, PD, MUTE, D, LD>
+where
+    SPI: Transfer<u8>,
+    LE: OutputPin,
+    PD: OutputPin,
+    MUTE: OutputPin,
+    LD: InputPin,
+{
+    /// Sets the reference buffer mode and low power mode together
+    /// The mode also becomes the reference type the driver checks reference paths against,
+    /// e.g. a differential reference can't be doubled
+    pub fn set_reference_buffer(
+        &mut self,
+        config: &ReferenceBufferConfig,
+    ) -> Result<(), Error<SPI, LE>> {
+        self.batched(|vco| {
+            let mut st4: regs::ST4 = vco.read_reg()?;
+            st4.ref_buff_mode = config.mode as u32;
+            vco.write_reg(&st4)?;
+            let mut st5: regs::ST5 = vco.read_reg()?;
+            st5.ref_buff_lp = config.low_power;
+            vco.write_reg(&st5)
+        })?;
+        self.ref_type = config.mode;
+        Ok(())
+    }
+
+    /// Gets the reference buffer settings
+    /// Returns `Error::InvalidFieldValue` if the buffer mode is 0, which has no defined meaning
+    pub fn get_reference_buffer(&mut self) -> Result<ReferenceBufferConfig, Error<SPI, LE>> {
+        let st4: regs::ST4 = self.read_reg()?;
+        let st5: regs::ST5 = self.read_reg()?;
+        let mode = match st4.ref_buff_mode {
+            1 => ReferenceType::Differential,
+            2 => ReferenceType::Crystal,
+            3 => ReferenceType::SingleEnded,
+            _ => return Err(Error::InvalidFieldValue),
+        };
+        Ok(ReferenceBufferConfig {
+            mode,
+            low_power: st5.ref_buff_lp,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::ReferenceClockPath;
+    use crate::error::ValidationError;
+    use crate::mock::tester_with;
+
+    #[test]
+    fn reference_buffer() {
+        let mut vco = tester_with(crate::SupplyVoltage::HighVoltage, 20_000_000);
+        vco.init().unwrap();
+        assert_eq!(
+            vco.get_reference_buffer().unwrap(),
+            ReferenceBufferConfig::recommended(ReferenceType::SingleEnded)
+        );
+        vco.set_reference_clock_path(ReferenceClockPath::Doubled)
+            .unwrap();
+
+        let lvpecl = ReferenceBufferConfig {
+            mode: ReferenceType::Differential,
+            low_power: true,
+        };
+        vco.set_reference_buffer(&lvpecl).unwrap();
+        assert_eq!(vco.get_reference_buffer().unwrap(), lvpecl);
+        // The doubler is off limits for a differential reference from now on
+        assert!(matches!(
+            vco.set_reference_clock_path(ReferenceClockPath::Doubled),
+            Err(Error::Validation(
+                ValidationError::ReferencePathUnsupported { .. }
+            ))
+        ));
+    }
+}
@@ -1,40 +1,210 @@
+//! The register map of the device
+//!
+//! Every register is a struct with one public field per datasheet field. They convert to and
+//! from the raw 27-bit register value with `Register::encode`/`Register::decode` (or the
+//! equivalent `From`/`Into` impls), and can be read and written whole with
+//! `STuW81300::read_reg` and `STuW81300::write_reg`.
+use core::fmt;
+
+/// Addresses of the device registers
 #[repr(u8)]
-#[derive(Debug, PartialEq)]
-pub(crate) enum RegisterAddr {
-    // Master register. N divider, CP current
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum RegisterAddr {
+    /// Master register. N divider, CP current
     ST0,
-    // FRAC value, RF1 output control
+    /// FRAC value, RF1 output control
     ST1,
-    // MOD value, RF2 output control
+    /// MOD value, RF2 output control
     ST2,
-    // R divider, CP leakage, CP down-split pulse, Ref. path selection, Device power down
+    /// R divider, CP leakage, CP down-split pulse, Ref. path selection, Device power down
     ST3,
-    // Lock det. control, Ref. Buffer, CP supply mode, VCO settings, output power control
+    /// Lock det. control, Ref. Buffer, CP supply mode, VCO settings, output power control
     ST4,
-    // Low power mode control bit
+    /// Low power mode control bit
     ST5,
-    // VCO calibrator, manual vco control, DSM settings
+    /// VCO calibrator, manual vco control, DSM settings
     ST6,
-    // Fast lock control, LD_SDO settings
+    /// Fast lock control, LD_SDO settings
     ST7,
-    // LDO voltage regulator settings
+    /// LDO voltage regulator settings
     ST8,
-    // Reserved - Test and initialization bit
+    /// Reserved - Test and initialization bit
     ST9,
-    // VCO, lock det. status, LDO status
+    /// VCO, lock det. status, LDO status
     ST10,
-    // Device ID
+    /// Device ID
     ST11,
 }
 
+/// Layout of a single field within a register
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Field {
+    /// Lowercase datasheet name of the field
+    pub name: &'static str,
+    /// Position of the least significant bit
+    pub start: u8,
+    /// Width in bits, flags are a single bit
+    pub size: u8,
+}
+
 impl RegisterAddr {
-    pub(crate) fn read_only(&self) -> bool {
+    /// Every register address, in ascending order
+    pub const ALL: [RegisterAddr; 12] = [
+        RegisterAddr::ST0,
+        RegisterAddr::ST1,
+        RegisterAddr::ST2,
+        RegisterAddr::ST3,
+        RegisterAddr::ST4,
+        RegisterAddr::ST5,
+        RegisterAddr::ST6,
+        RegisterAddr::ST7,
+        RegisterAddr::ST8,
+        RegisterAddr::ST9,
+        RegisterAddr::ST10,
+        RegisterAddr::ST11,
+    ];
+
+    /// Iterates over every register address, in ascending order
+    pub fn iter() -> impl Iterator<Item = RegisterAddr> {
+        Self::ALL.into_iter()
+    }
+
+    /// The datasheet name of the register
+    pub fn name(&self) -> &'static str {
+        match self {
+            RegisterAddr::ST0 => "ST0",
+            RegisterAddr::ST1 => "ST1",
+            RegisterAddr::ST2 => "ST2",
+            RegisterAddr::ST3 => "ST3",
+            RegisterAddr::ST4 => "ST4",
+            RegisterAddr::ST5 => "ST5",
+            RegisterAddr::ST6 => "ST6",
+            RegisterAddr::ST7 => "ST7",
+            RegisterAddr::ST8 => "ST8",
+            RegisterAddr::ST9 => "ST9",
+            RegisterAddr::ST10 => "ST10",
+            RegisterAddr::ST11 => "ST11",
+        }
+    }
+
+    /// Whether the register can only be read
+    pub fn read_only(&self) -> bool {
         matches!(self, RegisterAddr::ST10 | RegisterAddr::ST11)
     }
+
+    /// The layout of the fields in the register, numeric fields first followed by flags
+    /// Registers without modeled fields (ST9 and ST11) return an empty slice
+    pub fn fields(&self) -> &'static [Field] {
+        match self {
+            RegisterAddr::ST0 => ST0::FIELDS,
+            RegisterAddr::ST1 => ST1::FIELDS,
+            RegisterAddr::ST2 => ST2::FIELDS,
+            RegisterAddr::ST3 => ST3::FIELDS,
+            RegisterAddr::ST4 => ST4::FIELDS,
+            RegisterAddr::ST5 => ST5::FIELDS,
+            RegisterAddr::ST6 => ST6::FIELDS,
+            RegisterAddr::ST7 => ST7::FIELDS,
+            RegisterAddr::ST8 => ST8::FIELDS,
+            RegisterAddr::ST9 => ST9::FIELDS,
+            RegisterAddr::ST10 => ST10::FIELDS,
+            RegisterAddr::ST11 => &[],
+        }
+    }
+}
+
+/// A raw register value interpreted with the crate's field definitions
+/// The `Debug` (and `defmt::Format`) output lists every named field with its value
+#[derive(PartialEq, Eq, Copy, Clone)]
+pub struct DecodedRegister {
+    /// Register the value was read from
+    pub addr: RegisterAddr,
+    /// The raw 27-bit register contents
+    pub raw: u32,
+}
+
+impl DecodedRegister {
+    /// Iterates over the `(name, value)` pairs of each field, flags decode to 0 or 1
+    pub fn fields(&self) -> impl Iterator<Item = (&'static str, u32)> + '_ {
+        self.addr.fields().iter().map(move |field| {
+            (
+                field.name,
+                shift_num_back(self.raw, field.start, field.size),
+            )
+        })
+    }
+
+    /// Gets the value of a single field by name
+    pub fn field(&self, name: &str) -> Option<u32> {
+        self.fields().find(|(n, _)| *n == name).map(|(_, v)| v)
+    }
 }
 
-pub(crate) trait Register {
+impl fmt::Debug for DecodedRegister {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.addr.fields().is_empty() {
+            return f
+                .debug_tuple(self.addr.name())
+                .field(&format_args!("{:#09X}", self.raw))
+                .finish();
+        }
+        let mut s = f.debug_struct(self.addr.name());
+        for (name, value) in self.fields() {
+            s.field(name, &value);
+        }
+        s.finish()
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for DecodedRegister {
+    fn format(&self, f: defmt::Formatter) {
+        if self.addr.fields().is_empty() {
+            defmt::write!(f, "{=str}({=u32:#X})", self.addr.name(), self.raw);
+            return;
+        }
+        defmt::write!(f, "{=str} {{", self.addr.name());
+        for (name, value) in self.fields() {
+            defmt::write!(f, " {=str}: {=u32}", name, value);
+        }
+        defmt::write!(f, " }}");
+    }
+}
+
+/// Splits a raw register value, e.g. captured on a logic analyzer, into its named fields
+pub fn decode(addr: RegisterAddr, raw: u32) -> DecodedRegister {
+    DecodedRegister {
+        addr,
+        raw: raw & 0x07FF_FFFF,
+    }
+}
+
+/// A device register with a fixed address and field layout
+pub trait Register: From<u32> + Sized {
+    /// Address of the register
     fn addr() -> RegisterAddr;
+
+    /// Packs the fields into the raw 27-bit register value
+    /// Out of range values are masked to the field width
+    fn encode(&self) -> u32;
+
+    /// Unpacks a raw register value into its fields
+    fn decode(raw: u32) -> Self {
+        Self::from(raw)
+    }
+
+    /// The first numeric field holding a value wider than its bit width, if any
+    fn overflowing_field(&self) -> Option<&'static str> {
+        None
+    }
+}
+
+/// A register with a DBR bit, whose writes can be held back until the next ST0 write
+/// Only ST1, ST2 and ST3 have one, see `STuW81300::stage`
+pub trait DoubleBuffered: Register + Copy {
+    /// Sets or clears the DBR bit
+    fn set_dbr(&mut self, dbr: bool);
 }
 
 // Utilities
@@ -43,8 +213,12 @@ fn shift_flag_forward(flag: bool, bit: u8) -> u32 {
     (flag as u32) << bit
 }
 
-fn shift_num_forward(num: u32, start: u8) -> u32 {
-    num << start
+fn field_mask(size: u8) -> u32 {
+    2u32.pow(size as u32) - 1
+}
+
+fn shift_num_forward(num: u32, start: u8, size: u8) -> u32 {
+    (num & field_mask(size)) << start
 }
 
 fn shift_flag_back(payload: u32, bit: u8) -> bool {
@@ -52,9 +226,33 @@ fn shift_flag_back(payload: u32, bit: u8) -> bool {
 }
 
 fn shift_num_back(payload: u32, start: u8, size: u8) -> u32 {
-    (payload >> start) & (2u32.pow(size as u32) - 1)
+    (payload >> start) & field_mask(size)
+}
+
+/// Checks that every field lies within the 27 data bits and no two fields overlap
+/// Returns what is wrong with the layout, `register!` evaluates it at compile time
+const fn layout_error(fields: &[Field]) -> Option<&'static str> {
+    let mut used = 0u32;
+    let mut i = 0;
+    while i < fields.len() {
+        let field = &fields[i];
+        if field.size == 0 || field.start as u32 + field.size as u32 > 27 {
+            return Some("register field outside the 27 data bits");
+        }
+        let mask = (u32::MAX >> (32 - field.size as u32)) << field.start;
+        if used & mask != 0 {
+            return Some("overlapping register fields");
+        }
+        used |= mask;
+        i += 1;
+    }
+    None
 }
 
+/// A numeric field was given a value wider than its bit width, holds the field name
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct FieldOverflow(pub &'static str);
+
 macro_rules! register {
     ($name:ident,
      numbers:
@@ -65,28 +263,74 @@ macro_rules! register {
      {
         $($flag:ident: $pos:literal,)*
      }) => {
-        #[derive(Debug, PartialEq)]
-        pub(crate) struct $name {
-            $(pub(crate) $num: u32,)*
-            $(pub(crate) $flag: bool,)*
+        #[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+        pub struct $name {
+            $(pub $num: u32,)*
+            $(pub $flag: bool,)*
+        }
+        impl $name {
+            /// Layout of the fields in this register
+            pub const FIELDS: &'static [Field] = &[
+                $(Field { name: stringify!($num), start: $start, size: $size },)*
+                $(Field { name: stringify!($flag), start: $pos, size: 1 },)*
+            ];
+
+            /// Builds the register, checking that every numeric field fits in its bit width
+            #[allow(clippy::too_many_arguments)]
+            pub fn new($($num: u32,)* $($flag: bool,)*) -> Result<Self, FieldOverflow> {
+                $(if $num > field_mask($size) {
+                    return Err(FieldOverflow(stringify!($num)));
+                })*
+                Ok(Self {
+                    $($num,)*
+                    $($flag,)*
+                })
+            }
+        }
+        #[cfg(test)]
+        impl $name {
+            /// Every field at a random value within its width, for property tests
+            pub(crate) fn arbitrary() -> impl proptest::strategy::Strategy<Value = Self> {
+                use proptest::strategy::Strategy;
+                // Each field takes its own bits of the payload
+                proptest::arbitrary::any::<u32>().prop_map(Self::from)
+            }
         }
+        // Fails the build if a field definition is wrong
+        const _: () = if let Some(error) = layout_error($name::FIELDS) {
+            panic!("{}", error)
+        };
         impl Register for $name {
             fn addr() -> RegisterAddr {
                 RegisterAddr::$name
             }
+
+            fn encode(&self) -> u32 {
+                self.into()
+            }
+
+            fn overflowing_field(&self) -> Option<&'static str> {
+                $(if self.$num > field_mask($size) {
+                    return Some(stringify!($num));
+                })*
+                None
+            }
         }
         impl From<&$name> for u32 {
             fn from(register: &$name) -> Self {
-                #[cfg(not(release))]
-                {
-                    $(assert!(register.$num < 2u32.pow($size), concat!(stringify!($num), " must be ", stringify!($size), " bits!"));)*
-                }
+                // Out of range values are masked to the field width so they can't spill into
+                // neighboring fields, use `new` to catch them instead
                 let mut payload = 0u32;
-                $(payload |= shift_num_forward(register.$num,$start);)*
+                $(payload |= shift_num_forward(register.$num,$start,$size);)*
                 $(payload |= shift_flag_forward(register.$flag,$pos);)*
                 payload
             }
         }
+        impl From<$name> for u32 {
+            fn from(register: $name) -> Self {
+                (&register).into()
+            }
+        }
         impl From<u32> for $name {
             fn from(payload: u32) -> Self {
                 $(let $num = shift_num_back(payload,$start,$size);)*
@@ -202,6 +446,8 @@ register!(
     numbers:
     {
         dsm_order: (2,22),
+        vco_sel: (2,18),
+        vco_word: (5,13),
         prchg_del: (2,10),
         cal_div: (9,0),
     },
@@ -243,6 +489,58 @@ register!(
     }
 );
 
+impl DoubleBuffered for ST1 {
+    fn set_dbr(&mut self, dbr: bool) {
+        self.dbr = dbr;
+    }
+}
+
+impl DoubleBuffered for ST2 {
+    fn set_dbr(&mut self, dbr: bool) {
+        self.dbr = dbr;
+    }
+}
+
+impl DoubleBuffered for ST3 {
+    fn set_dbr(&mut self, dbr: bool) {
+        self.dbr = dbr;
+    }
+}
+
+/// Reserved test and initialization register, without any fields software may set
+/// Every bit has to stay cleared, so only `init` and `init_from` write it, with `ST9::INIT`
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+pub struct ST9;
+
+impl ST9 {
+    /// Layout of the fields in this register
+    pub const FIELDS: &'static [Field] = &[];
+    /// The value written first thing during initialization, all bits cleared
+    pub const INIT: ST9 = ST9;
+}
+
+impl Register for ST9 {
+    fn addr() -> RegisterAddr {
+        RegisterAddr::ST9
+    }
+
+    fn encode(&self) -> u32 {
+        0
+    }
+}
+
+impl From<ST9> for u32 {
+    fn from(_: ST9) -> Self {
+        0
+    }
+}
+
+impl From<u32> for ST9 {
+    fn from(_: u32) -> Self {
+        ST9
+    }
+}
+
 register!(
     ST10,
     numbers:
@@ -359,12 +657,14 @@ mod tests {
         #[test]
         fn round_trip_st6(dithering: bool,
                           dsm_order in 0u32..3u32,
+                          vco_sel in 0u32..3u32,
+                          vco_word in 0u32..31u32,
                           en_autocal: bool,
                           cal_temp_comp: bool,
                           prchg_del in 0u32..3u32,
                           cal_acc_en: bool,
                           cal_div in 1u32..511u32) {
-            let st6 = ST6 { dsm_order, prchg_del, cal_div, dithering, en_autocal, cal_temp_comp, cal_acc_en };
+            let st6 = ST6 { dsm_order, vco_sel, vco_word, prchg_del, cal_div, dithering, en_autocal, cal_temp_comp, cal_acc_en };
             let rt: ST6 = Into::<u32>::into(&st6).into();
             assert_eq!(rt,st6);
         }
@@ -408,4 +708,142 @@ mod tests {
             assert_eq!(rt,st10);
         }
     }
+
+    #[test]
+    fn addr_iter() {
+        assert_eq!(RegisterAddr::iter().count(), 12);
+        for (i, addr) in RegisterAddr::iter().enumerate() {
+            assert_eq!(addr as usize, i);
+            assert_eq!(addr.name(), format!("ST{}", i));
+        }
+    }
+
+    #[test]
+    fn field_layout() {
+        assert_eq!(
+            RegisterAddr::ST0.fields(),
+            &[
+                Field {
+                    name: "cp_sel",
+                    start: 21,
+                    size: 5
+                },
+                Field {
+                    name: "pfd_del",
+                    start: 19,
+                    size: 2
+                },
+                Field {
+                    name: "n",
+                    start: 0,
+                    size: 17
+                },
+            ]
+        );
+        // Every field fits in the 27 data bits and none overlap
+        for addr in RegisterAddr::iter() {
+            let mut used = 0u32;
+            for field in addr.fields() {
+                assert!(field.start + field.size <= 27, "{}", field.name);
+                let mask = field_mask(field.size) << field.start;
+                assert_eq!(used & mask, 0, "{} overlaps", field.name);
+                used |= mask;
+            }
+        }
+    }
+
+    #[test]
+    fn decode_fields() {
+        let st0 = decode(RegisterAddr::ST0, 0x03E0004C);
+        assert_eq!(st0.field("cp_sel"), Some(31));
+        assert_eq!(st0.field("n"), Some(76));
+        assert_eq!(st0.field("frac"), None);
+        assert_eq!(
+            format!("{:?}", st0),
+            "ST0 { cp_sel: 31, pfd_del: 0, n: 76 }"
+        );
+        let st1 = decode(RegisterAddr::ST1, 0x01400001);
+        assert_eq!(st1.field("rf1_out_pd"), Some(1));
+        assert_eq!(st1.field("pll_sel"), Some(1));
+        assert_eq!(st1.field("man_calb_en"), Some(0));
+        assert_eq!(
+            format!("{:?}", decode(RegisterAddr::ST11, 0x8052)),
+            "ST11(0x0008052)"
+        );
+    }
+
+    #[test]
+    fn encode_masks_fields() {
+        let st0 = ST0 {
+            cp_sel: 0,
+            pfd_del: 0,
+            n: 1 << 17,
+        };
+        assert_eq!(Into::<u32>::into(&st0), 0);
+        let st3 = ST3 {
+            cp_leak: 0,
+            pfd_del_mode: 0,
+            ref_path_sel: 0,
+            r: 0x3FFF,
+            dbr: false,
+            pd: false,
+            cp_leak_x2: false,
+            cp_leak_dir: false,
+            dnsplit_en: false,
+        };
+        assert_eq!(Into::<u32>::into(&st3), 0x1FFF);
+    }
+
+    #[test]
+    fn checked_new() {
+        assert_eq!(
+            ST0::new(31, 3, 76),
+            Ok(ST0 {
+                cp_sel: 31,
+                pfd_del: 3,
+                n: 76
+            })
+        );
+        assert_eq!(ST0::new(32, 0, 76), Err(FieldOverflow("cp_sel")));
+        assert_eq!(ST2::new(1 << 21, false, false), Err(FieldOverflow("modu")));
+    }
+
+    #[test]
+    fn layout_checks() {
+        for addr in RegisterAddr::iter() {
+            assert_eq!(layout_error(addr.fields()), None);
+        }
+        let field = |start, size| Field {
+            name: "field",
+            start,
+            size,
+        };
+        assert_eq!(
+            layout_error(&[field(0, 4), field(3, 2)]),
+            Some("overlapping register fields")
+        );
+        assert_eq!(
+            layout_error(&[field(20, 7), field(26, 1)]),
+            Some("overlapping register fields")
+        );
+        assert_eq!(
+            layout_error(&[field(21, 7)]),
+            Some("register field outside the 27 data bits")
+        );
+        assert_eq!(layout_error(&[field(0, 21), field(21, 6)]), None);
+    }
+
+    #[test]
+    fn register_trait() {
+        let st7 = ST7 {
+            cp_sel_fl: 31,
+            fstlck_cnt: 500,
+            cycle_slip_en: true,
+            ..Default::default()
+        };
+        assert_eq!(ST7::addr(), RegisterAddr::ST7);
+        assert_eq!(st7.encode(), u32::from(st7));
+        assert_eq!(st7.encode(), u32::from(&st7));
+        assert_eq!(ST7::decode(st7.encode()), st7);
+    }
 }
@@ -0,0 +1,100 @@
+//! Direction of the frequency error of `set_output_frequency`
+//!
+//! With the maximum MOD one FRAC LSB is about 24 Hz at a 50 MHz PFD, so most frequencies
+//! can't be hit exactly. By default the planner picks the nearest one, receivers that need
+//! the LO to stay on one side of the target can round down or up instead, and `Exact` rejects
+//! any frequency the divider can't produce exactly.
+use crate::STuW81300;
+
+/// How `set_output_frequency` rounds frequencies the divider can't hit exactly
+#[derive(Debug, PartialEq, Eq, Copy, Clone, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Rounding {
+    /// The nearest frequency, on either side
+    #[default]
+    Nearest,
+    /// The nearest frequency at or below the target
+    Down,
+    /// The nearest frequency at or above the target
+    Up,
+    /// Only the exact frequency, otherwise `ValidationError::InexactFrequency`
+    /// Dithering is disabled and MOD is the denominator of the divider fraction in lowest
+    /// terms. In float builds the target must be a whole number of Hz
+    Exact,
+}
+
+impl<SPI, LE, PD, MUTE, D, LD> STuW81300<SPI, LE, PD, MUTE, D, LD> {
+    /// Sets how `set_output_frequency` rounds, `Rounding::Nearest` by default
+    pub fn set_rounding(&mut self, rounding: Rounding) {
+        self.rounding = rounding;
+    }
+
+    /// How `set_output_frequency` rounds
+    pub fn rounding(&self) -> Rounding {
+        self.rounding
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::round_frac;
+    use crate::error::{Error, ValidationError};
+    use crate::float::hz;
+    use crate::mock::{initialized_tester, tester_with};
+    use crate::registers as regs;
+
+    #[test]
+    fn directions() {
+        // 7 half LSBs with dithering: FRAC = 3 hits it exactly
+        assert_eq!(round_frac(7, true, 10, true, Rounding::Down), (3, 0));
+        assert_eq!(round_frac(7, true, 10, true, Rounding::Up), (3, 0));
+        // Just above 7 half LSBs
+        assert_eq!(round_frac(7, false, 10, true, Rounding::Down), (3, 0));
+        assert_eq!(round_frac(7, false, 10, true, Rounding::Up), (4, 0));
+        // Without dithering 7 half LSBs sits between FRAC 3 and 4
+        assert_eq!(round_frac(7, true, 10, false, Rounding::Down), (3, 0));
+        assert_eq!(round_frac(7, true, 10, false, Rounding::Up), (4, 0));
+        // Below the first dithered step and above the last one, N_INT moves
+        assert_eq!(round_frac(0, true, 10, true, Rounding::Down), (9, -1));
+        assert_eq!(round_frac(19, false, 10, true, Rounding::Up), (0, 1));
+    }
+
+    #[test]
+    fn output_frequency() {
+        let mut vco = initialized_tester();
+        // Representable in every build, 512 Hz above a multiple of the PFD frequency
+        let target = hz(5_000_000_512);
+
+        vco.set_rounding(Rounding::Down);
+        vco.set_output_frequency(target).unwrap();
+        let below = vco.spi.simulated_output_hz().unwrap();
+        assert!(below <= 5_000_000_512.0);
+        vco.set_rounding(Rounding::Up);
+        vco.set_output_frequency(target).unwrap();
+        let above = vco.spi.simulated_output_hz().unwrap();
+        assert!(above >= 5_000_000_512.0);
+        // At most one LSB apart
+        assert!((above - below) * 2097151.0 / 50e6 < 1.001);
+
+        // 512 Hz at 50 MHz is 4 / 390625
+        vco.set_rounding(Rounding::Exact);
+        vco.set_output_frequency(target).unwrap();
+        let st1: regs::ST1 = vco.read_reg().unwrap();
+        let st2: regs::ST2 = vco.read_reg().unwrap();
+        let st6: regs::ST6 = vco.read_reg().unwrap();
+        assert_eq!((st1.frac, st2.modu, st6.dithering), (4, 390625, false));
+        let simulated = vco.spi.simulated_output_hz().unwrap();
+        assert!((simulated - 5_000_000_512.0).abs() < 1e-3);
+
+        // An odd reference leaves a fraction too fine for MOD
+        let mut vco = tester_with(crate::SupplyVoltage::HighVoltage, 100_000_007);
+        vco.init().unwrap();
+        vco.set_reference_clock_divider(2).unwrap();
+        vco.set_rounding(Rounding::Exact);
+        assert!(matches!(
+            vco.set_output_frequency(target),
+            Err(Error::Validation(ValidationError::InexactFrequency { .. }))
+        ));
+    }
+}
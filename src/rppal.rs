@@ -0,0 +1,84 @@
+//! Raspberry Pi quickstart through `rppal`
+//!
+//! ```ignore
+//! let config = RppalConfig::new(SupplyVoltage::HighVoltage, 100_000_000, ReferenceType::SingleEnded);
+//! let mut vco = STuW81300::from_rppal(Bus::Spi0, 25, config)?;
+//! vco.init()?;
+//! ```
+use crate::error::Error;
+use crate::{ReferenceType, STuW81300, SupplyVoltage};
+use embedded_hal::digital::v2::OutputPin;
+use rppal::gpio::{self, Gpio};
+use rppal::spi::{Bus, Mode, SlaveSelect, Spi};
+
+/// Settings for `from_rppal`
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct RppalConfig {
+    /// See `STuW81300::new_hz`
+    pub supply_voltage: SupplyVoltage,
+    /// Reference frequency in whole Hz, see `STuW81300::new_hz`
+    pub ref_freq: u64,
+    /// See `STuW81300::new_hz`
+    pub ref_type: ReferenceType,
+    /// SPI clock in Hz
+    pub clock_speed: u32,
+}
+
+impl RppalConfig {
+    /// Settings with a 1 MHz SPI clock, slow enough for jumper wires
+    pub fn new(supply_voltage: SupplyVoltage, ref_freq: u64, ref_type: ReferenceType) -> Self {
+        RppalConfig {
+            supply_voltage,
+            ref_freq,
+            ref_type,
+            clock_speed: 1_000_000,
+        }
+    }
+}
+
+/// The latch enable pin on a Raspberry Pi GPIO
+/// Driving it can't fail, its error type only carries GPIO setup errors from `from_rppal`
+pub struct RppalLatchEnable(gpio::OutputPin);
+
+impl OutputPin for RppalLatchEnable {
+    type Error = gpio::Error;
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.0.set_low();
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.0.set_high();
+        Ok(())
+    }
+}
+
+/// The driver on a Raspberry Pi, as returned by `from_rppal`
+pub type RppalStuw81300 = STuW81300<Spi, RppalLatchEnable>;
+
+impl STuW81300<Spi, RppalLatchEnable> {
+    /// Opens `spi_bus` and drives LE from the GPIO numbered `le_gpio` (BCM numbering)
+    /// The bus is opened with its first chip select, which the device doesn't use: LE latches
+    /// the data on its rising edge after the transfer, so it is driven from a plain GPIO
+    /// SPI setup errors are reported as `Error::Transfer`, GPIO ones as `Error::LatchEnable`
+    pub fn from_rppal(
+        spi_bus: Bus,
+        le_gpio: u8,
+        config: RppalConfig,
+    ) -> Result<Self, Error<Spi, RppalLatchEnable>> {
+        let spi = Spi::new(spi_bus, SlaveSelect::Ss0, config.clock_speed, Mode::Mode0)
+            .map_err(Error::Transfer)?;
+        let le = Gpio::new()
+            .and_then(|gpio| gpio.get(le_gpio))
+            .map_err(Error::LatchEnable)?
+            .into_output_high();
+        Ok(STuW81300::new_hz(
+            spi,
+            RppalLatchEnable(le),
+            config.supply_voltage,
+            config.ref_freq,
+            config.ref_type,
+        ))
+    }
+}
@@ -0,0 +1,133 @@
+//! A quick go/no-go check of the SPI connection, e.g. for production test
+use crate::error::Error;
+use crate::registers::{self as regs, Register, RegisterAddr};
+use crate::{DeviceVariant, STuW81300};
+use embedded_hal as hal;
+use hal::blocking::spi::Transfer;
+use hal::digital::v2::{InputPin, OutputPin};
+
+/// Patterns written to the scratch field, together they toggle every bit of it
+const PATTERNS: [u32; 2] = [0x1555, 0x0AAA];
+
+/// Outcome of `self_test`
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SelfTestReport {
+    /// The ID read from ST11
+    pub device_id: u32,
+    /// The ID belongs to a known variant
+    pub id_known: bool,
+    /// Every test pattern written to the scratch field read back unchanged
+    pub patterns_ok: bool,
+    /// A write to the read-only ID register left it unchanged
+    pub read_only_ok: bool,
+}
+
+impl SelfTestReport {
+    /// Returns true if every check passed
+    pub fn passed(&self) -> bool {
+        self.id_known && self.patterns_ok && self.read_only_ok
+    }
+}
+
+impl<SPI, LE, PD, MUTE, D, LD> STuW81300<SPI, LE, PD, MUTE, D, LD>
+where
+    SPI: Transfer<u8>,
+    LE: OutputPin,
+    PD: OutputPin,
+    MUTE: OutputPin,
+    LD: InputPin,
+{
+    /// Checks the SPI connection to the device
+    /// Reads the device ID, writes alternating bit patterns to the fast lock counter in ST7
+    /// and reads them back, and writes to the read-only ID register to check it is ignored.
+    /// The fast lock counter only matters during a retune and is restored afterwards, so the
+    /// test is safe to run on a configured device
+    /// The patterns go straight to the device and are read back from it, past any batch,
+    /// deferred writes and cached reads, and the shadow of ST7 is left as it was. Without SDO
+    /// nothing can be read back, in the `WriteOnly` interface mode this returns
+    /// `Error::Unreadable`
+    /// Failed checks are reported in the result, only bus errors return `Err`
+    pub fn self_test(&mut self) -> Result<SelfTestReport, Error<SPI, LE>> {
+        let device_id = self.device_id()?;
+
+        let shadow = self.shadow[RegisterAddr::ST7 as usize];
+        let patterns_ok = self.test_patterns();
+        self.shadow[RegisterAddr::ST7 as usize] = shadow;
+        let patterns_ok = patterns_ok?;
+
+        self.write_read_only(RegisterAddr::ST11, !device_id)?;
+        let read_only_ok = self.device_id()? == device_id;
+
+        Ok(SelfTestReport {
+            device_id,
+            id_known: DeviceVariant::from_id(device_id).is_some(),
+            patterns_ok,
+            read_only_ok,
+        })
+    }
+
+    /// Writes each pattern to the fast lock counter on the device and reads it back, then
+    /// restores the device's value
+    fn test_patterns(&mut self) -> Result<bool, Error<SPI, LE>> {
+        let original = self.read_device(RegisterAddr::ST7)?;
+        let mut patterns_ok = true;
+        for pattern in PATTERNS {
+            let data = regs::ST7 {
+                fstlck_cnt: pattern,
+                ..regs::ST7::decode(original)
+            }
+            .encode();
+            self.write_device(RegisterAddr::ST7, data)?;
+            patterns_ok &= self.read_device(RegisterAddr::ST7)? == data;
+        }
+        self.write_device(RegisterAddr::ST7, original)?;
+        Ok(patterns_ok)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mock::tester;
+    use crate::registers::{self as regs, RegisterAddr};
+
+    #[test]
+    fn passes() {
+        let mut vco = tester();
+        vco.spi.write(7, 0x0000_0100);
+        let report = vco.self_test().unwrap();
+        assert!(report.passed());
+        assert_eq!(report.device_id, 0x8052);
+        // The scratch field is restored
+        assert_eq!(vco.spi.read(7), 0x0000_0100);
+    }
+
+    #[test]
+    fn unknown_id() {
+        let mut vco = tester();
+        vco.spi.write(11, 0x1234);
+        let report = vco.self_test().unwrap();
+        assert!(!report.id_known);
+        assert!(report.patterns_ok);
+        assert!(!report.passed());
+    }
+
+    #[test]
+    fn bypasses_the_shadow() {
+        let mut vco = tester();
+        vco.set_cached_reads(true);
+        vco.defer_writes(true);
+        let pending = regs::ST7 {
+            cp_sel_fl: 5,
+            ..Default::default()
+        };
+        vco.write_reg(&pending).unwrap();
+        vco.spi.freeze(7);
+        // A device that drops the patterns fails, however the shadow is set up
+        assert!(!vco.self_test().unwrap().patterns_ok);
+        assert_eq!(vco.spi.take_writes(), [7, 7, 7, 11]);
+        // The deferred write is still pending
+        assert_eq!(vco.cached_reg::<regs::ST7>(), Some(pending));
+        assert_eq!(vco.dirty_registers(), 1 << RegisterAddr::ST7 as u16);
+    }
+}
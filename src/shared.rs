@@ -0,0 +1,267 @@
+//! Sharing the driver between tasks
+//!
+//! `STuW81300` only holds the SPI bus, the LE pin, the optional pins and delay and plain
+//! configuration data, so it is `Send` whenever those are. In RTIC it can be used directly as a shared resource, the
+//! framework already provides the locking. For Embassy (or anything else built on
+//! `embassy-sync`'s `RawMutex`), wrap it in a `SharedStuw81300` stored in a `static` and
+//! access it from any task with `lock`:
+//!
+//! ```ignore
+//! static VCO: StaticCell<SharedStuw81300<CriticalSectionRawMutex, Spi, Le>> = StaticCell::new();
+//!
+//! // Retune task
+//! vco.lock(|vco| vco.set_output_frequency(5e9))?;
+//! // Monitor task
+//! let locked = vco.lock(|vco| vco.is_locked())?;
+//! ```
+//!
+//! To hand independent parts of the chip to different tasks, `split` the shared driver into
+//! `PllControl`, `Outputs` and `Status` handles. Every handle goes through the same mutex, so
+//! read-modify-write cycles on registers that hold fields for several subsystems (e.g. ST1
+//! holds both FRAC and the RF1 power down bit) never interleave.
+use crate::api::{DsmOrder, ReferenceClockPath, Rf1Path};
+use crate::error::Error;
+use crate::pll_path::PllPath;
+use crate::{Hz, HzDelta, NoDelay, NoPin, PowerState, STuW81300};
+use core::cell::RefCell;
+use embassy_sync::blocking_mutex::{raw::RawMutex, Mutex};
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::{InputPin, OutputPin};
+
+/// A `STuW81300` behind a blocking mutex, shareable between tasks
+pub struct SharedStuw81300<M, SPI, LE, PD = NoPin, MUTE = NoPin, D = NoDelay, LD = NoPin>
+where
+    M: RawMutex,
+{
+    #[allow(clippy::type_complexity)]
+    inner: Mutex<M, RefCell<STuW81300<SPI, LE, PD, MUTE, D, LD>>>,
+}
+
+impl<M, SPI, LE, PD, MUTE, D, LD> SharedStuw81300<M, SPI, LE, PD, MUTE, D, LD>
+where
+    M: RawMutex,
+{
+    /// Wraps an already constructed driver
+    pub const fn new(vco: STuW81300<SPI, LE, PD, MUTE, D, LD>) -> Self {
+        SharedStuw81300 {
+            inner: Mutex::new(RefCell::new(vco)),
+        }
+    }
+
+    /// Runs `f` with exclusive access to the driver
+    /// Calling `lock` again from inside `f` panics
+    pub fn lock<R>(&self, f: impl FnOnce(&mut STuW81300<SPI, LE, PD, MUTE, D, LD>) -> R) -> R {
+        self.inner.lock(|vco| f(&mut vco.borrow_mut()))
+    }
+
+    /// Unwraps the driver
+    pub fn into_inner(self) -> STuW81300<SPI, LE, PD, MUTE, D, LD> {
+        self.inner.into_inner().into_inner()
+    }
+
+    /// Splits the driver into independent handles for frequency control, RF outputs and status
+    #[allow(clippy::type_complexity)]
+    pub fn split(
+        &self,
+    ) -> (
+        PllControl<'_, M, SPI, LE, PD, MUTE, D, LD>,
+        Outputs<'_, M, SPI, LE, PD, MUTE, D, LD>,
+        Status<'_, M, SPI, LE, PD, MUTE, D, LD>,
+    ) {
+        (
+            PllControl { shared: self },
+            Outputs { shared: self },
+            Status { shared: self },
+        )
+    }
+}
+
+/// Frequency synthesis half of a split driver
+pub struct PllControl<'a, M, SPI, LE, PD = NoPin, MUTE = NoPin, D = NoDelay, LD = NoPin>
+where
+    M: RawMutex,
+{
+    shared: &'a SharedStuw81300<M, SPI, LE, PD, MUTE, D, LD>,
+}
+
+impl<M, SPI, LE, PD, MUTE, D, LD> PllControl<'_, M, SPI, LE, PD, MUTE, D, LD>
+where
+    M: RawMutex,
+    SPI: Transfer<u8>,
+    LE: OutputPin,
+    PD: OutputPin,
+    MUTE: OutputPin,
+    LD: InputPin,
+{
+    /// See `STuW81300::set_output_frequency`
+    pub fn set_output_frequency(&mut self, f: Hz) -> Result<(), Error<SPI, LE>> {
+        self.shared.lock(|vco| vco.set_output_frequency(f))
+    }
+
+    /// See `STuW81300::get_output_frequency`
+    pub fn get_output_frequency(&mut self) -> Result<Hz, Error<SPI, LE>> {
+        self.shared.lock(|vco| vco.get_output_frequency())
+    }
+
+    /// See `STuW81300::retune_nearby`
+    pub fn retune_nearby(&mut self, delta: HzDelta) -> Result<(), Error<SPI, LE>> {
+        self.shared.lock(|vco| vco.retune_nearby(delta))
+    }
+
+    /// See `STuW81300::set_reference_clock_path`
+    pub fn set_reference_clock_path(
+        &mut self,
+        path: ReferenceClockPath,
+    ) -> Result<(), Error<SPI, LE>> {
+        self.shared.lock(|vco| vco.set_reference_clock_path(path))
+    }
+
+    /// See `STuW81300::set_reference_clock_divider`
+    pub fn set_reference_clock_divider(&mut self, r: u32) -> Result<(), Error<SPI, LE>> {
+        self.shared.lock(|vco| vco.set_reference_clock_divider(r))
+    }
+
+    /// See `STuW81300::get_pfd_frequency`
+    pub fn get_pfd_frequency(&mut self) -> Result<Hz, Error<SPI, LE>> {
+        self.shared.lock(|vco| vco.get_pfd_frequency())
+    }
+
+    /// See `STuW81300::set_dsm_order`
+    pub fn set_dsm_order(&mut self, order: DsmOrder) -> Result<(), Error<SPI, LE>> {
+        self.shared.lock(|vco| vco.set_dsm_order(order))
+    }
+
+    /// See `STuW81300::set_dithering`
+    pub fn set_dithering(&mut self, active: bool) -> Result<(), Error<SPI, LE>> {
+        self.shared.lock(|vco| vco.set_dithering(active))
+    }
+
+    /// See `STuW81300::set_pll_path`
+    pub fn set_pll_path(&mut self, path: PllPath) -> Result<(), Error<SPI, LE>> {
+        self.shared.lock(|vco| vco.set_pll_path(path))
+    }
+
+    /// See `STuW81300::set_charge_pump`
+    pub fn set_charge_pump(&mut self, scale: u32) -> Result<(), Error<SPI, LE>> {
+        self.shared.lock(|vco| vco.set_charge_pump(scale))
+    }
+}
+
+/// RF output half of a split driver
+pub struct Outputs<'a, M, SPI, LE, PD = NoPin, MUTE = NoPin, D = NoDelay, LD = NoPin>
+where
+    M: RawMutex,
+{
+    shared: &'a SharedStuw81300<M, SPI, LE, PD, MUTE, D, LD>,
+}
+
+impl<M, SPI, LE, PD, MUTE, D, LD> Outputs<'_, M, SPI, LE, PD, MUTE, D, LD>
+where
+    M: RawMutex,
+    SPI: Transfer<u8>,
+    LE: OutputPin,
+    PD: OutputPin,
+    MUTE: OutputPin,
+    LD: InputPin,
+{
+    /// See `STuW81300::set_rf1_output`
+    pub fn set_rf1_output(&mut self, enabled: bool) -> Result<(), Error<SPI, LE>> {
+        self.shared.lock(|vco| vco.set_rf1_output(enabled))
+    }
+
+    /// See `STuW81300::get_rf1_output`
+    pub fn get_rf1_output(&mut self) -> Result<bool, Error<SPI, LE>> {
+        self.shared.lock(|vco| vco.get_rf1_output())
+    }
+
+    /// See `STuW81300::set_rf1_path`
+    pub fn set_rf1_path(&mut self, path: Rf1Path) -> Result<(), Error<SPI, LE>> {
+        self.shared.lock(|vco| vco.set_rf1_path(path))
+    }
+
+    /// See `STuW81300::mute`
+    pub fn mute(&mut self, muted: bool) -> Result<(), Error<SPI, LE>> {
+        self.shared.lock(|vco| vco.mute(muted))
+    }
+
+    /// See `STuW81300::set_vco_amplitude`
+    pub fn set_vco_amplitude(&mut self, amplitude: u32) -> Result<(), Error<SPI, LE>> {
+        self.shared.lock(|vco| vco.set_vco_amplitude(amplitude))
+    }
+}
+
+/// Read-only status half of a split driver
+pub struct Status<'a, M, SPI, LE, PD = NoPin, MUTE = NoPin, D = NoDelay, LD = NoPin>
+where
+    M: RawMutex,
+{
+    shared: &'a SharedStuw81300<M, SPI, LE, PD, MUTE, D, LD>,
+}
+
+impl<M, SPI, LE, PD, MUTE, D, LD> Status<'_, M, SPI, LE, PD, MUTE, D, LD>
+where
+    M: RawMutex,
+    SPI: Transfer<u8>,
+    LE: OutputPin,
+    PD: OutputPin,
+    MUTE: OutputPin,
+    LD: InputPin,
+{
+    /// See `STuW81300::device_id`
+    pub fn device_id(&mut self) -> Result<u32, Error<SPI, LE>> {
+        self.shared.lock(|vco| vco.device_id())
+    }
+
+    /// See `STuW81300::is_locked`
+    pub fn is_locked(&mut self) -> Result<bool, Error<SPI, LE>> {
+        self.shared.lock(|vco| vco.is_locked())
+    }
+
+    /// See `STuW81300::is_startup`
+    pub fn is_startup(&mut self) -> Result<bool, Error<SPI, LE>> {
+        self.shared.lock(|vco| vco.is_startup())
+    }
+
+    /// See `STuW81300::is_ocp`
+    pub fn is_ocp(&mut self) -> Result<bool, Error<SPI, LE>> {
+        self.shared.lock(|vco| vco.is_ocp())
+    }
+
+    /// See `STuW81300::power_state`
+    pub fn power_state(&mut self) -> Result<PowerState, Error<SPI, LE>> {
+        self.shared.lock(|vco| vco.power_state())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::float::hz;
+    use crate::mock::{assert_freq_eq, tester};
+    use embassy_sync::blocking_mutex::raw::NoopRawMutex;
+
+    #[test]
+    fn shared_access() {
+        let shared: SharedStuw81300<NoopRawMutex, _, _> = SharedStuw81300::new(tester());
+        assert_eq!(shared.lock(|vco| vco.device_id().unwrap()), 0x8052);
+        shared.lock(|vco| vco.set_charge_pump(12)).unwrap();
+        let mut vco = shared.into_inner();
+        assert_eq!(vco.get_charge_pump().unwrap(), 12);
+    }
+
+    #[test]
+    fn split_handles() {
+        let shared: SharedStuw81300<NoopRawMutex, _, _> = SharedStuw81300::new(tester());
+        shared.lock(|vco| vco.init()).unwrap();
+        let (mut pll, mut outputs, mut status) = shared.split();
+        pll.set_reference_clock_path(ReferenceClockPath::Direct)
+            .unwrap();
+        pll.set_reference_clock_divider(2).unwrap();
+        pll.set_output_frequency(hz(5_000_000_000)).unwrap();
+        outputs.set_rf1_output(false).unwrap();
+        // The output handle's ST1 write must not clobber FRAC/PLL settings
+        assert_freq_eq!(pll.get_output_frequency().unwrap(), hz(5_000_000_000));
+        assert!(!outputs.get_rf1_output().unwrap());
+        assert_eq!(status.device_id().unwrap(), 0x8052);
+    }
+}
@@ -1,18 +1,22 @@
-use crate::error::Error;
+use crate::error::{Error, ValidationError};
+use crate::logging::{trace, warning};
 use crate::registers::{Register, RegisterAddr};
-use crate::STuW81300;
+use crate::{Interface, STuW81300};
 use embedded_hal as hal;
 use hal::blocking::spi::Transfer;
 use hal::digital::v2::OutputPin;
 
+/// Registers hold 27 bits of data
+const DATA_MASK: u32 = 0x07FF_FFFF;
+
 #[repr(u8)]
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Copy, Clone)]
 enum AccessMode {
     Write = 0,
     Read = 1,
 }
 
-impl<SPI, LE> STuW81300<SPI, LE>
+impl<SPI, LE, PD, MUTE, D, LD> STuW81300<SPI, LE, PD, MUTE, D, LD>
 where
     SPI: Transfer<u8>,
     LE: OutputPin,
@@ -22,52 +26,198 @@ where
         addr: RegisterAddr,
         data: u32,
         mode: AccessMode,
+    ) -> Result<u32, Error<SPI, LE>> {
+        // Guard against writes to read-only registers before touching the bus
+        if mode == AccessMode::Write && addr.read_only() {
+            return Err(Error::ReadOnly);
+        }
+        self.transfer_frame(addr, data, mode)
+    }
+
+    /// Sends one frame without any checks
+    fn transfer_frame(
+        &mut self,
+        addr: RegisterAddr,
+        data: u32,
+        mode: AccessMode,
     ) -> Result<u32, Error<SPI, LE>> {
         // Pack data
         let mut buf = pack(addr, data, mode);
-        // Perform transaction. Do we care about timing?
-        self.le.set_low().map_err(|e| Error::LatchEnable(e))?;
-        self.spi
-            .transfer(&mut buf)
-            .map_err(|e| Error::Transfer(e))?;
-        self.le.set_high().map_err(|e| Error::LatchEnable(e))?;
+        #[cfg(feature = "critical-section")]
+        critical_section::with(|_| self.send_frame(addr, &mut buf))?;
+        #[cfg(not(feature = "critical-section"))]
+        self.send_frame(addr, &mut buf)?;
         // Extract data
-        Ok(u32::from_be_bytes(buf))
+        let received = u32::from_be_bytes(buf) & DATA_MASK;
+        match mode {
+            AccessMode::Write => trace!("write {} {:#09X}", addr.name(), data & DATA_MASK),
+            AccessMode::Read => trace!("read {} {:#09X}", addr.name(), received),
+        }
+        // Status registers change on their own, only the writable ones are worth keeping
+        if !addr.read_only() {
+            self.shadow[addr as usize] = Some(match mode {
+                AccessMode::Write => data & DATA_MASK,
+                AccessMode::Read => received,
+            });
+        }
+        Ok(received)
+    }
+
+    /// Frames `buf` with LE and transfers it, leaving the received bytes in `buf`
+    /// With the `critical-section` feature this runs in a critical section, so nothing else
+    /// can use the bus between the LE edges
+    fn send_frame(&mut self, addr: RegisterAddr, buf: &mut [u8; 4]) -> Result<(), Error<SPI, LE>> {
+        // LE only has to be low around the transfer, however long the pin takes to switch
+        self.le.set_low().map_err(|e| {
+            warning!("LE failed on {}", addr.name());
+            Error::LatchEnable(e)
+        })?;
+        self.spi.transfer(buf).map_err(|e| {
+            warning!("SPI transfer failed on {}", addr.name());
+            Error::Transfer(e)
+        })?;
+        self.le.set_high().map_err(|e| {
+            warning!("LE failed on {}", addr.name());
+            Error::LatchEnable(e)
+        })
     }
 
     pub(crate) fn read(&mut self, addr: RegisterAddr) -> Result<u32, Error<SPI, LE>> {
+        // A write held back by a batch is newer than the device's value
+        if let Some(data) = self.batch.as_ref().and_then(|batch| batch.get(addr)) {
+            return Ok(data);
+        }
+        // So do deferred writes, and with writes deferred or cached reads enabled the shadow is
+        // trusted to save a read
+        if self.dirty & (1 << addr as u16) != 0 || self.deferred || self.cached_reads {
+            if let Some(data) = self.shadow[addr as usize] {
+                return Ok(data);
+            }
+        }
+        // Without SDO the shadow is all there is
+        if let Interface::WriteOnly { .. } = self.interface {
+            return self.shadow[addr as usize].ok_or(Error::Unreadable(addr));
+        }
         self.operate(addr, 0, AccessMode::Read)
     }
 
+    /// Reads `addr` from the device, leaving its shadow value as it was
+    pub(crate) fn read_device(&mut self, addr: RegisterAddr) -> Result<u32, Error<SPI, LE>> {
+        if let Interface::WriteOnly { .. } = self.interface {
+            return Err(Error::Unreadable(addr));
+        }
+        let shadow = self.shadow[addr as usize];
+        let data = self.operate(addr, 0, AccessMode::Read)?;
+        self.shadow[addr as usize] = shadow;
+        Ok(data)
+    }
+
+    /// Sends `data` to `addr` on the device, past any batch or deferral and the strict checks
+    pub(crate) fn write_device(
+        &mut self,
+        addr: RegisterAddr,
+        data: u32,
+    ) -> Result<(), Error<SPI, LE>> {
+        self.operate(addr, data, AccessMode::Write)?;
+        Ok(())
+    }
+
     pub(crate) fn write(&mut self, addr: RegisterAddr, data: u32) -> Result<(), Error<SPI, LE>> {
+        if let Some(batch) = self.batch.as_mut() {
+            if addr.read_only() {
+                return Err(Error::ReadOnly);
+            }
+            batch.stage(addr, data & DATA_MASK);
+            return Ok(());
+        }
+        // Deferred writes are checked together by `flush`
+        if self.strict && !self.deferred {
+            self.check_write(addr, data & DATA_MASK)?;
+        }
+        self.write_unchecked(addr, data)
+    }
+
+    /// Writes without the strict mode checks, for batches that were checked as a whole
+    /// With writes deferred the value only goes to the shadow and is marked dirty
+    pub(crate) fn write_unchecked(
+        &mut self,
+        addr: RegisterAddr,
+        data: u32,
+    ) -> Result<(), Error<SPI, LE>> {
+        if self.deferred {
+            if addr.read_only() {
+                return Err(Error::ReadOnly);
+            }
+            self.shadow[addr as usize] = Some(data & DATA_MASK);
+            self.dirty |= 1 << addr as u16;
+            return Ok(());
+        }
         self.operate(addr, data, AccessMode::Write)?;
         Ok(())
     }
 
-    pub(crate) fn read_reg<R>(&mut self) -> Result<R, Error<SPI, LE>>
+    /// Writes to a read-only register on the bus, for `self_test` to check that the device
+    /// ignores it
+    pub(crate) fn write_read_only(
+        &mut self,
+        addr: RegisterAddr,
+        data: u32,
+    ) -> Result<(), Error<SPI, LE>> {
+        self.transfer_frame(addr, data, AccessMode::Write)?;
+        Ok(())
+    }
+
+    /// Reads and decodes a whole register, e.g. `vco.read_reg::<registers::ST4>()`
+    pub fn read_reg<R>(&mut self) -> Result<R, Error<SPI, LE>>
     where
-        R: Register + From<u32>,
+        R: Register,
     {
-        self.read(R::addr()).map(Into::into)
+        self.read(R::addr()).map(R::decode)
     }
 
-    pub(crate) fn write_reg<'a, R>(&mut self, register: &'a R) -> Result<(), Error<SPI, LE>>
+    /// The last value of a register sent to or read from the device, without touching the bus
+    /// Includes writes deferred by `defer_writes` that haven't been flushed yet
+    /// `None` until the register has been written or read once
+    pub fn cached_reg<R>(&self) -> Option<R>
     where
         R: Register,
-        &'a R: Into<u32>,
     {
-        self.write(R::addr(), register.into())
+        self.shadow[R::addr() as usize].map(R::decode)
+    }
+
+    /// Enables or disables cached reads, off by default
+    /// While enabled, reads of writable registers are answered from the shadow (see
+    /// `cached_reg`) once it holds a value, so read-modify-write setters only cost their write
+    /// Only use it while nothing else changes the registers, e.g. another SPI master or a reset
+    pub fn set_cached_reads(&mut self, cached: bool) {
+        self.cached_reads = cached;
+    }
+
+    /// Encodes and writes a whole register
+    /// Prefer the dedicated setters where they exist, they keep dependent settings consistent
+    /// In strict mode (see `set_strict`) the register is checked before it is written
+    /// ST9 is rejected, `init` and `init_from` write it with `ST9::INIT`
+    pub fn write_reg<R>(&mut self, register: &R) -> Result<(), Error<SPI, LE>>
+    where
+        R: Register,
+    {
+        // The reserved test register is left to initialization
+        if R::addr() == RegisterAddr::ST9 {
+            return Err(ValidationError::InitOnlyRegister.into());
+        }
+        if self.strict {
+            if let Some(field) = register.overflowing_field() {
+                return Err(ValidationError::FieldOverflow { field }.into());
+            }
+        }
+        self.write(R::addr(), register.encode())
     }
 }
 
 fn pack(addr: RegisterAddr, data: u32, mode: AccessMode) -> [u8; 4] {
-    // Guard against data size and read-only registers
-    assert!(data < (2_u32.pow(27)), "Data must be 27 bits");
-    if mode == AccessMode::Write {
-        assert!(!addr.read_only(), "Address is read only");
-    }
+    // Data is 27 bits, anything above would clobber the address and mode bits
     // data_bytes[0] contains the msb
-    let mut buf = data.to_be_bytes();
+    let mut buf = (data & DATA_MASK).to_be_bytes();
     // Zeroth index gets sent first, MSB first order
     buf[0] |= ((mode as u8) << 7) | ((addr as u8) << 3);
     buf
@@ -81,6 +231,7 @@ mod tests {
     use embedded_hal_mock as mock;
     use mock::pin::{Mock as PinMock, State as PinState, Transaction as PinTransaction};
     use mock::spi::{Mock as SpiMock, Transaction as SpiTransaction};
+    use proptest::prelude::*;
 
     #[test]
     fn register() {
@@ -100,6 +251,74 @@ mod tests {
         );
     }
 
+    #[test]
+    fn payload_masked() {
+        assert_eq!(
+            pack(RegisterAddr::ST0, 0xFFFFFFFF, AccessMode::Write),
+            [0x07, 0xFF, 0xFF, 0xFF]
+        );
+    }
+
+    /// Sends `register` to the mock device in a frame and reads it back in another
+    /// Read-only registers are filled in on the device side instead, like the device does
+    fn frame_round_trip<R: Register>(register: R) -> R {
+        use crate::mock::tester;
+        let mut vco = tester();
+        match R::addr().read_only() {
+            true => vco.spi.write(R::addr() as usize, register.encode()),
+            false => vco.write_reg(&register).unwrap(),
+        }
+        vco.read_reg().unwrap()
+    }
+
+    /// A property test of `frame_round_trip` for each register, over random field values
+    /// ST9 has no fields and ST11, the device ID, no register type
+    macro_rules! frame_round_trips {
+        ($($test:ident: $reg:ident,)*) => {
+            proptest! {
+                $(
+                    #[test]
+                    fn $test(register in crate::registers::$reg::arbitrary()) {
+                        prop_assert_eq!(frame_round_trip(register), register);
+                    }
+                )*
+            }
+        };
+    }
+
+    frame_round_trips!(
+        frame_round_trip_st0: ST0,
+        frame_round_trip_st1: ST1,
+        frame_round_trip_st2: ST2,
+        frame_round_trip_st3: ST3,
+        frame_round_trip_st4: ST4,
+        frame_round_trip_st5: ST5,
+        frame_round_trip_st6: ST6,
+        frame_round_trip_st7: ST7,
+        frame_round_trip_st8: ST8,
+        frame_round_trip_st10: ST10,
+    );
+
+    #[test]
+    fn write_read_only() {
+        let mut vco = STuW81300::new(
+            SpiMock::new(&[]),
+            PinMock::new(&[]),
+            crate::SupplyVoltage::HighVoltage,
+            crate::mock::REF_FREQ,
+            crate::ReferenceType::SingleEnded,
+        );
+        assert!(matches!(
+            vco.write(RegisterAddr::ST11, 0),
+            Err(Error::ReadOnly)
+        ));
+        // Nothing reaches the bus, not even the init value
+        assert!(matches!(
+            vco.write_reg(&crate::registers::ST9::INIT),
+            Err(Error::Validation(ValidationError::InitOnlyRegister))
+        ));
+    }
+
     fn spi_tester(mosi: Vec<u8>, miso: Vec<u8>) -> STuW81300<SpiMock, PinMock> {
         let spi_expectations = [SpiTransaction::transfer(mosi, miso)];
 
@@ -111,13 +330,13 @@ mod tests {
         let spi = SpiMock::new(&spi_expectations);
         let le = PinMock::new(&pin_expectations);
 
-        STuW81300 {
+        STuW81300::new(
             spi,
             le,
-            supply_voltage: crate::SupplyVoltage::HighVoltage,
-            ref_freq: 100e6,
-            ref_type: crate::ReferenceType::SingleEnded,
-        }
+            crate::SupplyVoltage::HighVoltage,
+            crate::mock::REF_FREQ,
+            crate::ReferenceType::SingleEnded,
+        )
     }
 
     #[test]
@@ -155,6 +374,8 @@ mod tests {
 
         let st6 = crate::registers::ST6 {
             dsm_order: 0,
+            vco_sel: 0,
+            vco_word: 0,
             prchg_del: 0,
             cal_div: 0,
             dithering: false,
@@ -0,0 +1,77 @@
+//! Startup time estimate for power sequencing budgets
+//!
+//! From power up to a locked output the device waits for its regulators, runs the VCO
+//! calibration and then lets the loop settle until the lock detector reports lock. The first
+//! two follow from the configuration, the calibration taking a fixed number of steps of
+//! calibrator clock cycles. Loop settling depends on the external loop filter, which the
+//! driver knows nothing about, so a typical figure for a loop bandwidth of around 100 kHz is
+//! assumed. Measure with `measure_lock_time` where the budget is tight.
+use crate::config::Config;
+use crate::math;
+use crate::power::POWER_UP_SETTLE_US;
+use crate::STuW81300;
+
+/// Binary search steps of a VCO calibration, 2 for the core and 5 for the word
+const CALIBRATION_STEPS: u64 = 7;
+/// Loop settling after the calibration assumed for the estimate, in microseconds
+const LOOP_SETTLE_US: u32 = 30;
+
+impl<SPI, LE, PD, MUTE, D, LD> STuW81300<SPI, LE, PD, MUTE, D, LD> {
+    /// Estimated time from power up to lock with `config` applied, in microseconds
+    /// The sum of the regulator settling, the VCO calibration at the calibrator frequency
+    /// `config` gives, an assumed loop settling time and the lock detector count. Without
+    /// automatic calibration the calibration time is left out
+    /// Returns `None` if `config` can't be applied, i.e. R is out of range or the PFD is too
+    /// fast for the calibrator
+    pub fn estimated_startup_us(&self, config: &Config) -> Option<u32> {
+        if !(1..=8191).contains(&config.reference_divider) {
+            return None;
+        }
+        let (num, den) = math::pfd_ratio(config.reference_path as u32, config.reference_divider);
+        let pfd = self.ref_freq * num / den;
+        let cal_div = math::caldiv_for(pfd)? as u64;
+        // Each step precharges for the configured delay, then compares for one cycle
+        let cycles = CALIBRATION_STEPS * (config.calibration.precharge_delay as u64 + 2);
+        let calibration_us = match config.calibration.auto {
+            true => (cycles * cal_div * 1_000_000).div_ceil(pfd),
+            false => 0,
+        };
+        let lock_detect_us = (config.lock_detector.count as u64 * 1_000_000).div_ceil(pfd);
+        Some(POWER_UP_SETTLE_US + calibration_us as u32 + LOOP_SETTLE_US + lock_detect_us as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::tester;
+
+    #[test]
+    fn estimated_startup() {
+        let vco = tester();
+        // A 50 MHz PFD runs the calibrator at 250 kHz, 7 steps of 5 cycles take 140 us
+        let config = Config {
+            reference_divider: 2,
+            ..Default::default()
+        };
+        assert_eq!(vco.estimated_startup_us(&config), Some(200 + 140 + 30 + 1));
+        // 100 MHz divided by 400 is 250 kHz as well
+        assert_eq!(
+            vco.estimated_startup_us(&Config::default()),
+            Some(200 + 140 + 30 + 1)
+        );
+        let config = Config {
+            reference_divider: 0,
+            ..Default::default()
+        };
+        assert_eq!(vco.estimated_startup_us(&config), None);
+        let config = Config {
+            calibration: crate::Calibration {
+                auto: false,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(vco.estimated_startup_us(&config), Some(200 + 30 + 1));
+    }
+}
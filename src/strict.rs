@@ -0,0 +1,190 @@
+//! Strict mode, checking every register write against the datasheet before it is sent
+//!
+//! The setters already validate their arguments, strict mode adds a last line of defense
+//! for raw `write_reg` calls and settings that depend on other registers. Each write is
+//! checked on its own, except inside bulk operations, where the whole batch is checked
+//! against its final values before any of it is sent.
+use crate::api::check_divider_ratio;
+use crate::error::{Error, ValidationError};
+use crate::registers::{self as regs, RegisterAddr};
+use crate::supply::check_supply;
+use crate::STuW81300;
+use embedded_hal as hal;
+use hal::blocking::spi::Transfer;
+use hal::digital::v2::OutputPin;
+
+impl<SPI, LE, PD, MUTE, D, LD> STuW81300<SPI, LE, PD, MUTE, D, LD>
+where
+    SPI: Transfer<u8>,
+    LE: OutputPin,
+{
+    /// Enables or disables strict mode, off by default
+    /// Rejects writes that break a datasheet rule with `Error::Validation` before they reach
+    /// the bus. The dependent rules read the registers they depend on, so writes cost extra
+    /// SPI transfers while enabled
+    /// R must be at least 1, so enable it once the reference divider is set, e.g. after
+    /// `apply_config`
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Whether strict mode is enabled
+    pub fn is_strict(&self) -> bool {
+        self.strict
+    }
+
+    /// Checks every write of the pending batch, reads see the pending values
+    pub(crate) fn check_batch(&mut self) -> Result<(), Error<SPI, LE>> {
+        let batch = self.batch.clone().unwrap_or_default();
+        for (addr, data) in batch.in_order() {
+            self.check_write(addr, data)?;
+        }
+        Ok(())
+    }
+
+    /// Checks writing `data` to `addr` against the field ranges and the registers it
+    /// depends on
+    pub(crate) fn check_write(
+        &mut self,
+        addr: RegisterAddr,
+        data: u32,
+    ) -> Result<(), Error<SPI, LE>> {
+        match addr {
+            RegisterAddr::ST0 => {
+                let st0 = regs::ST0::from(data);
+                let st1: regs::ST1 = self.read_reg()?;
+                let st6: regs::ST6 = self.read_reg()?;
+                check_divider_ratio(st0.n, st1.frac != 0, st6.dsm_order)?;
+            }
+            RegisterAddr::ST1 => {
+                let st1 = regs::ST1::from(data);
+                let st2: regs::ST2 = self.read_reg()?;
+                if st1.frac != 0 && st1.frac >= st2.modu {
+                    return Err(ValidationError::FracOutOfRange {
+                        given: st1.frac,
+                        max: st2.modu.saturating_sub(1),
+                    }
+                    .into());
+                }
+            }
+            RegisterAddr::ST2 => {
+                let st2 = regs::ST2::from(data);
+                if st2.modu < 2 {
+                    return Err(ValidationError::ModOutOfRange {
+                        given: st2.modu,
+                        min: 2,
+                        max: 2097151,
+                    }
+                    .into());
+                }
+            }
+            RegisterAddr::ST3 => {
+                let st3 = regs::ST3::from(data);
+                if st3.r == 0 {
+                    return Err(ValidationError::RefDividerOutOfRange {
+                        given: 0,
+                        min: 1,
+                        max: 8191,
+                    }
+                    .into());
+                }
+            }
+            RegisterAddr::ST4 => {
+                let st4 = regs::ST4::from(data);
+                check_supply(&st4, self.supply_voltage, self.variant)?;
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{tester, MockStuw81300LE, MockStuw81300SPI};
+    use crate::Config;
+
+    fn strict_tester() -> STuW81300<MockStuw81300SPI, MockStuw81300LE> {
+        let mut vco = tester();
+        vco.init().unwrap();
+        vco.apply_config(&Config {
+            reference_divider: 2,
+            ..Default::default()
+        })
+        .unwrap();
+        vco.set_mod(4).unwrap();
+        vco.set_n(100).unwrap();
+        vco.set_strict(true);
+        vco
+    }
+
+    #[test]
+    fn rejects_before_writing() {
+        let mut vco = strict_tester();
+        let before = vco.spi.read(1);
+        let st1 = regs::ST1 {
+            frac: 4,
+            ..vco.read_reg().unwrap()
+        };
+        assert!(matches!(
+            vco.write_reg(&st1),
+            Err(Error::Validation(ValidationError::FracOutOfRange {
+                given: 4,
+                max: 3
+            }))
+        ));
+        assert_eq!(vco.spi.read(1), before);
+        let st3 = regs::ST3 {
+            r: 0,
+            ..vco.read_reg().unwrap()
+        };
+        assert!(matches!(
+            vco.write_reg(&st3),
+            Err(Error::Validation(ValidationError::RefDividerOutOfRange {
+                given: 0,
+                ..
+            }))
+        ));
+        let st0 = regs::ST0 {
+            n: 1 << 17,
+            ..vco.read_reg().unwrap()
+        };
+        assert!(matches!(
+            vco.write_reg(&st0),
+            Err(Error::Validation(ValidationError::FieldOverflow {
+                field: "n"
+            }))
+        ));
+        // Setters that keep the registers consistent still go through
+        vco.set_frac(3).unwrap();
+    }
+
+    #[test]
+    fn checks_batches_as_a_whole() {
+        let mut vco = strict_tester();
+        // FRAC only fits the new MOD, which is written first
+        vco.batched(|vco| {
+            let st1: regs::ST1 = vco.read_reg()?;
+            vco.write_reg(&regs::ST1 { frac: 6, ..st1 })?;
+            let st2: regs::ST2 = vco.read_reg()?;
+            vco.write_reg(&regs::ST2 { modu: 8, ..st2 })
+        })
+        .unwrap();
+        assert_eq!(vco.read_reg::<regs::ST1>().unwrap().frac, 6);
+        // One bad write holds back the whole batch
+        let result = vco.batched(|vco| {
+            vco.set_charge_pump(3)?;
+            let st2: regs::ST2 = vco.read_reg()?;
+            vco.write_reg(&regs::ST2 { modu: 1, ..st2 })
+        });
+        assert!(matches!(
+            result,
+            Err(Error::Validation(ValidationError::ModOutOfRange {
+                given: 1,
+                ..
+            }))
+        ));
+        assert_ne!(vco.get_charge_pump().unwrap(), 3);
+    }
+}
@@ -0,0 +1,199 @@
+//! Bounded recovery after the PLL loses lock
+//!
+//! `supervise` is meant to be called periodically, e.g. from a housekeeping loop. While the
+//! PLL is locked it costs one lock detector read. After a loss of lock it reruns VCO
+//! calibration up to `max_attempts` times with a growing pause in between, optionally with a
+//! raised charge pump or fast lock for the acquisition, and reports what it took.
+use crate::error::Error;
+use crate::fast_lock::FastLock;
+use crate::logging::{debug, warning};
+use crate::recalibration::VcoWord;
+use crate::STuW81300;
+use embedded_hal as hal;
+use hal::blocking::delay::DelayUs;
+use hal::blocking::spi::Transfer;
+use hal::digital::v2::{InputPin, OutputPin};
+
+/// How `supervise` tries to get the PLL locked again
+#[derive(Debug, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RecoveryPolicy {
+    /// Recalibrations before giving up
+    pub max_attempts: u32,
+    /// How long each attempt waits for lock, in microseconds
+    pub lock_timeout_us: u32,
+    /// Pause after the first failed attempt in microseconds, doubled after every further one
+    pub backoff_us: u32,
+    /// Charge pump scaling factor during the attempts, `None` leaves it alone
+    pub charge_pump: Option<u32>,
+    /// Fast lock settings during the attempts, `None` leaves fast lock alone
+    pub fast_lock: Option<FastLock>,
+}
+
+impl Default for RecoveryPolicy {
+    /// Three attempts of 1 ms each, 100 us apart at first, with the loop settings unchanged
+    fn default() -> Self {
+        RecoveryPolicy {
+            max_attempts: 3,
+            lock_timeout_us: 1000,
+            backoff_us: 100,
+            charge_pump: None,
+            fast_lock: None,
+        }
+    }
+}
+
+/// Outcome of `supervise`
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RecoveryReport {
+    /// The PLL was unlocked when `supervise` was called
+    pub lock_lost: bool,
+    /// Recalibrations run, 0 if the PLL was locked
+    pub attempts: u32,
+    /// The PLL is locked again, or never lost lock
+    pub recovered: bool,
+    /// The VCO word of the successful recalibration
+    pub vco_word: Option<VcoWord>,
+}
+
+impl<SPI, LE, PD, MUTE, D, LD> STuW81300<SPI, LE, PD, MUTE, D, LD>
+where
+    SPI: Transfer<u8>,
+    LE: OutputPin,
+    PD: OutputPin,
+    MUTE: OutputPin,
+    LD: InputPin,
+    D: DelayUs<u32>,
+{
+    /// Checks the lock and, if it was lost, recalibrates following `policy`
+    /// Each attempt is a `recalibrate`, with the output muted if a mute pin is attached. The
+    /// charge pump and fast lock settings of `policy` apply for the attempts only, the
+    /// previous ones are restored afterwards whether or not lock came back, and also when an
+    /// attempt fails with an error
+    /// Running out of attempts is reported in the result, only bus errors return `Err`
+    pub fn supervise(&mut self, policy: &RecoveryPolicy) -> Result<RecoveryReport, Error<SPI, LE>> {
+        let mut report = RecoveryReport {
+            lock_lost: !self.is_locked()?,
+            attempts: 0,
+            recovered: true,
+            vco_word: None,
+        };
+        if !report.lock_lost {
+            return Ok(report);
+        }
+        warning!("lock lost, recovering");
+        let charge_pump = self.get_charge_pump()?;
+        let fast_lock = self.get_fast_lock()?;
+        report.recovered = false;
+        let recovery = self.recover(policy, &mut report);
+        // The previous settings go back before an error from the attempts is passed on
+        let restored = match policy.charge_pump {
+            Some(_) => self.set_charge_pump(charge_pump),
+            None => Ok(()),
+        };
+        let restored = match policy.fast_lock {
+            Some(_) => restored.and(self.set_fast_lock(fast_lock)),
+            None => restored,
+        };
+        recovery.and(restored)?;
+        match report.recovered {
+            true => debug!("relocked after {} attempts", report.attempts),
+            false => warning!("no relock after {} attempts", report.attempts),
+        }
+        Ok(report)
+    }
+
+    /// Applies the settings of `policy` and runs the recalibration attempts, for `supervise`
+    fn recover(
+        &mut self,
+        policy: &RecoveryPolicy,
+        report: &mut RecoveryReport,
+    ) -> Result<(), Error<SPI, LE>> {
+        if let Some(scale) = policy.charge_pump {
+            self.set_charge_pump(scale)?;
+        }
+        if policy.fast_lock.is_some() {
+            self.set_fast_lock(policy.fast_lock)?;
+        }
+        let mut backoff_us = policy.backoff_us;
+        while report.attempts < policy.max_attempts {
+            if report.attempts > 0 {
+                self.delay_us(backoff_us);
+                backoff_us = backoff_us.saturating_mul(2);
+            }
+            report.attempts += 1;
+            match self.recalibrate(policy.lock_timeout_us) {
+                Ok(word) => {
+                    report.recovered = true;
+                    report.vco_word = Some(word);
+                    return Ok(());
+                }
+                Err(Error::LockTimeout) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::float::hz;
+    use crate::mock::{initialized_tester, tester};
+    use crate::registers::{self as regs, Register};
+    use embedded_hal_mock as mock;
+    use mock::pin::{Mock as PinMock, State as PinState, Transaction as PinTransaction};
+
+    #[test]
+    fn supervise() {
+        let mut vco = initialized_tester();
+        vco.set_output_frequency(hz(5_000_000_000)).unwrap();
+        let charge_pump = vco.get_charge_pump().unwrap();
+        let policy = RecoveryPolicy {
+            charge_pump: Some(31),
+            ..Default::default()
+        };
+
+        // The mock never locks
+        let report = vco.supervise(&policy).unwrap();
+        assert_eq!(
+            report,
+            RecoveryReport {
+                lock_lost: true,
+                attempts: 3,
+                recovered: false,
+                vco_word: None,
+            }
+        );
+        assert_eq!(vco.get_charge_pump().unwrap(), charge_pump);
+
+        let st10 = regs::ST10 {
+            lock_det: true,
+            ..Default::default()
+        };
+        vco.spi.write(10, st10.encode());
+        let report = vco.supervise(&policy).unwrap();
+        assert!(!report.lock_lost && report.recovered);
+        assert_eq!(report.attempts, 0);
+    }
+
+    #[test]
+    fn restores_on_error() {
+        // Muting fails in the first attempt
+        let io = mock::MockError::Io(std::io::ErrorKind::Other);
+        let pin = PinMock::new(&[PinTransaction::set(PinState::High).with_error(io)]);
+        let mut vco = tester().with_mute_pin(pin);
+        vco.init().unwrap();
+        vco.set_reference_clock_divider(2).unwrap();
+        vco.set_output_frequency(hz(5_000_000_000)).unwrap();
+        let charge_pump = vco.get_charge_pump().unwrap();
+        let policy = RecoveryPolicy {
+            charge_pump: Some(31),
+            ..Default::default()
+        };
+        assert!(matches!(vco.supervise(&policy), Err(Error::MutePin)));
+        assert_eq!(vco.get_charge_pump().unwrap(), charge_pump);
+    }
+}
@@ -0,0 +1,209 @@
+//! Consistency of the supply-dependent ST4 fields with the declared `SupplyVoltage`
+use crate::error::{Error, ValidationError};
+use crate::float::{hz, Hz};
+use crate::registers as regs;
+use crate::{DeviceVariant, STuW81300, SupplyVoltage};
+use embedded_hal as hal;
+use hal::blocking::spi::Transfer;
+use hal::digital::v2::{InputPin, OutputPin};
+
+/// Largest VCO amplitude setting for `supply`
+pub(crate) fn max_vco_amplitude(supply: SupplyVoltage) -> u32 {
+    match supply {
+        SupplyVoltage::LowVoltage => 2,
+        SupplyVoltage::HighVoltage => 7,
+    }
+}
+
+/// Sets the RF output and calibrator voltage modes of `st4` for `supply`
+/// On the 5 V supply the calibrator voltage mode follows the VCO frequency, so it is left
+/// for the caller
+pub(crate) fn configure_supply(st4: &mut regs::ST4, supply: SupplyVoltage, variant: DeviceVariant) {
+    let low = supply == SupplyVoltage::LowVoltage;
+    // The -1T variant runs its calibrator in the 5 V mode on either supply
+    let calb_3v3 = low && variant == DeviceVariant::Stuw81300T;
+    st4.calb_3v3_mode0 = calb_3v3;
+    st4.calb_3v3_mode1 = calb_3v3;
+    st4.rf_out_3v3 = low;
+    if low {
+        st4.vcalb_mode = true;
+    }
+}
+
+/// Checks every supply-dependent field of `st4` against `supply`
+/// The calibrator 3.3 V modes are only checked once the variant is known
+pub(crate) fn check_supply(
+    st4: &regs::ST4,
+    supply: SupplyVoltage,
+    variant: Option<DeviceVariant>,
+) -> Result<(), ValidationError> {
+    let low = supply == SupplyVoltage::LowVoltage;
+    let max = max_vco_amplitude(supply);
+    if st4.vco_amp > max {
+        return Err(ValidationError::VcoAmplitudeOutOfRange {
+            given: st4.vco_amp,
+            max,
+        });
+    }
+    let mismatch = |field| Err(ValidationError::SupplyMismatch { field, supply });
+    if st4.rf_out_3v3 != low {
+        return mismatch("rf_out_3v3");
+    }
+    let calb_3v3 = low && variant == Some(DeviceVariant::Stuw81300T);
+    if variant.is_some() {
+        if st4.calb_3v3_mode0 != calb_3v3 {
+            return mismatch("calb_3v3_mode0");
+        }
+        if st4.calb_3v3_mode1 != calb_3v3 {
+            return mismatch("calb_3v3_mode1");
+        }
+    }
+    // Only the 5 V supply picks the calibrator voltage mode by frequency
+    if low && !st4.vcalb_mode {
+        return mismatch("vcalb_mode");
+    }
+    Ok(())
+}
+
+impl<SPI, LE, PD, MUTE, D, LD> STuW81300<SPI, LE, PD, MUTE, D, LD>
+where
+    SPI: Transfer<u8>,
+    LE: OutputPin,
+{
+    /// Checks every supply-dependent setting in ST4 against the declared `SupplyVoltage`
+    /// The VCO amplitude, RF output and calibrator voltage modes all have to match the
+    /// supply, see `init`, `set_vco_amplitude` and `set_output_frequency`. Run by `init` and
+    /// `apply_config`, and on every ST4 write in strict mode
+    pub fn validate_against_supply(&mut self) -> Result<(), Error<SPI, LE>> {
+        let st4: regs::ST4 = self.read_reg()?;
+        Ok(check_supply(&st4, self.supply_voltage, self.variant)?)
+    }
+}
+
+impl<SPI, LE, PD, MUTE, D, LD> STuW81300<SPI, LE, PD, MUTE, D, LD>
+where
+    SPI: Transfer<u8>,
+    LE: OutputPin,
+    PD: OutputPin,
+    MUTE: OutputPin,
+    LD: InputPin,
+{
+    /// Switches to a different supply voltage on pin 36 without reconstructing the driver
+    /// Rewrites the RF output and calibrator voltage modes for the new supply and lowers the
+    /// VCO amplitude to the new maximum if it is above it. Before `init` only the declared
+    /// supply changes, `init` programs the rest
+    pub fn set_supply_voltage(&mut self, supply: SupplyVoltage) -> Result<(), Error<SPI, LE>> {
+        let Some(variant) = self.variant else {
+            self.supply_voltage = supply;
+            return Ok(());
+        };
+        let mut st4: regs::ST4 = self.read_reg()?;
+        configure_supply(&mut st4, supply, variant);
+        st4.vco_amp = st4.vco_amp.min(max_vco_amplitude(supply));
+        if supply == SupplyVoltage::HighVoltage {
+            st4.vcalb_mode = self.vco_frequency()? > hz(4_500_000_000);
+        }
+        // Strict mode checks the write against the new supply
+        let previous = core::mem::replace(&mut self.supply_voltage, supply);
+        let result = self.write_reg(&st4);
+        if result.is_err() {
+            self.supply_voltage = previous;
+        }
+        result
+    }
+
+    /// The VCO frequency from the registers, 0 until R and MOD are configured
+    fn vco_frequency(&mut self) -> Result<Hz, Error<SPI, LE>> {
+        let st2: regs::ST2 = self.read_reg()?;
+        let st3: regs::ST3 = self.read_reg()?;
+        if st2.modu == 0 || st3.r == 0 {
+            return Ok(hz(0));
+        }
+        Ok(self.clock_tree()?.vco_freq)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{initialized_tester, tester_with};
+    use crate::registers::Register;
+
+    #[test]
+    fn flags_mismatches() {
+        let mut vco = tester_with(SupplyVoltage::LowVoltage, 100_000_000);
+        vco.init().unwrap();
+        vco.validate_against_supply().unwrap();
+
+        let st4: regs::ST4 = vco.read_reg().unwrap();
+        for (field, bad) in [
+            (
+                "rf_out_3v3",
+                regs::ST4 {
+                    rf_out_3v3: false,
+                    ..st4
+                },
+            ),
+            (
+                "calb_3v3_mode0",
+                regs::ST4 {
+                    calb_3v3_mode0: false,
+                    ..st4
+                },
+            ),
+            (
+                "vcalb_mode",
+                regs::ST4 {
+                    vcalb_mode: false,
+                    ..st4
+                },
+            ),
+        ] {
+            vco.spi.write(4, bad.encode());
+            assert!(matches!(
+                vco.validate_against_supply(),
+                Err(Error::Validation(ValidationError::SupplyMismatch {
+                    field: f,
+                    supply: SupplyVoltage::LowVoltage,
+                })) if f == field
+            ));
+        }
+        vco.spi.write(4, regs::ST4 { vco_amp: 3, ..st4 }.encode());
+        assert!(matches!(
+            vco.validate_against_supply(),
+            Err(Error::Validation(ValidationError::VcoAmplitudeOutOfRange {
+                given: 3,
+                max: 2
+            }))
+        ));
+        // A stale amplitude from a 5 V setup fails init on a 3.3 V supply
+        assert!(vco.init().is_err());
+
+        let mut vco = tester_with(SupplyVoltage::HighVoltage, 100_000_000);
+        vco.init().unwrap();
+        vco.validate_against_supply().unwrap();
+    }
+
+    #[test]
+    fn switch_supply() {
+        let mut vco = initialized_tester();
+        vco.set_vco_amplitude(7).unwrap();
+        vco.set_output_frequency(hz(4_000_000_000)).unwrap();
+        vco.set_strict(true);
+
+        vco.set_supply_voltage(SupplyVoltage::LowVoltage).unwrap();
+        vco.validate_against_supply().unwrap();
+        let st4: regs::ST4 = vco.read_reg().unwrap();
+        assert_eq!(st4.vco_amp, 2);
+        assert!(st4.rf_out_3v3 && st4.calb_3v3_mode0 && st4.calb_3v3_mode1 && st4.vcalb_mode);
+        assert!(vco.set_vco_amplitude(3).is_err());
+
+        // Back on 5 V the calibrator mode follows the 4 GHz VCO again
+        vco.set_supply_voltage(SupplyVoltage::HighVoltage).unwrap();
+        vco.validate_against_supply().unwrap();
+        let st4: regs::ST4 = vco.read_reg().unwrap();
+        assert_eq!(st4.vco_amp, 2);
+        assert!(!st4.rf_out_3v3 && !st4.calb_3v3_mode0 && !st4.vcalb_mode);
+        vco.set_vco_amplitude(7).unwrap();
+    }
+}
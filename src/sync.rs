@@ -0,0 +1,297 @@
+//! Coherent retuning of several chips
+//!
+//! ST1, ST2 and ST3 are double buffered: with their DBR bit set, a write only lands in a
+//! buffer and takes effect on the next ST0 write. Retuning a group stages FRAC and MOD on every
+//! chip first, then writes ST0 to each chip back to back so the new dividers (and VCO
+//! calibration) start as close together as the bus allows.
+//!
+//! ST4 and ST6 have no DBR bit, so the calibrator and dithering settings planned for a staged
+//! frequency are held back in the driver and written just before its ST0.
+use crate::error::{Error, ValidationError};
+use crate::registers::{self as regs, DoubleBuffered, Register, RegisterAddr};
+use crate::{Hz, NoDelay, NoPin, STuW81300};
+use embedded_hal as hal;
+use hal::blocking::spi::Transfer;
+use hal::digital::v2::{InputPin, OutputPin};
+
+/// A frequency staged with `stage_output_frequency`, with the registers `latch` writes
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct StagedFrequency {
+    st0: regs::ST0,
+    st4: regs::ST4,
+    st6: regs::ST6,
+    f: Hz,
+}
+
+impl<SPI, LE, PD, MUTE, D, LD> STuW81300<SPI, LE, PD, MUTE, D, LD>
+where
+    SPI: Transfer<u8>,
+    LE: OutputPin,
+    PD: OutputPin,
+    MUTE: OutputPin,
+    LD: InputPin,
+{
+    /// Plans output frequency `f` and writes ST1 and ST2 double buffered
+    /// ST4, ST6 and ST0 are held back, nothing the device is running with changes until
+    /// `latch` writes them
+    pub fn stage_output_frequency(&mut self, f: Hz) -> Result<(), Error<SPI, LE>> {
+        let staged = self.batched(|vco| {
+            let held = |vco: &Self, addr| vco.batch.as_ref().and_then(|batch| batch.get(addr));
+            let (st4, st6) = (held(vco, RegisterAddr::ST4), held(vco, RegisterAddr::ST6));
+            let (st0, mut st1, mut st2) = vco.prepare_output_frequency(f)?;
+            st1.dbr = true;
+            st2.dbr = true;
+            vco.write_reg(&st2)?;
+            vco.write_reg(&st1)?;
+            let staged = StagedFrequency {
+                st0,
+                st4: vco.read_reg()?,
+                st6: vco.read_reg()?,
+                f,
+            };
+            // Back to what the batch held before planning, `latch` writes the planned values
+            if let Some(batch) = vco.batch.as_mut() {
+                batch.reset(RegisterAddr::ST4, st4);
+                batch.reset(RegisterAddr::ST6, st6);
+            }
+            Ok(staged)
+        })?;
+        self.staged = Some(staged);
+        self.buffered |= 1 << RegisterAddr::ST1 as u16 | 1 << RegisterAddr::ST2 as u16;
+        Ok(())
+    }
+
+    /// Writes `register` double buffered, it takes effect on `latch`
+    /// Only registers with a DBR bit can be staged. Any other ST0 write latches it as well
+    pub fn stage<R: DoubleBuffered>(&mut self, register: &R) -> Result<(), Error<SPI, LE>> {
+        let mut register = *register;
+        register.set_dbr(true);
+        self.write_reg(&register)?;
+        self.buffered |= 1 << R::addr() as u16;
+        Ok(())
+    }
+
+    /// Applies everything staged with `stage_output_frequency` or `stage` with one ST0 write,
+    /// then clears double buffering again. Does nothing if nothing is staged
+    /// Without a staged frequency ST0 is rewritten unchanged
+    pub fn latch(&mut self) -> Result<(), Error<SPI, LE>> {
+        self.write_staged_settings()?;
+        if self.write_staged()? {
+            self.finish_staged()?;
+        }
+        Ok(())
+    }
+
+    /// Whether `latch` has anything to apply
+    fn has_staged(&self) -> bool {
+        self.staged.is_some() || self.buffered != 0
+    }
+
+    /// Writes the ST4 and ST6 held back for a staged frequency, if any
+    fn write_staged_settings(&mut self) -> Result<(), Error<SPI, LE>> {
+        if let Some(staged) = self.staged {
+            self.write_reg(&staged.st4)?;
+            self.write_reg(&staged.st6)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the staged ST0, or the current one, transferring the buffered registers
+    /// Returns false if nothing was staged
+    fn write_staged(&mut self) -> Result<bool, Error<SPI, LE>> {
+        let word = match self.staged.as_ref() {
+            Some(staged) => staged.st0.encode(),
+            None if self.buffered != 0 => self.read(RegisterAddr::ST0)?,
+            None => return Ok(false),
+        };
+        self.write(RegisterAddr::ST0, word)?;
+        Ok(true)
+    }
+
+    /// Clears double buffering again so later writes to the staged registers apply immediately
+    fn finish_staged(&mut self) -> Result<(), Error<SPI, LE>> {
+        let buffered = core::mem::take(&mut self.buffered);
+        self.batched(|vco| {
+            if buffered & 1 << RegisterAddr::ST1 as u16 != 0 {
+                vco.unbuffer::<regs::ST1>()?;
+            }
+            if buffered & 1 << RegisterAddr::ST2 as u16 != 0 {
+                vco.unbuffer::<regs::ST2>()?;
+            }
+            if buffered & 1 << RegisterAddr::ST3 as u16 != 0 {
+                vco.unbuffer::<regs::ST3>()?;
+            }
+            Ok(())
+        })?;
+        match self.staged.take() {
+            Some(StagedFrequency { f, .. }) => {
+                self.requested_freq = Some(f);
+                self.programmed_freq = Some(self.get_output_frequency()?);
+            }
+            // Raw register contents, the divider or PFD frequency may have changed
+            None => self.forget_tuning(),
+        }
+        Ok(())
+    }
+
+    /// Rewrites `R` with its DBR bit cleared
+    fn unbuffer<R: DoubleBuffered>(&mut self) -> Result<(), Error<SPI, LE>> {
+        let mut register: R = self.read_reg()?;
+        register.set_dbr(false);
+        self.write_reg(&register)
+    }
+}
+
+/// A set of chips retuned together with minimal skew
+/// Errors carry the index of the chip that failed, chips before it may be left staged
+pub struct SyncGroup<'a, SPI, LE, PD = NoPin, MUTE = NoPin, D = NoDelay, LD = NoPin> {
+    chips: &'a mut [STuW81300<SPI, LE, PD, MUTE, D, LD>],
+}
+
+impl<'a, SPI, LE, PD, MUTE, D, LD> SyncGroup<'a, SPI, LE, PD, MUTE, D, LD>
+where
+    SPI: Transfer<u8>,
+    LE: OutputPin,
+    PD: OutputPin,
+    MUTE: OutputPin,
+    LD: InputPin,
+{
+    /// Groups already initialized and configured chips
+    pub fn new(chips: &'a mut [STuW81300<SPI, LE, PD, MUTE, D, LD>]) -> Self {
+        SyncGroup { chips }
+    }
+
+    /// Tunes every chip to `f`
+    pub fn set_output_frequency(&mut self, f: Hz) -> Result<(), (usize, Error<SPI, LE>)> {
+        for (i, chip) in self.chips.iter_mut().enumerate() {
+            chip.stage_output_frequency(f).map_err(|e| (i, e))?;
+        }
+        self.latch()
+    }
+
+    /// Tunes each chip to its own frequency, `fs` must have one entry per chip
+    /// Otherwise nothing is written and `ValidationError::FrequencyCountMismatch` is returned
+    /// with the index of the first chip or frequency without a partner
+    pub fn set_output_frequencies(&mut self, fs: &[Hz]) -> Result<(), (usize, Error<SPI, LE>)> {
+        if fs.len() != self.chips.len() {
+            let error = ValidationError::FrequencyCountMismatch {
+                given: fs.len(),
+                chips: self.chips.len(),
+            };
+            return Err((fs.len().min(self.chips.len()), error.into()));
+        }
+        for (i, (chip, f)) in self.chips.iter_mut().zip(fs).enumerate() {
+            chip.stage_output_frequency(*f).map_err(|e| (i, e))?;
+        }
+        self.latch()
+    }
+
+    /// Latches the staged frequencies of every chip in quick succession
+    /// The held back ST4 and ST6 of every chip go out first, then the ST0 writes back to back,
+    /// the cleanup reads and writes only happen afterwards
+    pub fn latch(&mut self) -> Result<(), (usize, Error<SPI, LE>)> {
+        for (i, chip) in self.chips.iter_mut().enumerate() {
+            chip.write_staged_settings().map_err(|e| (i, e))?;
+        }
+        for (i, chip) in self.chips.iter_mut().enumerate() {
+            chip.write_staged().map_err(|e| (i, e))?;
+        }
+        for (i, chip) in self.chips.iter_mut().enumerate() {
+            if chip.has_staged() {
+                chip.finish_staged().map_err(|e| (i, e))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Gives the chips back
+    pub fn free(self) -> &'a mut [STuW81300<SPI, LE, PD, MUTE, D, LD>] {
+        self.chips
+    }
+}
+
+#[cfg(test)]
+#[cfg(not(feature = "no-float"))]
+mod tests {
+    use super::*;
+    use crate::api::ReferenceClockPath;
+    use crate::mock::{assert_freq_eq, initialized_tester, MockStuw81300LE, MockStuw81300SPI};
+
+    fn chip() -> STuW81300<MockStuw81300SPI, MockStuw81300LE> {
+        let mut vco = initialized_tester();
+        vco.set_reference_clock_path(ReferenceClockPath::Direct)
+            .unwrap();
+        vco
+    }
+
+    #[test]
+    fn stage_then_latch() {
+        let mut vco = chip();
+        vco.spi.take_writes();
+        vco.stage_output_frequency(5e9).unwrap();
+        // Only the double buffered registers go out, N isn't written until the latch
+        assert_eq!(vco.spi.take_writes(), [2, 1]);
+        assert_eq!(vco.read_reg::<regs::ST0>().unwrap().n, 0);
+        assert!(vco.read_reg::<regs::ST1>().unwrap().dbr);
+        vco.latch().unwrap();
+        assert_eq!(vco.spi.take_writes()[..3], [4, 6, 0]);
+        assert_freq_eq!(vco.get_output_frequency().unwrap(), 5e9);
+        assert!(!vco.read_reg::<regs::ST1>().unwrap().dbr);
+        assert!(!vco.read_reg::<regs::ST2>().unwrap().dbr);
+        assert_freq_eq!(vco.programmed_frequency().unwrap(), 5e9);
+    }
+
+    #[test]
+    fn stage_registers() {
+        let mut vco = chip();
+        vco.set_output_frequency(5e9).unwrap();
+        let before = vco.spi.simulated_output_hz().unwrap();
+        let st1: regs::ST1 = vco.read_reg().unwrap();
+        vco.stage(&regs::ST1 {
+            frac: st1.frac + 1000,
+            ..st1
+        })
+        .unwrap();
+        let st3: regs::ST3 = vco.read_reg().unwrap();
+        vco.stage(&st3).unwrap();
+        assert!(vco.read_reg::<regs::ST3>().unwrap().dbr);
+        assert_eq!(vco.spi.simulated_output_hz().unwrap(), before);
+
+        vco.spi.take_writes();
+        vco.latch().unwrap();
+        // ST0 unchanged, then the DBR bits cleared
+        assert_eq!(vco.spi.take_writes(), [0, 3, 1]);
+        assert!(vco.spi.simulated_output_hz().unwrap() > before);
+        assert!(!vco.read_reg::<regs::ST1>().unwrap().dbr);
+        assert!(!vco.read_reg::<regs::ST3>().unwrap().dbr);
+        assert_eq!(vco.programmed_frequency(), None);
+        // Nothing left to latch
+        vco.latch().unwrap();
+        assert!(vco.spi.take_writes().is_empty());
+    }
+
+    #[test]
+    fn group() {
+        let mut chips = [chip(), chip(), chip()];
+        let mut group = SyncGroup::new(&mut chips);
+        group.set_output_frequency(7e9).unwrap();
+        group.set_output_frequencies(&[3.5e9, 4e9, 4.5e9]).unwrap();
+        let chips = group.free();
+        for (chip, f) in chips.iter_mut().zip([3.5e9, 4e9, 4.5e9]) {
+            assert_freq_eq!(chip.get_output_frequency().unwrap(), f);
+        }
+        assert!(matches!(
+            SyncGroup::new(chips).set_output_frequencies(&[5e9, 9e9, 5e9]),
+            Err((1, Error::FrequencyOutOfRange { .. }))
+        ));
+        chips[0].spi.take_writes();
+        assert!(matches!(
+            SyncGroup::new(chips).set_output_frequencies(&[5e9, 5e9]),
+            Err((
+                2,
+                Error::Validation(ValidationError::FrequencyCountMismatch { given: 2, chips: 3 })
+            ))
+        ));
+        assert!(chips[0].spi.take_writes().is_empty());
+    }
+}
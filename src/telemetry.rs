@@ -0,0 +1,131 @@
+//! Compact status samples for a telemetry link
+//!
+//! `telemetry` is meant to be sampled at a fixed rate and shipped over CAN, UART or a radio
+//! link, so it costs at most two SPI reads: ST10 for the status and ST0 for the charge pump
+//! and integer divider. The rest of the frequency comes from the shadow registers, which hold
+//! what the driver last wrote.
+use crate::error::Error;
+use crate::registers as regs;
+use crate::{Hz, STuW81300};
+use embedded_hal as hal;
+use hal::blocking::spi::Transfer;
+use hal::digital::v2::{InputPin, OutputPin};
+
+/// One telemetry sample, see `telemetry`
+#[derive(Debug, PartialEq, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Telemetry {
+    /// The output frequency in Hz, `None` until ST1, ST2, ST3 and ST6 are in the shadow
+    pub frequency: Option<Hz>,
+    /// ST10 LOCK_DET
+    pub locked: bool,
+    /// The overcurrent flags of ST10, bit 0 for the VCO 4.5 V regulator up to bit 4 for the
+    /// digital one, see `OCP_*`
+    pub ocp: u8,
+    /// VCO core chosen by the calibration, ST10 VCO_SEL
+    pub vco_core: u8,
+    /// Band within the core chosen by the calibration, ST10 WORD
+    pub vco_word: u8,
+    /// Charge pump scaling factor, ST0 CP_SEL
+    pub charge_pump: u8,
+}
+
+impl Telemetry {
+    /// `ocp` bit of the VCO 4.5 V regulator, REG_VCO_4V5_OCP
+    pub const OCP_VCO_4V5: u8 = 1 << 0;
+    /// `ocp` bit of the VCO regulator, REG_VCO_OCP
+    pub const OCP_VCO: u8 = 1 << 1;
+    /// `ocp` bit of the RF regulator, REG_RF_OCP
+    pub const OCP_RF: u8 = 1 << 2;
+    /// `ocp` bit of the reference regulator, REG_REF_OCP
+    pub const OCP_REF: u8 = 1 << 3;
+    /// `ocp` bit of the digital regulator, REG_DIG_OCP
+    pub const OCP_DIG: u8 = 1 << 4;
+}
+
+impl<SPI, LE, PD, MUTE, D, LD> STuW81300<SPI, LE, PD, MUTE, D, LD>
+where
+    SPI: Transfer<u8>,
+    LE: OutputPin,
+    PD: OutputPin,
+    MUTE: OutputPin,
+    LD: InputPin,
+{
+    /// Samples the frequency, lock, overcurrent flags, calibration result and charge pump
+    /// Reads ST10 and ST0, the frequency also uses the shadow of ST1, ST2, ST3 and ST6 and
+    /// reflects the registers rather than what the PLL is doing while unlocked
+    pub fn telemetry(&mut self) -> Result<Telemetry, Error<SPI, LE>> {
+        let st10: regs::ST10 = self.read_reg()?;
+        let st0: regs::ST0 = self.read_reg()?;
+        let frequency = (|| {
+            Some(self.output_frequency_of(
+                &st0,
+                &self.cached_reg()?,
+                &self.cached_reg()?,
+                &self.cached_reg()?,
+                &self.cached_reg()?,
+            ))
+        })();
+        let ocp = [
+            st10.reg_vco_4v5_ocp,
+            st10.reg_vco_ocp,
+            st10.reg_rf_ocp,
+            st10.reg_ref_ocp,
+            st10.reg_dig_ocp,
+        ]
+        .iter()
+        .enumerate()
+        .fold(0, |ocp, (bit, &flag)| ocp | (flag as u8) << bit);
+        Ok(Telemetry {
+            frequency,
+            locked: st10.lock_det,
+            ocp,
+            vco_core: st10.vco_sel as u8,
+            vco_word: st10.word as u8,
+            charge_pump: st0.cp_sel as u8,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::float::hz;
+    use crate::mock::{assert_freq_eq, tester};
+    use crate::registers::Register;
+
+    #[test]
+    fn telemetry() {
+        let mut vco = tester();
+        // Nothing in the shadow yet
+        assert_eq!(vco.telemetry().unwrap().frequency, None);
+
+        vco.init().unwrap();
+        vco.set_reference_clock_divider(2).unwrap();
+        vco.set_output_frequency(hz(5_000_000_000)).unwrap();
+        vco.set_charge_pump(12).unwrap();
+        let st10 = regs::ST10 {
+            vco_sel: 2,
+            word: 17,
+            reg_rf_ocp: true,
+            reg_dig_ocp: true,
+            lock_det: true,
+            ..Default::default()
+        };
+        vco.spi.write(10, st10.encode());
+
+        let sample = vco.telemetry().unwrap();
+        assert_freq_eq!(sample.frequency.unwrap(), hz(5_000_000_000));
+        assert_eq!(
+            (
+                sample.locked,
+                sample.vco_core,
+                sample.vco_word,
+                sample.charge_pump
+            ),
+            (true, 2, 17, 12)
+        );
+        assert_eq!(sample.ocp, Telemetry::OCP_RF | Telemetry::OCP_DIG);
+    }
+}
@@ -0,0 +1,139 @@
+//! `uom` quantities for the frequency and charge pump settings
+//!
+//! Frequencies use the same storage as `Hz`: `f32` by default, `f64` with the `f64` feature
+//! and `u64` with `no-float`. The plain `Hz` methods stay the primary API, these wrap them.
+use crate::error::Error;
+use crate::{ReferenceType, STuW81300, SupplyVoltage};
+use embedded_hal as hal;
+use hal::blocking::spi::Transfer;
+use hal::digital::v2::{InputPin, OutputPin};
+use uom::si::frequency::hertz;
+
+#[cfg(not(any(feature = "f64", feature = "no-float")))]
+pub use uom::si::f32::Frequency;
+#[cfg(all(feature = "f64", not(feature = "no-float")))]
+pub use uom::si::f64::Frequency;
+#[cfg(feature = "no-float")]
+pub use uom::si::u64::Frequency;
+
+#[cfg(not(feature = "no-float"))]
+pub use uom::si::f32::ElectricCurrent;
+
+/// Charge pump current per step of the scaling factor (Imin), in microamperes
+#[cfg(not(feature = "no-float"))]
+const CHARGE_PUMP_STEP_UA: f32 = 160.0;
+
+impl<SPI, LE> STuW81300<SPI, LE> {
+    /// Creates the driver for a reference of `ref_freq`, rounded to the nearest Hz
+    pub fn new_quantity(
+        spi: SPI,
+        le: LE,
+        supply_voltage: SupplyVoltage,
+        ref_freq: Frequency,
+        ref_type: ReferenceType,
+    ) -> Self {
+        Self::new(spi, le, supply_voltage, ref_freq.get::<hertz>(), ref_type)
+    }
+}
+
+impl<SPI, LE, PD, MUTE, D, LD> STuW81300<SPI, LE, PD, MUTE, D, LD>
+where
+    SPI: Transfer<u8>,
+    LE: OutputPin,
+    PD: OutputPin,
+    MUTE: OutputPin,
+    LD: InputPin,
+{
+    /// The reference frequency
+    pub fn reference_frequency_quantity(&self) -> Frequency {
+        Frequency::new::<hertz>(crate::float::hz(self.ref_freq))
+    }
+
+    /// Gets the PFD frequency, see `get_pfd_frequency`
+    pub fn get_pfd_frequency_quantity(&mut self) -> Result<Frequency, Error<SPI, LE>> {
+        Ok(Frequency::new::<hertz>(self.get_pfd_frequency()?))
+    }
+
+    /// Gets the output frequency, see `get_output_frequency`
+    pub fn get_output_frequency_quantity(&mut self) -> Result<Frequency, Error<SPI, LE>> {
+        Ok(Frequency::new::<hertz>(self.get_output_frequency()?))
+    }
+
+    /// Sets the output frequency, see `set_output_frequency`
+    pub fn set_output_frequency_quantity(&mut self, f: Frequency) -> Result<(), Error<SPI, LE>> {
+        self.set_output_frequency(f.get::<hertz>())
+    }
+
+    /// Sets the charge pump current, rounded to the nearest multiple of Imin (160 uA)
+    /// Returns a validation error above 31 * Imin, see `set_charge_pump`
+    #[cfg(not(feature = "no-float"))]
+    pub fn set_charge_pump_current(
+        &mut self,
+        current: ElectricCurrent,
+    ) -> Result<(), Error<SPI, LE>> {
+        use uom::si::electric_current::microampere;
+        // Negative currents saturate to a scale of 0
+        let steps = current.get::<microampere>() / CHARGE_PUMP_STEP_UA;
+        self.set_charge_pump(crate::float::round(crate::Float::from(steps)) as u32)
+    }
+
+    /// Gets the charge pump current
+    #[cfg(not(feature = "no-float"))]
+    pub fn get_charge_pump_current(&mut self) -> Result<ElectricCurrent, Error<SPI, LE>> {
+        use uom::si::electric_current::microampere;
+        let scale = self.get_charge_pump()?;
+        Ok(ElectricCurrent::new::<microampere>(
+            scale as f32 * CHARGE_PUMP_STEP_UA,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::ReferenceClockPath;
+    use crate::float::hz;
+    use crate::mock::{assert_freq_eq, MockStuw81300LE, MockStuw81300SPI};
+    use uom::si::frequency::megahertz;
+
+    #[test]
+    fn quantities() {
+        let mut vco = STuW81300::new_quantity(
+            MockStuw81300SPI::default(),
+            MockStuw81300LE::default(),
+            SupplyVoltage::HighVoltage,
+            Frequency::new::<megahertz>(100 as _),
+            ReferenceType::SingleEnded,
+        );
+        vco.init().unwrap();
+        vco.set_reference_clock_path(ReferenceClockPath::Direct)
+            .unwrap();
+        vco.set_reference_clock_divider(2).unwrap();
+        assert_eq!(
+            vco.get_pfd_frequency_quantity().unwrap().get::<hertz>(),
+            hz(50_000_000)
+        );
+        vco.set_output_frequency_quantity(Frequency::new::<hertz>(hz(5_000_000_000)))
+            .unwrap();
+        assert_freq_eq!(
+            vco.get_output_frequency_quantity().unwrap().get::<hertz>(),
+            hz(5_000_000_000)
+        );
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-float"))]
+    fn charge_pump_current() {
+        use uom::si::electric_current::milliampere;
+        let mut vco = crate::mock::tester();
+        vco.set_charge_pump_current(ElectricCurrent::new::<milliampere>(2.0))
+            .unwrap();
+        // 2 mA is 12.5 steps of 160 uA, which rounds away from zero
+        assert_eq!(vco.get_charge_pump().unwrap(), 13);
+        let current = vco.get_charge_pump_current().unwrap();
+        assert!((current.get::<milliampere>() - 2.08).abs() < 1e-6);
+        assert!(vco
+            .set_charge_pump_current(ElectricCurrent::new::<milliampere>(6.0))
+            .is_err());
+    }
+}
@@ -0,0 +1,78 @@
+//! Acceptance test for assembled boards, run against real hardware
+//!
+//! Needs the `hil-test` feature, a Linux host with the device on spidev and LE on a GPIO
+//! character device, and is ignored unless asked for:
+//!
+//! ```text
+//! STUW81300_SPI=/dev/spidev0.0 STUW81300_LE_CHIP=/dev/gpiochip0 STUW81300_LE_LINE=25 \
+//!     cargo test --features hil-test --test hil -- --ignored --nocapture
+//! ```
+//!
+//! `STUW81300_REF_HZ` sets the reference frequency, 100 MHz by default. The reference is
+//! assumed to be single ended and the supply 5 V.
+#![cfg(feature = "hil-test")]
+
+use linux_embedded_hal::gpio_cdev::{Chip, LineRequestFlags};
+use linux_embedded_hal::spidev::{SpiModeFlags, SpidevOptions};
+use linux_embedded_hal::{CdevPin, Delay, Spidev};
+use stuw81300::{Hz, ReferenceType, STuW81300, SupplyVoltage};
+
+/// Number of frequencies the board has to lock at, spread over the RF1 range of the variant
+const FREQUENCIES: u32 = 5;
+/// How long each frequency may take to lock, in us
+const LOCK_TIMEOUT_US: u32 = 10_000;
+
+fn env(name: &str, default: Option<&str>) -> String {
+    std::env::var(name)
+        .ok()
+        .or(default.map(String::from))
+        .unwrap_or_else(|| panic!("{} isn't set", name))
+}
+
+#[test]
+#[ignore]
+fn smoke_test() {
+    let mut spi = Spidev::open(env("STUW81300_SPI", Some("/dev/spidev0.0"))).unwrap();
+    let options = SpidevOptions::new()
+        .bits_per_word(8)
+        .max_speed_hz(1_000_000)
+        .mode(SpiModeFlags::SPI_MODE_0)
+        .build();
+    spi.0.configure(&options).unwrap();
+    let line: u32 = env("STUW81300_LE_LINE", None).parse().unwrap();
+    let le = Chip::new(env("STUW81300_LE_CHIP", Some("/dev/gpiochip0")))
+        .and_then(|mut chip| chip.get_line(line))
+        .and_then(|line| line.request(LineRequestFlags::OUTPUT, 0, "stuw81300-le"))
+        .unwrap();
+    let le = CdevPin::new(le).unwrap();
+    let ref_freq: u64 = env("STUW81300_REF_HZ", Some("100000000")).parse().unwrap();
+
+    let (spi, le, variant) = STuW81300::probe(spi, le)
+        .map_err(|(_, _, e)| e)
+        .expect("no device found");
+    println!("found {:?}", variant);
+    let mut vco = STuW81300::new(
+        spi,
+        le,
+        SupplyVoltage::HighVoltage,
+        ref_freq as Hz,
+        ReferenceType::SingleEnded,
+    )
+    .with_delay(Delay);
+    vco.init().unwrap();
+    let report = vco.self_test().unwrap();
+    assert!(report.passed(), "self test failed: {:?}", report);
+
+    // The middle of each of FREQUENCIES equal slices of the range
+    let (min, max) = vco.output_frequency_range();
+    for i in 0..FREQUENCIES {
+        let f = min + (max - min) * (2 * i + 1) as Hz / (2 * FREQUENCIES) as Hz;
+        let lock_time_us = vco.measure_lock_time(f, 10, LOCK_TIMEOUT_US).unwrap();
+        println!("{} Hz: {:?}", f, vco.telemetry().unwrap());
+        assert!(lock_time_us.is_some(), "no lock at {} Hz", f);
+        println!("{} Hz locked in {} us", f, lock_time_us.unwrap());
+        assert!(!vco.is_ocp().unwrap(), "overcurrent at {} Hz", f);
+    }
+    println!("{:?}", vco.vco_band_report().unwrap());
+    println!("{:#?}", vco);
+}